@@ -0,0 +1,55 @@
+//! Extend a context with a custom namespace configuration.
+//!
+//! This runs a command in a new network namespace in which unprivileged
+//! processes may bind to any port, by writing a sysctl from within the
+//! namespace before the command runs.
+
+extern crate isolate;
+extern crate libc;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+
+use isolate::*;
+use isolate::namespace::*;
+use libc::{CLONE_NEWNET, c_int};
+
+/// Allow unprivileged processes to bind to ports from `port` upwards.
+#[derive(Clone, Debug)]
+struct UnprivilegedPorts {
+	port: u16,
+}
+
+impl Namespace for UnprivilegedPorts {
+	// The sysctl is per network namespace, so changing it in the network
+	// namespace of the parent would affect the entire system.
+	fn required_flags(&self) -> c_int {
+		CLONE_NEWNET
+	}
+
+	fn internal_config(&mut self) -> isolate::Result<()> {
+		let mut sysctl = OpenOptions::new()
+			.write(true)
+			.open("/proc/sys/net/ipv4/ip_unprivileged_port_start")?;
+		sysctl.write_all(format!("{}", self.port).as_bytes())?;
+
+		Ok(())
+	}
+}
+
+fn main() -> isolate::Result<()> {
+	let context = Context::new()
+		.with(User::new().map_root_user().map_root_group())
+		.with(Network::new())
+		.with(UnprivilegedPorts { port: 0 });
+
+	let child = context.exec_private(|| {
+		Command::new("cat")
+			.arg("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+			.status()
+			.unwrap();
+	})?;
+	child.wait()?;
+	Ok(())
+}
@@ -30,7 +30,8 @@ fn main() -> isolate::Result<()> {
 		.with(sys);
 
 	let child = context.exec_private(shell)?;
-	child.wait()
+	child.wait()?;
+	Ok(())
 }
 
 fn shell() {
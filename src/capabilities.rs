@@ -0,0 +1,350 @@
+//! Restriction of the capabilities of the child (see `capabilities(7)`).
+
+use std::fmt;
+use std::mem;
+
+use libc::{
+	EINVAL,
+	PR_CAPBSET_DROP,
+	PR_CAPBSET_READ,
+	PR_CAP_AMBIENT,
+	PR_CAP_AMBIENT_CLEAR_ALL,
+	PR_CAP_AMBIENT_RAISE,
+	PR_SET_KEEPCAPS,
+	SYS_capget,
+	SYS_capset,
+	c_int,
+	c_ulong,
+	prctl,
+	syscall,
+};
+
+use ::error::*;
+
+/// The version of the capability structures supporting 64 capabilities.
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// A capability of a process.
+///
+/// Capabilities are displayed by name, e.g. `CAP_NET_BIND_SERVICE`, with
+/// capabilities unknown to this crate displayed by number.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capability(pub u32);
+
+impl Capability {
+	/// Change the owner of files.
+	pub const CHOWN: Capability = Capability(0);
+	/// Bypass file permission checks.
+	pub const DAC_OVERRIDE: Capability = Capability(1);
+	/// Bypass file read and directory search permission checks.
+	pub const DAC_READ_SEARCH: Capability = Capability(2);
+	/// Bypass permission checks requiring ownership of a file.
+	pub const FOWNER: Capability = Capability(3);
+	/// Keep set-ID bits when a file is modified.
+	pub const FSETID: Capability = Capability(4);
+	/// Send signals to any process.
+	pub const KILL: Capability = Capability(5);
+	/// Change group IDs.
+	pub const SETGID: Capability = Capability(6);
+	/// Change user IDs.
+	pub const SETUID: Capability = Capability(7);
+	/// Change the capability bounding set and transfer capabilities.
+	pub const SETPCAP: Capability = Capability(8);
+	/// Set immutable and append-only file attributes.
+	pub const LINUX_IMMUTABLE: Capability = Capability(9);
+	/// Bind sockets to ports below 1024.
+	pub const NET_BIND_SERVICE: Capability = Capability(10);
+	/// Make socket broadcasts and listen to multicasts.
+	pub const NET_BROADCAST: Capability = Capability(11);
+	/// Configure networking.
+	pub const NET_ADMIN: Capability = Capability(12);
+	/// Use raw and packet sockets.
+	pub const NET_RAW: Capability = Capability(13);
+	/// Lock memory.
+	pub const IPC_LOCK: Capability = Capability(14);
+	/// Bypass permission checks on System V IPC objects.
+	pub const IPC_OWNER: Capability = Capability(15);
+	/// Load and unload kernel modules.
+	pub const SYS_MODULE: Capability = Capability(16);
+	/// Perform I/O port operations and access raw devices.
+	pub const SYS_RAWIO: Capability = Capability(17);
+	/// Change the root directory.
+	pub const SYS_CHROOT: Capability = Capability(18);
+	/// Trace any process.
+	pub const SYS_PTRACE: Capability = Capability(19);
+	/// Configure process accounting.
+	pub const SYS_PACCT: Capability = Capability(20);
+	/// Perform a range of system administration operations.
+	pub const SYS_ADMIN: Capability = Capability(21);
+	/// Reboot the system.
+	pub const SYS_BOOT: Capability = Capability(22);
+	/// Raise the priority of processes.
+	pub const SYS_NICE: Capability = Capability(23);
+	/// Override resource limits.
+	pub const SYS_RESOURCE: Capability = Capability(24);
+	/// Set the system clock.
+	pub const SYS_TIME: Capability = Capability(25);
+	/// Configure terminals.
+	pub const SYS_TTY_CONFIG: Capability = Capability(26);
+	/// Create special files.
+	pub const MKNOD: Capability = Capability(27);
+	/// Take leases on files.
+	pub const LEASE: Capability = Capability(28);
+	/// Write to the kernel audit log.
+	pub const AUDIT_WRITE: Capability = Capability(29);
+	/// Configure kernel auditing.
+	pub const AUDIT_CONTROL: Capability = Capability(30);
+	/// Set the capabilities of files.
+	pub const SETFCAP: Capability = Capability(31);
+	/// Override mandatory access control.
+	pub const MAC_OVERRIDE: Capability = Capability(32);
+	/// Configure mandatory access control.
+	pub const MAC_ADMIN: Capability = Capability(33);
+	/// Perform privileged `syslog(2)` operations.
+	pub const SYSLOG: Capability = Capability(34);
+	/// Trigger wake-ups of the system.
+	pub const WAKE_ALARM: Capability = Capability(35);
+	/// Prevent the system from suspending.
+	pub const BLOCK_SUSPEND: Capability = Capability(36);
+	/// Read the kernel audit log.
+	pub const AUDIT_READ: Capability = Capability(37);
+	/// Use performance monitoring.
+	pub const PERFMON: Capability = Capability(38);
+	/// Use privileged BPF operations.
+	pub const BPF: Capability = Capability(39);
+	/// Checkpoint and restore processes.
+	pub const CHECKPOINT_RESTORE: Capability = Capability(40);
+}
+
+const CAPABILITY_NAMES: &[&str] = &[
+	"CAP_CHOWN",
+	"CAP_DAC_OVERRIDE",
+	"CAP_DAC_READ_SEARCH",
+	"CAP_FOWNER",
+	"CAP_FSETID",
+	"CAP_KILL",
+	"CAP_SETGID",
+	"CAP_SETUID",
+	"CAP_SETPCAP",
+	"CAP_LINUX_IMMUTABLE",
+	"CAP_NET_BIND_SERVICE",
+	"CAP_NET_BROADCAST",
+	"CAP_NET_ADMIN",
+	"CAP_NET_RAW",
+	"CAP_IPC_LOCK",
+	"CAP_IPC_OWNER",
+	"CAP_SYS_MODULE",
+	"CAP_SYS_RAWIO",
+	"CAP_SYS_CHROOT",
+	"CAP_SYS_PTRACE",
+	"CAP_SYS_PACCT",
+	"CAP_SYS_ADMIN",
+	"CAP_SYS_BOOT",
+	"CAP_SYS_NICE",
+	"CAP_SYS_RESOURCE",
+	"CAP_SYS_TIME",
+	"CAP_SYS_TTY_CONFIG",
+	"CAP_MKNOD",
+	"CAP_LEASE",
+	"CAP_AUDIT_WRITE",
+	"CAP_AUDIT_CONTROL",
+	"CAP_SETFCAP",
+	"CAP_MAC_OVERRIDE",
+	"CAP_MAC_ADMIN",
+	"CAP_SYSLOG",
+	"CAP_WAKE_ALARM",
+	"CAP_BLOCK_SUSPEND",
+	"CAP_AUDIT_READ",
+	"CAP_PERFMON",
+	"CAP_BPF",
+	"CAP_CHECKPOINT_RESTORE",
+];
+
+impl fmt::Display for Capability {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match CAPABILITY_NAMES.get(self.0 as usize) {
+			Some(name) => write!(f, "{}", name),
+			None => write!(f, "CAP_{}", self.0),
+		}
+	}
+}
+
+/// Restrict the capabilities of the child.
+///
+/// Every capability that is not kept is dropped from the bounding,
+/// permitted, effective, and inheritable sets of the child, so that it can
+/// never be regained. The kept capabilities are raised in the permitted,
+/// effective, inheritable, and ambient sets so that they are retained when
+/// the child executes a program that is not set-ID and has no file
+/// capabilities. The kept capabilities must already be permitted.
+///
+/// The capabilities are restricted after all other configuration, including
+/// [`Context::switch_to`](struct.Context.html#method.switch_to), so they are
+/// kept by an unprivileged user.
+///
+/// ```rust
+/// Context::new()
+///     .with(User::new().map_root_user())
+///     .capabilities(Capabilities::keep(&[Capability::NET_BIND_SERVICE]))
+///     .exec_private(|| {
+///         Command::new("nc").args(&["-l", "80"]).status().unwrap();
+///     })?
+///     .wait()?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+	keep: Vec<Capability>,
+}
+
+impl Capabilities {
+	/// Drop every capability except those in `keep`.
+	pub fn keep(keep: &[Capability]) -> Capabilities {
+		Capabilities {
+			keep: keep.to_vec(),
+		}
+	}
+
+	/// Retain the permitted capabilities of the child when it switches to a
+	/// user other than root.
+	pub(crate) fn keep_across_switch(&self) -> Result<()> {
+		match unsafe { prctl(PR_SET_KEEPCAPS, 1, 0, 0, 0) } {
+			-1 => Err(errno!(SetCapabilities)),
+			_ => Ok(()),
+		}
+	}
+
+	/// Restrict the capabilities of the calling process.
+	pub(crate) fn apply(&self) -> Result<()> {
+		let mut sets = get_capabilities()?;
+
+		// Changing the bounding set requires CAP_SETPCAP to be effective,
+		// which it may not be after switching user.
+		sets[0].effective = sets[0].permitted;
+		sets[1].effective = sets[1].permitted;
+		set_capabilities(&sets)?;
+
+		let mut cap = 0;
+		while unsafe { prctl(PR_CAPBSET_READ, cap as c_ulong, 0, 0, 0) } != -1 {
+			let capability = Capability(cap);
+			let dropped = self.keep.contains(&capability)
+				|| unsafe { prctl(PR_CAPBSET_DROP, cap as c_ulong, 0, 0, 0) } != -1;
+			if !dropped {
+				return Err(errno!(DropCapability, capability));
+			}
+			cap += 1;
+		}
+		if ::errno::errno().0 != EINVAL {
+			return Err(errno!(SetCapabilities));
+		}
+
+		// Capabilities can only be raised in the ambient set once they are
+		// both permitted and inheritable.
+		let mut kept = [CapData::default(); 2];
+		for capability in &self.keep {
+			let (index, bit) = ((capability.0 / 32) as usize, 1 << (capability.0 % 32));
+			if index >= kept.len() || sets[index].permitted & bit == 0 {
+				return Err(ErrorKind::CapabilityNotPermitted(*capability).into());
+			}
+			kept[index].effective |= bit;
+			kept[index].permitted |= bit;
+			kept[index].inheritable |= bit;
+		}
+		set_capabilities(&kept)?;
+
+		if unsafe { prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_CLEAR_ALL as c_ulong, 0, 0, 0) } == -1 {
+			return Err(errno!(SetCapabilities));
+		}
+		for capability in &self.keep {
+			let raise = unsafe {
+				prctl(PR_CAP_AMBIENT, PR_CAP_AMBIENT_RAISE as c_ulong, capability.0 as c_ulong, 0, 0)
+			};
+			if raise == -1 {
+				return Err(errno!(AmbientCapability, *capability));
+			}
+		}
+
+		match unsafe { prctl(PR_SET_KEEPCAPS, 0, 0, 0, 0) } {
+			-1 => Err(errno!(SetCapabilities)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// The header of the capability sets passed to `capget(2)` and `capset(2)`.
+#[repr(C)]
+struct CapHeader {
+	version: u32,
+	pid: c_int,
+}
+
+/// Capability sets for 32 capabilities.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct CapData {
+	effective: u32,
+	permitted: u32,
+	inheritable: u32,
+}
+
+/// Get the capability sets of the calling thread.
+fn get_capabilities() -> Result<[CapData; 2]> {
+	let mut header = CapHeader {
+		version: LINUX_CAPABILITY_VERSION_3,
+		pid: 0,
+	};
+	let mut sets: [CapData; 2] = unsafe { mem::zeroed() };
+
+	match unsafe { syscall(SYS_capget, &mut header as *mut CapHeader, sets.as_mut_ptr()) } {
+		-1 => Err(errno!(SetCapabilities)),
+		_ => Ok(sets),
+	}
+}
+
+/// Set the capability sets of the calling thread.
+fn set_capabilities(sets: &[CapData; 2]) -> Result<()> {
+	let mut header = CapHeader {
+		version: LINUX_CAPABILITY_VERSION_3,
+		pid: 0,
+	};
+
+	match unsafe { syscall(SYS_capset, &mut header as *mut CapHeader, sets.as_ptr()) } {
+		-1 => Err(errno!(SetCapabilities)),
+		_ => Ok(()),
+	}
+}
+
+/// Test that a kept capability is the only one that remains usable.
+#[test]
+fn keep_net_bind_service() {
+	use std::fs::File;
+	use std::io::Read;
+	use std::net::TcpListener;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use ::namespace::{Network, User};
+	use ::Context;
+
+	static KEPT: AtomicUsize = AtomicUsize::new(0);
+
+	let child = Context::new()
+		.with(User::new().map_root_user())
+		.with(Network::new())
+		.capabilities(Capabilities::keep(&[Capability::NET_BIND_SERVICE]))
+		.exec_shared(|| {
+			let mut status = String::new();
+			File::open("/proc/self/status").unwrap().read_to_string(&mut status).unwrap();
+			let sets: Vec<&str> = status.lines()
+				.filter(|line| line.starts_with("Cap"))
+				.map(|line| line.split_whitespace().nth(1).unwrap())
+				.collect();
+
+			// CapInh, CapPrm, CapEff, CapBnd, and CapAmb.
+			let only_bind = sets.iter().all(|&set| set == "0000000000000400");
+			if only_bind && TcpListener::bind("0.0.0.0:80").is_ok() {
+				KEPT.store(1, Ordering::SeqCst);
+			}
+		})
+		.unwrap();
+	child.wait().unwrap();
+
+	assert_eq!(KEPT.load(Ordering::SeqCst), 1);
+}
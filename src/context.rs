@@ -1,39 +1,150 @@
-use std::ops::Deref;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::env::current_dir;
+use std::fs::{File, metadata, read_dir};
+use std::io::Read;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
 use std::ptr::{NonNull, self};
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use libc::{
+	CLONE_CHILD_CLEARTID,
+	CLONE_CHILD_SETTID,
+	CLONE_FILES,
+	CLONE_FS,
+	CLONE_NEWCGROUP,
+	CLONE_NEWIPC,
+	CLONE_NEWNET,
+	CLONE_NEWNS,
+	CLONE_NEWPID,
+	CLONE_NEWUSER,
+	CLONE_NEWUTS,
+	CLONE_PARENT,
+	CLONE_PARENT_SETTID,
+	CLONE_SETTLS,
+	CLONE_SIGHAND,
+	CLONE_THREAD,
+	CLONE_VFORK,
 	CLONE_VM,
+	ECHILD,
+	EINTR,
+	EINVAL,
+	ESRCH,
+	EXIT_FAILURE,
 	EXIT_SUCCESS,
 	MAP_ANONYMOUS,
 	MAP_PRIVATE,
 	MAP_SHARED,
 	MAP_STACK,
+	O_CLOEXEC,
+	POLLIN,
 	PROT_READ,
 	PROT_WRITE,
+	PR_SET_CHILD_SUBREAPER,
+	PR_SET_NO_NEW_PRIVS,
+	RLIMIT_STACK,
+	RLIM_INFINITY,
 	SIGCHLD,
-	SIGCONT,
-	SIGSTOP,
+	SIGKILL,
+	SIGTERM,
+	STDERR_FILENO,
+	WEXITSTATUS,
+	WIFSIGNALED,
+	WNOHANG,
+	WTERMSIG,
 	_SC_PAGE_SIZE,
+	_exit,
 	c_int,
+	c_ulong,
 	c_void,
 	clone,
-	getpid,
+	close,
+	dup2,
+	fork,
+	getrlimit,
+	gid_t,
 	kill,
 	mmap,
+	munmap,
 	off_t,
 	pid_t,
+	pipe2,
+	poll,
+	pollfd,
+	read,
+	rlimit,
+	rusage,
 	size_t,
 	sysconf,
+	timeval,
+	uid_t,
+	unshare,
+	wait4,
 	waitpid,
+	write,
 };
 
 use error::*;
-use ::namespace::Namespace;
+use ::capabilities::Capabilities;
+use ::namespace::{
+	CgroupNamespace,
+	CloneFlags,
+	EmptyMount,
+	Enter,
+	Ipc,
+	Mount,
+	Namespace,
+	NamespaceGuard,
+	Network,
+	NsKind,
+	Pid,
+	Propagation,
+	RootFs,
+	User,
+	Uts,
+};
+use ::spec::ContextSpec;
+use ::process::{
+	CpuAffinity,
+	EffectiveRoot,
+	Environment,
+	Groups,
+	LoginUid,
+	MaxPids,
+	OomScoreAdj,
+	Policy,
+	Prctl,
+	PreserveFd,
+	ResetSignals,
+	SchedPolicy,
+	SwitchTo,
+};
 
 /// A process execution context constructed of namespaces.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Context {
-	namespaces: Vec<Box<Namespace>>,
+	namespaces: Vec<Box<dyn Namespace>>,
+	stack_size: Option<size_t>,
+	stack: Option<BorrowedStack>,
+	spawn_mode: SpawnMode,
+	extra_clone_flags: c_int,
+	switch_to: Option<SwitchTo>,
+	capabilities: Option<Capabilities>,
+	root_propagation: Vec<Mount>,
+	seal_root: Option<Mount>,
+	capture_stderr: bool,
+	root: Option<PathBuf>,
+	confine_mounts: bool,
+	cleanup_handler: Option<CleanupHandler>,
+	pre_exec: Vec<PreExec>,
+	env_keep: Vec<String>,
+	unisolated: bool,
+	prepare_as_root: bool,
 }
 
 impl Context {
@@ -43,8 +154,109 @@ impl Context {
 	/// in a new process with the same privileges as the parent.
 	pub fn new() -> Context {
 		Context {
-			namespaces: Vec::new()
+			namespaces: Vec::new(),
+			stack_size: None,
+			stack: None,
+			spawn_mode: SpawnMode::Clone,
+			extra_clone_flags: 0,
+			switch_to: None,
+			capabilities: None,
+			root_propagation: Vec::new(),
+			seal_root: None,
+			capture_stderr: false,
+			root: None,
+			confine_mounts: false,
+			cleanup_handler: None,
+			pre_exec: Vec::new(),
+			env_keep: Vec::new(),
+			unisolated: false,
+			prepare_as_root: false,
+		}
+	}
+
+	/// Create a context with a secure default configuration.
+	///
+	/// The context creates new user, PID, network, IPC, UTS, and mount
+	/// namespaces, in which:
+	///
+	/// - the calling user and group are mapped to root, with no other users;
+	/// - `/proc` is mounted anew so that it only shows the processes of the
+	///   child, and its sensitive entries (such as `/proc/kcore` and
+	///   `/proc/keys`) are masked, as is `/sys/firmware`;
+	/// - the only network interface is the loopback interface, which is up;
+	/// - the child can't gain privileges through `execve(2)` (see
+	///   [`no_new_privs`](#method.no_new_privs)); and
+	/// - the entire root file-system is read-only (see
+	///   [`seal_root`](#method.seal_root)).
+	///
+	/// The child still sees every file of the parent that the calling user
+	/// can read. The returned context can be customised further, such as
+	/// with writable `tmpfs` mounts or a [`Chroot`](namespace/struct.Chroot.html).
+	/// Use [`exec_private`](#method.exec_private) to give the child its own
+	/// address space.
+	///
+	/// ```rust
+	/// Context::sandbox()?
+	///     .with(Mount::tmpfs("/tmp", 64 << 20)?)
+	///     .exec_private(|| {
+	///         Command::new("sh").status().unwrap();
+	///     })?
+	///     .wait()?;
+	/// ```
+	pub fn sandbox() -> Result<Context> {
+		let mut context = Context::new()
+			.with(User::new().map_root_user().map_root_group())
+			.with(Pid::new())
+			.with(Network::new().loopback_up())
+			.with(Ipc::new())
+			.with(Uts::new())
+			.with(EmptyMount::new())
+			.with(Mount::proc("/proc")?);
+
+		for path in SANDBOX_MASKED_DIRS {
+			if Path::new(path).is_dir() {
+				context = context.with(Mount::mask(path)?);
+			}
+		}
+		for path in SANDBOX_MASKED_FILES {
+			if Path::new(path).exists() {
+				context = context.with(Mount::bind("/dev/null", path)?.read_only());
+			}
+		}
+
+		context.no_new_privs().seal_root()
+	}
+
+	/// Create a context from a declarative spec.
+	///
+	/// The namespaces are added first in the order they are named, followed
+	/// by the mounts and then the environment. Mounts must be named after a
+	/// mount namespace to be isolated from the parent. Returns an error if a
+	/// namespace or mount in the spec is invalid.
+	///
+	/// ```rust
+	/// let spec = ContextSpec {
+	///     namespaces: vec!["pid".to_owned()],
+	///     hostname: Some("sandbox".to_owned()),
+	///     ..ContextSpec::default()
+	/// };
+	///
+	/// Context::from_spec(&spec)?.exec_private(|| {})?.wait()?;
+	/// ```
+	pub fn from_spec(spec: &ContextSpec) -> Result<Context> {
+		let mut context = Context::new();
+		context.namespaces = spec.namespaces()?;
+
+		let (keep, clear) = spec.env_kept()?;
+		context = context.env_keep(&keep);
+		if clear {
+			context = context.env_clear();
 		}
+		for (name, value) in &spec.env {
+			context = context.env(name, value)?;
+		}
+
+		Ok(context)
 	}
 
 	/// Add a namespace configuration to the context.
@@ -56,212 +268,2828 @@ impl Context {
 		self
 	}
 
-	/// Create a process in a new private address space.
+	/// Iterate over the namespaces added to the context in order.
 	///
-	/// The address space is copied and no references are shared.
-	pub fn exec_private(&self, f: fn()) -> Result<Child>
-	{
-		self.exec(f, Share::Private)
+	/// Each namespace can be inspected through its
+	/// [`clone_flag`](namespace/trait.Namespace.html#method.clone_flag) and
+	/// `Debug` representation, or downcast with
+	/// [`as_any`](namespace/trait.NamespaceClone.html#tymethod.as_any).
+	pub fn namespaces<'a>(&'a self) -> impl Iterator<Item = &'a dyn Namespace> + 'a {
+		self.namespaces.iter().map(|ns| &**ns)
 	}
 
-	/// Create and enter the context, running the given function.
+	/// Iterate over the mounts added directly to the context in order.
+	pub fn mounts<'a>(&'a self) -> impl Iterator<Item = &'a Mount> + 'a {
+		self.namespaces().filter_map(|ns| ns.as_any().downcast_ref::<Mount>())
+	}
+
+	/// Remove all namespaces from the context.
 	///
-	/// The address space is shared with the child and the calling process
-	/// allowing shared access to globals, etc.
-	pub fn exec_shared(&self, f: fn()) -> Result<Child>
-	{
-		self.exec(f, Share::Shared)
+	/// Other settings of the context, such as the stack size, are kept.
+	pub fn clear(mut self) -> Context {
+		self.namespaces.clear();
+		self
 	}
 
-	/// Execute a child with a given function.
-	fn exec(&self, close: fn(), shared: Share) -> Result<Child> {
-		// Send the closure to a new process.
-		let child = unsafe {
-			let pair = Box::new((self.clone(), close));
-			Child::from_tid(clone(
-				exec_closure,
-				create_stack(shared)?.as_ptr(),
-				self.clone_flag() | shared.addrspace() | SIGCHLD,
-				Box::into_raw(pair) as *mut c_void,
-			))
-		}?;
-
-		self.configure(&child)?;
-		child.cont()?;
+	/// Set the supplementary groups of the child.
+	///
+	/// This is applied from within the child after the namespaces added
+	/// before it have been configured, so any group mapping for a user
+	/// namespace will have already been written. The user namespace must be
+	/// configured with [`User::allow_setgroups`] for this to succeed.
+	///
+	/// [`User::allow_setgroups`]: namespace/struct.User.html#method.allow_setgroups
+	pub fn groups(self, groups: &[gid_t]) -> Context {
+		self.with(Groups::new(groups))
+	}
 
-		Ok(child)
+	/// Adjust the likelihood of the child being killed when out of memory.
+	///
+	/// The adjustment must be between -1000 and 1000 and is written to
+	/// `/proc/<pid>/oom_score_adj` once the child has been created. Lowering
+	/// the adjustment requires `CAP_SYS_RESOURCE`.
+	pub fn oom_score_adj(self, adj: i32) -> Result<Context> {
+		Ok(self.with(OomScoreAdj::new(adj)?))
 	}
 
-	/// Configure the context of the child externally.
-	fn configure(&self, child: &Child) -> Result<()> {
-		for namespace in &self.namespaces {
-			namespace.external_config(child)?;
-		}
+	/// Run the child without network access.
+	///
+	/// This places the child in a new, empty [`Network`] namespace. The
+	/// namespace has no interfaces other than a loopback interface, which is
+	/// left down, so the child has no connectivity at all, not even to
+	/// `localhost`. To allow connections to `localhost` only, add a network
+	/// namespace with [`Network::loopback_up`] instead.
+	///
+	/// ```rust
+	/// Context::new().no_network();
+	/// Context::new().with(Network::new().loopback_up());
+	/// ```
+	///
+	/// [`Network`]: namespace/struct.Network.html
+	/// [`Network::loopback_up`]: namespace/struct.Network.html#method.loopback_up
+	pub fn no_network(self) -> Context {
+		self.with(Network::new())
+	}
 
-		Ok(())
+	/// Hide the control group hierarchy of the host from the child.
+	///
+	/// This places the child in a new
+	/// [`CgroupNamespace`](namespace/struct.CgroupNamespace.html) without
+	/// applying any limits, so that `/proc/self/cgroup` in the child shows
+	/// `/` rather than the path of its control group on the host. The view
+	/// is relative to the control group the child is in when the namespace
+	/// is created; the child is not moved to a new control group.
+	///
+	/// ```rust
+	/// Context::new().private_cgroup_view();
+	/// ```
+	pub fn private_cgroup_view(self) -> Context {
+		self.with(CgroupNamespace::new())
 	}
-}
 
-impl Namespace for Context {
-	fn clone_flag(&self) -> c_int {
-		self.namespaces.iter().fold(0, |f, n| f | n.clone_flag())
+	/// Limit the number of processes that the child and its descendants may
+	/// create.
+	///
+	/// This sets the `RLIMIT_NPROC` resource limit of the child, which counts
+	/// every process owned by the real user of the child, not only those in
+	/// its PID namespace. With a [`User`](namespace/struct.User.html)
+	/// namespace, the count is of the processes of the mapped user within that
+	/// namespace, so the limit bounds the child without regard for other
+	/// processes of the calling user. Without a user namespace, the limit
+	/// applies to the real user of the caller, so the child can't create
+	/// processes if the user already owns `pids` processes. The limit is not
+	/// enforced for processes with `CAP_SYS_ADMIN` or `CAP_SYS_RESOURCE` in
+	/// the initial user namespace.
+	///
+	/// For a limit that is independent of the user, place the child in a
+	/// control group with
+	/// [`CgroupLimits::pids_max`](namespace/struct.CgroupLimits.html#method.pids_max)
+	/// instead.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(User::new().map_root_user())
+	///     .with(Pid::new())
+	///     .max_pids(64);
+	/// ```
+	pub fn max_pids(self, pids: u64) -> Context {
+		self.with(MaxPids::new(pids))
 	}
 
-	fn prepare(&self) -> Result<()> {
-		for ns in &self.namespaces {
-			ns.prepare()?;
-		}
+	/// Set the audit login user ID of the child.
+	///
+	/// The ID is written to `/proc/<pid>/loginuid` from the parent once the
+	/// child has been created, so that the actions of the child are recorded
+	/// by the audit system as those of `uid`. This requires
+	/// `CAP_AUDIT_CONTROL` in the initial user namespace. If the login user
+	/// ID of the child has already been set and login user IDs are
+	/// immutable, or the caller lacks the capability, creating the child
+	/// fails with
+	/// [`LoginUidDenied`](enum.ErrorKind.html#variant.LoginUidDenied).
+	/// Requires a kernel with audit support.
+	///
+	/// ```rust
+	/// Context::new().loginuid(1000);
+	/// ```
+	pub fn loginuid(self, uid: uid_t) -> Context {
+		self.with(LoginUid::new(uid))
+	}
 
-		Ok(())
+	/// Prevent the child from gaining privileges through `execve(2)`.
+	///
+	/// Sets `no_new_privs` from within the child, so that set-user-ID and
+	/// set-group-ID programs and file capabilities no longer grant privileges
+	/// to it or its descendants. This can't be undone.
+	pub fn no_new_privs(self) -> Context {
+		self.with(Prctl::new(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))
 	}
 
-	fn internal_config(&mut self) -> Result<()> {
-		for ns in &mut self.namespaces {
-			ns.internal_config()?;
-		}
+	/// Make the child reap orphaned descendants.
+	///
+	/// The child is made a subreaper with `PR_SET_CHILD_SUBREAPER` before the
+	/// closure runs, so descendants that are orphaned are reparented to the
+	/// child rather than to the init process of the PID namespace. Unlike PID
+	/// 1 of a [`Pid`](namespace/struct.Pid.html) namespace, the child keeps
+	/// its PID, receives signals as normal, and its descendants are not
+	/// killed when it exits; they are reparented to the next subreaper or
+	/// init process instead. The child must wait on reparented processes to
+	/// reap them.
+	///
+	/// ```rust
+	/// Context::new().subreaper().exec_private(|| {
+	///     // Spawn a daemon and reap it once it is orphaned.
+	/// })?;
+	/// ```
+	pub fn subreaper(self) -> Context {
+		self.with(Prctl::new(PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0))
+	}
 
-		Ok(())
+	/// Apply a raw `prctl(2)` operation within the child.
+	///
+	/// Each operation is applied from within the child in the order it is
+	/// added along with the rest of the context, so that options the crate
+	/// doesn't wrap can be set, such as `PR_SET_TIMERSLACK` or
+	/// `PR_SET_SECUREBITS`. Unused arguments should be zero. If the operation
+	/// fails, the child isn't run and its `errno` is reported. Options that
+	/// change the identity of the child are applied before
+	/// [`switch_to`](#method.switch_to) changes it.
+	///
+	/// ```rust
+	/// // Let the kernel coalesce the timers of the child by up to 1ms.
+	/// Context::new().prctl(PR_SET_TIMERSLACK, 1_000_000, 0, 0, 0);
+	/// ```
+	pub fn prctl(self, option: c_int, arg2: c_ulong, arg3: c_ulong, arg4: c_ulong, arg5: c_ulong) -> Context {
+		self.with(Prctl::new(option, arg2, arg3, arg4, arg5))
 	}
 
-	fn external_config(&self, child: &Child) -> Result<()> {
-		for ns in &self.namespaces {
-			ns.external_config(child)?;
+	/// Give the child a clean signal environment.
+	///
+	/// The child inherits the signal mask of the parent, and any signals
+	/// that the parent ignores, which can leave a program in the child with
+	/// signals unexpectedly blocked or ignored. This resets the disposition
+	/// of every signal to its default and unblocks all signals from within
+	/// the child before the closure runs.
+	///
+	/// ```rust
+	/// Context::new().reset_signals().exec_private(|| {
+	///     Command::new("sh").status().unwrap();
+	/// })?;
+	/// ```
+	pub fn reset_signals(self) -> Context {
+		self.with(ResetSignals)
+	}
+
+	/// Pass the file descriptor `src` to the child as the descriptor `dest`.
+	///
+	/// The descriptor is duplicated within the child and will not be closed
+	/// when the child calls `exec`, making this suitable for passing listening
+	/// sockets to servers. `src` must be a valid descriptor in the parent when
+	/// the child is created. If `dest` is already open in the child, it is
+	/// replaced.
+	pub fn preserve_fd(self, src: RawFd, dest: RawFd) -> Context {
+		self.with(PreserveFd::new(src, dest))
+	}
+
+	/// Set the size of the stack allocated for the child.
+	///
+	/// By default, the size of the stack is taken from the soft
+	/// `RLIMIT_STACK` limit of the calling process. The size is rounded up to
+	/// a whole number of pages and must be at least 16 pages, returning
+	/// [`StackTooSmall`](enum.ErrorKind.html#variant.StackTooSmall)
+	/// otherwise.
+	///
+	/// ```rust
+	/// Context::new().stack_size(1024 * 1024)?;
+	/// ```
+	pub fn stack_size(mut self, size: size_t) -> Result<Context> {
+		let min = min_stack_size();
+		if size < min {
+			return Err(ErrorKind::StackTooSmall(size, min).into());
 		}
 
-		Ok(())
+		self.stack_size = Some(size);
+		Ok(self)
 	}
-}
 
-impl Drop for Context {
-	fn drop(&mut self) {
-		// Forcibly drop namespaces in reverse order.
-		while self.namespaces.pop().is_some() {};
+	/// Choose how the child process is created.
+	///
+	/// By default, the child is created with `clone(2)` on a stack allocated
+	/// for it, creating its namespaces at the same time. With
+	/// [`SpawnMode::ForkUnshare`](enum.SpawnMode.html), the child is created
+	/// with `fork(2)` and then creates its namespaces with `unshare(2)`,
+	/// avoiding the separate stack. See [`SpawnMode`](enum.SpawnMode.html)
+	/// for the differences between them.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(User::new().map_root_user())
+	///     .with(Uts::new().hostname("forked"))
+	///     .spawn_mode(SpawnMode::ForkUnshare);
+	/// ```
+	pub fn spawn_mode(mut self, mode: SpawnMode) -> Context {
+		self.spawn_mode = mode;
+		self
 	}
-}
 
-#[derive(Copy, Clone, Debug)]
-enum Share {
-	Shared,
-	Private
-}
+	/// Pass additional flags to `clone(2)` when creating the child.
+	///
+	/// The flags are added to those of the namespaces in the context. This
+	/// allows options that aren't otherwise supported to be used, e.g.
+	/// `CLONE_IO`. Flags that would share state with the parent that the
+	/// crate relies on being separate, or that require arguments that aren't
+	/// passed to `clone(2)`, are rejected when the child is created (see
+	/// [`validate`](#method.validate)). This includes `CLONE_NEWTIME`, which
+	/// overlaps the termination signal and can only be used with
+	/// `unshare(2)`.
+	///
+	/// ```rust
+	/// Context::new().extra_clone_flags(CloneFlags(CLONE_IO));
+	/// ```
+	pub fn extra_clone_flags(mut self, flags: CloneFlags) -> Context {
+		self.extra_clone_flags |= flags.0;
+		self
+	}
 
-impl Share {
-	fn map(&self) -> c_int {
-		match *self {
-			Share::Shared => MAP_SHARED,
-			Share::Private => MAP_PRIVATE,
-		}
+	/// Use a buffer provided by the caller as the stack of the child.
+	///
+	/// By default, a new stack is mapped for each child and unmapped once the
+	/// child has been reaped. Using a caller-provided buffer avoids this for
+	/// callers that create many children, for example by taking stacks from
+	/// a pre-allocated pool. The buffer is never unmapped or freed by the
+	/// crate. This takes precedence over
+	/// [`stack_size`](#method.stack_size). The buffer must be at least as
+	/// large as the minimum size accepted by `stack_size`, or executing the
+	/// context fails with
+	/// [`StackTooSmall`](enum.ErrorKind.html#variant.StackTooSmall).
+	///
+	/// # Safety
+	///
+	/// The buffer must remain valid and must not be otherwise used until
+	/// every child executed with the context has been reaped.
+	pub unsafe fn with_stack(mut self, stack: &mut [u8]) -> Context {
+		self.stack = Some(BorrowedStack {
+			ptr: stack.as_mut_ptr(),
+			len: stack.len(),
+		});
+		self
 	}
 
-	fn addrspace(&self) -> c_int {
-		match *self {
-			Share::Private => CLONE_VM,
-			Share::Shared => 0,
+	/// Restrict the child to running on the given CPUs.
+	///
+	/// The affinity is set from the parent once the child has been created
+	/// and is inherited by any processes the child creates. Each CPU must be
+	/// present in the system.
+	pub fn cpu_affinity(self, cpus: &[usize]) -> Result<Context> {
+		Ok(self.with(CpuAffinity::new(cpus)?))
+	}
+
+	/// Set an environment variable in the child.
+	///
+	/// Environment changes are applied from within the child in the order
+	/// they are added to the context. When the address space is shared with
+	/// the child, the environment of the parent is modified as well.
+	pub fn env(self, name: &str, value: &str) -> Result<Context> {
+		Ok(self.with(Environment::set(name, value)?))
+	}
+
+	/// Remove all environment variables inherited by the child.
+	///
+	/// Only variables set after this is added to the context, and those kept
+	/// with [`env_keep`](#method.env_keep), will be seen by the child.
+	pub fn env_clear(self) -> Context {
+		match self.env_keep.len() {
+			0 => self.with(Environment::Clear),
+			_ => {
+				let keep = self.env_keep.clone();
+				self.with(Environment::ClearExcept(keep))
+			}
 		}
 	}
-}
 
-struct Stack(NonNull<c_void>);
+	/// Keep the named environment variables when the environment is cleared.
+	///
+	/// Each time the environment is cleared (see
+	/// [`env_clear`](#method.env_clear)), the named variables are kept with
+	/// the values inherited from the parent. Variables set with
+	/// [`env`](#method.env) after clearing the environment still replace
+	/// them. This applies whether it is added before or after the environment
+	/// is cleared.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .env_clear()
+	///     .env_keep(&["PATH", "TERM", "HOME"])
+	///     .env("HOME", "/home/sandbox")?;
+	/// ```
+	pub fn env_keep(mut self, names: &[&str]) -> Context {
+		let names: Vec<String> = names.iter().map(|&name| name.to_owned()).collect();
 
-impl Stack {
-	fn from_ptr(ptr: *mut c_void, size: size_t) -> Result<Stack> {
-		match ptr as isize {
-			-1 | 0 => Err(errno!(StackAllocation)),
-			ptr => unsafe {
-				Ok(Stack(NonNull::new_unchecked(
-					(ptr + size as isize) as *mut c_void
-				)))
-			},
+		for ns in &mut self.namespaces {
+			if let Some(env) = ns.as_any_mut().downcast_mut::<Environment>() {
+				match *env {
+					Environment::Clear => *env = Environment::ClearExcept(names.clone()),
+					Environment::ClearExcept(ref mut keep) => keep.extend(names.iter().cloned()),
+					Environment::Set(..) => {},
+				}
+			}
 		}
+
+		self.env_keep.extend(names);
+		self
 	}
-}
 
-impl Deref for Stack {
-	type Target = NonNull<c_void>;
+	/// Restrict the capabilities of the child.
+	///
+	/// The capabilities are restricted from within the child after all other
+	/// configuration, including [`switch_to`](#method.switch_to), so that the
+	/// kept capabilities are retained by an unprivileged user. See
+	/// [`Capabilities`](struct.Capabilities.html).
+	pub fn capabilities(mut self, capabilities: Capabilities) -> Context {
+		self.capabilities = Some(capabilities);
+		self
+	}
 
-	fn deref(&self) -> &NonNull<c_void> {
-		&self.0
+	/// Run the child as the user `uid` and group `gid`.
+	///
+	/// The identity is switched from within the child after all other
+	/// configuration, immediately before the closure runs. This allows a
+	/// context to map root in a user namespace, perform privileged setup such
+	/// as mounts, and then drop to an unprivileged user. Both ids must be
+	/// mapped in the user namespace of the child.
+	///
+	/// The supplementary groups are cleared before the group is changed,
+	/// unless they are set with [`groups`](#method.groups). If `setgroups(2)`
+	/// is denied in the user namespace of the child (the default, see
+	/// [`User::allow_setgroups`]), they are left as they are, as neither the
+	/// child nor anything it runs can change them.
+	///
+	/// [`User::allow_setgroups`]: namespace/struct.User.html#method.allow_setgroups
+	pub fn switch_to(mut self, uid: uid_t, gid: gid_t) -> Context {
+		self.switch_to = Some(SwitchTo::new(uid, gid));
+		self
 	}
-}
 
-const STACK_PAGES: size_t = 2 * 1024;
-const NO_FILE: c_int = -1;
-const NO_OFFSET: off_t = 0;
+	/// Set the root directory of the jail.
+	///
+	/// Targets of mounts created with
+	/// [`Mount::relative`](namespace/struct.Mount.html#method.relative) are
+	/// joined onto this directory when the child is created. This does not
+	/// change the root directory of the child; add a
+	/// [`Chroot`](namespace/struct.Chroot.html) after the mounts to do so.
+	pub fn root<P: AsRef<Path>>(mut self, root: P) -> Context {
+		self.root = Some(root.as_ref().to_owned());
+		self
+	}
 
-/// Create a new stack in which to execute a child function.
-fn create_stack(share: Share) -> Result<Stack> {
-	let prot = PROT_WRITE | PROT_READ;
-	let flags =
-		share.map() |
-		MAP_ANONYMOUS |
-		MAP_STACK;
+	/// Reject mounts with targets outside the root of the jail.
+	///
+	/// When the context has a [`root`](#method.root), the target of each
+	/// [`Mount`](namespace/struct.Mount.html) is resolved when the context is
+	/// validated, following symbolic links and `..` components, and an
+	/// `ErrorKind::MountEscapesRoot` error is returned if it is outside the
+	/// root. A target that doesn't exist yet is resolved from its closest
+	/// existing ancestor. Targets are resolved in the mount namespace of the
+	/// caller, so links created by earlier mounts within the child aren't
+	/// followed. This has no effect on a context without a root.
+	///
+	/// ```rust
+	/// // Fails to validate, as the target is outside the jail.
+	/// Context::new()
+	///     .root("/tmp/jail")
+	///     .confine_mounts()
+	///     .with(Mount::relative("/lib", "../lib")?);
+	/// ```
+	pub fn confine_mounts(mut self) -> Context {
+		self.confine_mounts = true;
+		self
+	}
 
-	unsafe {
-		let size = STACK_PAGES * sysconf(_SC_PAGE_SIZE) as size_t;
-		Stack::from_ptr(
-			mmap(ptr::null_mut(), size, prot, flags, NO_FILE, NO_OFFSET),
-			size
-		)
+	/// Acknowledge that the child is not isolated from the parent.
+	///
+	/// A child that shares the address space of the parent (see
+	/// [`exec_shared`](#method.exec_shared)) and is in none of its own
+	/// namespaces is no more isolated than a thread, so a warning is logged
+	/// when one is created. This silences that warning for contexts that are
+	/// intentionally unisolated.
+	///
+	/// ```rust
+	/// Context::new().unisolated().exec_shared(|| {})?.wait()?;
+	/// ```
+	pub fn unisolated(mut self) -> Context {
+		self.unisolated = true;
+		self
 	}
-}
 
-/// Execute a function from a closure.
-extern "C"
-fn exec_closure(closure: *mut c_void) -> c_int {
-	// Stop and wait for parent to finish config.
-	unsafe {
-		if kill(getpid(), SIGSTOP) != 0 {
-			panic!("Could not stop child before running")
-		}
+	/// Configure the context from the parent with an effective user ID of
+	/// root.
+	///
+	/// The effective user ID of the parent is set to root while the context
+	/// is prepared before the child is created, while it is configured
+	/// externally, and while it is cleaned up, and is restored afterwards
+	/// even if any of these fail. The child is created with the original
+	/// effective user ID. This requires `CAP_SETUID`, or a saved user ID of
+	/// root as in a setuid program.
+	///
+	/// This allows a setuid helper to create control groups or network
+	/// devices for an unprivileged child. Such a helper should drop its
+	/// effective user ID before creating the child, and the child should
+	/// drop its saved user ID (for example with
+	/// [`switch_to`](#method.switch_to)) so that it cannot regain root.
+	///
+	/// ```rust
+	/// unsafe { libc::seteuid(libc::getuid()) };
+	/// Context::new()
+	///     .prepare_as_root()
+	///     .with(CgroupLimits::new("/sys/fs/cgroup/jail").pids_max(64))
+	///     .switch_to(uid, gid)
+	///     .exec_private(|| {})?
+	///     .wait()?;
+	/// ```
+	pub fn prepare_as_root(mut self) -> Context {
+		self.prepare_as_root = true;
+		self
 	}
 
-	let mut pair: Box<(Context, fn())> = unsafe {
-		Box::from_raw(closure as *mut (Context, fn()))
-	};
+	/// Handle errors that occur while cleaning up after a dropped child.
+	///
+	/// By default, errors from cleaning up the context of a child when it is
+	/// dropped are logged at the `warn` level (with the `log` feature) and
+	/// otherwise ignored. Use [`Child::cleanup`](struct.Child.html#method.cleanup)
+	/// to handle them directly instead.
+	pub fn cleanup_handler<F>(mut self, handler: F) -> Context
+	where
+		F: 'static + Fn(Error)
+	{
+		self.cleanup_handler = Some(CleanupHandler(Rc::new(handler)));
+		self
+	}
+
+	/// Run a closure within the child before the function it executes.
+	///
+	/// Hooks run in the order they were added, after the child has been
+	/// configured from within and has switched user (see
+	/// [`switch_to`](#method.switch_to)). This allows anything that is not
+	/// otherwise supported to be applied to the child. If a hook returns an
+	/// error, the child exits without running any further hooks or the
+	/// function and the error is returned when executing the child.
+	///
+	/// ```rust
+	/// Context::new().pre_exec(|| {
+	///     match unsafe { prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } {
+	///         0 => Ok(()),
+	///         _ => Err("could not set no_new_privs".into()),
+	///     }
+	/// });
+	/// ```
+	pub fn pre_exec<F>(mut self, hook: F) -> Context
+	where
+		F: 'static + FnMut() -> Result<()>
+	{
+		self.pre_exec.push(PreExec(Rc::new(RefCell::new(hook))));
+		self
+	}
 
-	let (ref mut context, ref close) = pair.as_mut();
+	/// Capture the standard error of the child.
+	///
+	/// The standard error of the child is replaced with the write end of a
+	/// pipe before it is configured and the read end is made available from
+	/// [`Child::stderr`](struct.Child.html#method.stderr). The child will block
+	/// once the pipe is full so the parent must keep reading from it.
+	pub fn capture_stderr(mut self) -> Context {
+		self.capture_stderr = true;
+		self
+	}
 
-	context.internal_config().expect("Unable to internally configure child");
+	/// Set the propagation of every mount in the new mount namespace before
+	/// anything else is configured.
+	///
+	/// The propagation of the whole mount tree is changed recursively from
+	/// `/` as the very first step within the child, ahead of any
+	/// [`Mount`](namespace/struct.Mount.html) or other configuration. Making
+	/// the tree a slave or private ensures that mounts made by the child,
+	/// such as bind mounts into a new root, never propagate back to the mount
+	/// namespace of the caller. The peer groups of the given propagation are
+	/// ignored. This requires a new mount namespace.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(EmptyMount::new())
+	///     .root_propagation(Propagation::Private)?
+	///     .with(Mount::bind("/srv/data", "/tmp/jail/data")?);
+	/// ```
+	pub fn root_propagation(mut self, propagation: Propagation) -> Result<Context> {
+		self.root_propagation = match propagation {
+			Propagation::Private => vec![Mount::recursive_private("/")?],
+			Propagation::Shared(_) => vec![Mount::recursive_shared("/")?],
+			Propagation::Slave(_) => vec![Mount::recursive_slave("/")?],
+			Propagation::SharedAndSlave(..) => vec![
+				Mount::recursive_slave("/")?,
+				Mount::recursive_shared("/")?,
+			],
+			Propagation::Unbindable => vec![Mount::recursive_unbindable("/")?],
+		};
+		Ok(self)
+	}
 
-	close();
-	return EXIT_SUCCESS;
-}
+	/// Make the entire root file-system read-only once the child has been
+	/// configured.
+	///
+	/// After every other configuration has been applied from within the
+	/// child, `/` is remounted read-only. Mounts made on top of the root, such
+	/// as writable `tmpfs` directories, are separate mounts and remain
+	/// writable. This requires a new mount namespace.
+	///
+	/// The root is remounted as a bind mount, so if the child has changed its
+	/// root with [`Chroot`](namespace/struct.Chroot.html) or `pivot_root(2)`,
+	/// the new root must itself be a mount point, e.g. by binding it onto
+	/// itself. The existing flags of the root are kept (see
+	/// [`Mount::preserve_flags`](namespace/struct.Mount.html#method.preserve_flags)),
+	/// as they are locked in a new user namespace, so `/proc` must be
+	/// mounted within the root.
+	pub fn seal_root(mut self) -> Result<Context> {
+		self.seal_root = Some(Mount::remount("/")?.as_bind().read_only().preserve_flags());
+		Ok(self)
+	}
 
-/// The child thread that has been started in the context.
-pub struct Child(pid_t);
+	/// Set the scheduling policy and static priority of the child.
+	///
+	/// The policy is set from the parent once the child has been created and
+	/// is inherited by any processes the child creates. The real-time
+	/// policies require `CAP_SYS_NICE` or a sufficient `RLIMIT_RTPRIO`.
+	///
+	/// ```rust
+	/// Context::new().sched_policy(Policy::Idle, 0)?;
+	/// ```
+	pub fn sched_policy(self, policy: Policy, priority: i32) -> Result<Context> {
+		Ok(self.with(SchedPolicy::new(policy, priority)?))
+	}
 
-impl Child {
-	fn from_tid(tid: c_int) -> Result<Child> {
-		match tid {
-			-1 => Err(errno!(Clone)),
-			tid => Ok(Child(tid)),
+	/// Check that the configuration of the context is consistent.
+	///
+	/// This checks that:
+	///
+	/// * every namespace that a configuration depends on (see
+	///   [`Namespace::required_flags`]) is created or entered by the context;
+	/// * configuration that depends on an entered namespace (see
+	///   [`Enter`]) is added after the namespace is entered;
+	/// * a [`User`] namespace is added before any other namespace, as the
+	///   configuration of other namespaces relies on its mapping;
+	/// * no unsupported flags are given to
+	///   [`extra_clone_flags`](#method.extra_clone_flags); and
+	/// * the targets of mounts are within the root, if mounts are
+	///   [confined](#method.confine_mounts).
+	///
+	/// This is checked before a child is created. Adding more than one
+	/// [`Pid`], [`User`], or [`Uts`] namespace is not an error, as only one of
+	/// each is created, but is logged as a warning.
+	///
+	/// [`Namespace::required_flags`]: namespace/trait.Namespace.html#method.required_flags
+	/// [`User`]: namespace/struct.User.html
+	/// [`Pid`]: namespace/struct.Pid.html
+	/// [`Uts`]: namespace/struct.Uts.html
+	/// [`Enter`]: namespace/struct.Enter.html
+	pub fn validate(&self) -> Result<()> {
+		let invalid = |reason: String| -> Result<()> {
+			Err(ErrorKind::InvalidNamespaceOrder(reason).into())
+		};
+
+		let unsupported = match self.spawn_mode {
+			SpawnMode::Clone => self.extra_clone_flags & UNSUPPORTED_CLONE_FLAGS,
+			SpawnMode::ForkUnshare => self.extra_clone_flags & !NAMESPACE_FLAGS,
+		};
+		if unsupported != 0 {
+			return Err(ErrorKind::UnsupportedCloneFlags(CloneFlags(unsupported)).into());
 		}
-	}
 
-	/// Wait for a the child process to exit.
-	pub fn wait(self) -> Result<()> {
-		let Child(pid) = self;
+		let created = self.clone_flag();
+		let entered = self.entered_flags();
+		let mut entered_before = 0;
+		for ns in &self.namespaces {
+			let missing = ns.required_flags() & !(created | entered);
+			if missing != 0 {
+				return invalid(format!(
+					"{:?} requires namespaces that are not created ({})",
+					ns,
+					CloneFlags(missing),
+				));
+			}
 
-		let mut wstatus = 0;
+			let not_entered = ns.required_flags() & entered & !entered_before;
+			if not_entered != 0 {
+				return invalid(format!(
+					"{:?} must be added after the namespaces it requires are entered ({})",
+					ns,
+					CloneFlags(not_entered),
+				));
+			}
+			entered_before |= ns.entered_flags();
+		}
 
-		unsafe {
-			match waitpid(pid, &mut wstatus as *mut c_int, 0) {
-				-1 => Err(errno!(ChildWait)),
-				_ => Ok(())
+		for user in self.namespaces().filter_map(|ns| ns.as_any().downcast_ref::<User>()) {
+			user.validate()?;
+		}
+
+		if self.root.is_none() && self.count::<RootFs>() != 0 {
+			return invalid("a root file-system requires the context to have a root".to_owned());
+		}
+
+		if self.seal_root.is_some() && (created | entered) & CLONE_NEWNS == 0 {
+			return invalid("sealing the root requires a new mount namespace".to_owned());
+		}
+
+		// The root propagation is changed before any namespace is entered,
+		// so an entered mount namespace is not enough.
+		if !self.root_propagation.is_empty() && created & CLONE_NEWNS == 0 {
+			return invalid("setting the root propagation requires a new mount namespace".to_owned());
+		}
+
+		if let (true, Some(root)) = (self.confine_mounts, self.root.as_ref()) {
+			let root = resolve_partial(root)?;
+			for mount in self.mounts() {
+				let target = resolve_partial(&mount.target_under(&root))?;
+				if !target.starts_with(&root) {
+					return Err(ErrorKind::MountEscapesRoot(target, root).into());
+				}
 			}
 		}
-	}
 
-	/// Get the PID of the child process.
-	pub fn pid(&self) -> i32 {
-		self.0
+		let namespaces = self.namespaces.iter().filter(|ns| ns.clone_flag() != 0);
+		for (index, ns) in namespaces.enumerate() {
+			if index > 0 && ns.clone_flag() & CLONE_NEWUSER != 0 {
+				return invalid(format!(
+					"{:?} must be added before any other namespace",
+					ns,
+				));
+			}
+		}
+
+		if entered & CLONE_NEWUSER != 0 && created & NAMESPACE_FLAGS != 0 {
+			warn!(
+				"namespaces ({}) are created before the user namespace is entered, so are not owned by it",
+				CloneFlags(created & NAMESPACE_FLAGS),
+			);
+		}
+
+		for name in self.duplicate_namespaces() {
+			warn!("{} namespace added more than once, only one is created", name);
+		}
+
+		Ok(())
 	}
 
-	/// Tell the child to continue execution.
-	fn cont(&self) -> Result<()> {
-		match unsafe { kill(self.pid(), SIGCONT) } {
-			-1 => Err(errno!(ChildContinue)),
-			_ => Ok(())
+	/// Find namespaces that are added more than once but of which only one
+	/// can be created.
+	fn duplicate_namespaces(&self) -> Vec<&'static str> {
+		let mut duplicates = Vec::new();
+		if self.count::<Pid>() > 1 {
+			duplicates.push("PID");
+		}
+		if self.count::<User>() > 1 {
+			duplicates.push("User");
 		}
+		if self.count::<Uts>() > 1 {
+			duplicates.push("UTS");
+		}
+
+		duplicates
+	}
+
+	/// Determine if the child is in any namespace other than those of the
+	/// parent.
+	fn isolated(&self) -> bool {
+		(self.clone_flag() | self.entered_flags()) & NAMESPACE_FLAGS != 0
+	}
+
+	/// Count the namespaces of a given type in the context.
+	fn count<N: Namespace + 'static>(&self) -> usize {
+		self.namespaces.iter().filter(|ns| ns.as_any().is::<N>()).count()
+	}
+
+	/// Create a process in a new private address space.
+	///
+	/// The address space is copied and no references are shared.
+	pub fn exec_private<F>(&self, f: F) -> Result<Child>
+	where
+		F: 'static + FnOnce()
+	{
+		self.exec(f, Share::Private)
+	}
+
+	/// Create and enter the context, running the given function.
+	///
+	/// The address space is shared with the child and the calling process
+	/// allowing shared access to globals, etc. A warning is logged if the
+	/// child is not placed in any new namespaces unless the context is
+	/// [`unisolated`](#method.unisolated).
+	pub fn exec_shared<F>(&self, f: F) -> Result<Child>
+	where
+		F: 'static + FnOnce()
+	{
+		self.exec(f, Share::Shared)
+	}
+
+	/// Run the given function in a new private address space and wait for
+	/// it to exit.
+	///
+	/// This is the same as [`exec_private`](#method.exec_private) followed by
+	/// waiting on and cleaning up after the child, returning how it exited.
+	///
+	/// ```rust
+	/// let status = Context::new().with(Pid::new()).exec_blocking(|| {})?;
+	/// assert!(status.success());
+	/// ```
+	pub fn exec_blocking<F>(&self, f: F) -> Result<WaitStatus>
+	where
+		F: 'static + FnOnce()
+	{
+		let mut child = self.exec_private(f)?;
+		let (status, _) = child.reap()?;
+		child.cleanup()?;
+
+		Ok(status)
+	}
+
+	/// Create a process in a new private address space that can signal when
+	/// it is ready.
+	///
+	/// The function is given a [`Ready`](struct.Ready.html) handle to call
+	/// once the child is ready, such as when a server has bound its socket,
+	/// and the parent waits for it with the returned
+	/// [`ReadyWaiter`](struct.ReadyWaiter.html).
+	///
+	/// ```rust
+	/// let (child, mut waiter) = context.spawn_with_ready(|ready| {
+	///     let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+	///     ready.ready().unwrap();
+	///     serve(listener);
+	/// })?;
+	/// if !waiter.wait_ready(Duration::from_secs(5))? {
+	///     child.terminate(Duration::from_secs(1))?;
+	/// }
+	/// ```
+	pub fn spawn_with_ready<F>(&self, f: F) -> Result<(Child, ReadyWaiter)>
+	where
+		F: 'static + FnOnce(Ready)
+	{
+		let mut fds = [0; 2];
+		if unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } == -1 {
+			return Err(errno!(ReadyPipe));
+		}
+
+		let ready = Ready { fd: fds[1] };
+		let waiter = ReadyWaiter { fd: fds[0], ready: false };
+		let child = self.exec_private(move || f(ready));
+
+		// Only the child holds the write end so that the pipe is closed if it
+		// exits or executes a program without signalling.
+		unsafe { close(fds[1]) };
+
+		Ok((child?, waiter))
+	}
+
+	/// Execute a child with a given function.
+	fn exec<F>(&self, close: F, shared: Share) -> Result<Child>
+	where
+		F: 'static + FnOnce()
+	{
+		self.validate()?;
+
+		if let Share::Shared = shared {
+			if !self.unisolated && !self.isolated() {
+				warn!("child shares the address space of the parent and is in no new namespaces");
+			}
+		}
+
+		let flags = self.clone_flag() | shared.addrspace() | SIGCHLD;
+		let stack = match self.spawn_mode {
+			SpawnMode::Clone => {
+				debug!("cloning child with flags {:#x}", flags);
+				Some(match self.stack {
+					Some(borrowed) => borrowed.top()?,
+					None => create_stack(shared, self.stack_size)?,
+				})
+			}
+			SpawnMode::ForkUnshare => {
+				if let Share::Shared = shared {
+					return Err(ErrorKind::UnsupportedCloneFlags(CloneFlags(CLONE_VM)).into());
+				}
+				debug!("forking child to unshare flags {:#x}", flags & NAMESPACE_FLAGS);
+				None
+			}
+		};
+
+		// Send the closure to a new process.
+		let handshake = Handshake::new()?;
+		let errors = match ErrorPipe::new() {
+			Ok(errors) => errors,
+			Err(err) => {
+				handshake.close();
+				return Err(err);
+			}
+		};
+		let stderr = if self.capture_stderr {
+			match StderrPipe::new() {
+				Ok(stderr) => Some(stderr),
+				Err(err) => {
+					handshake.close();
+					errors.close();
+					return Err(err);
+				}
+			}
+		} else {
+			None
+		};
+
+		// Prepare a copy of the context so that anything recorded while
+		// preparing is cleaned up with the child.
+		let mut config = self.clone();
+		if let Some(root) = self.root.clone() {
+			config.set_root(&root);
+		}
+		if let Err(err) = config.prepare() {
+			handshake.close();
+			errors.close();
+			if let Some(stderr) = stderr {
+				stderr.close();
+			}
+			let _ = config.cleanup();
+			return Err(err);
+		}
+
+		// A forked child creates its own namespaces, which must exist before
+		// they are configured from the parent.
+		let unshared = match stack {
+			Some(_) => None,
+			None => match Handshake::new() {
+				Ok(unshared) => Some(unshared),
+				Err(err) => {
+					handshake.close();
+					errors.close();
+					if let Some(stderr) = stderr {
+						stderr.close();
+					}
+					let _ = config.cleanup();
+					return Err(err);
+				}
+			},
+		};
+
+		let pair = Box::into_raw(Box::new((config.clone(), close, handshake, stderr, errors, unshared)));
+		let tid = match stack {
+			Some(ref stack) => unsafe {
+				clone(exec_closure::<F>, stack.top(), flags, pair as *mut c_void)
+			},
+			None => match unsafe { fork() } {
+				0 => unsafe { _exit(exec_closure::<F>(pair as *mut c_void)) },
+				pid => pid,
+			},
+		};
+		if tid == -1 {
+			let err = errno!(Clone, CloneFlags(flags & !CSIGNAL));
+			drop(unsafe { Box::from_raw(pair) });
+			handshake.close();
+			errors.close();
+			if let Some(stderr) = stderr {
+				stderr.close();
+			}
+			if let Some(unshared) = unshared {
+				unshared.close();
+			}
+			let _ = config.cleanup();
+			return Err(err);
+		}
+
+		let mut child = Child::new(tid, config);
+		child.stack = stack;
+		child.stderr = stderr.map(StderrPipe::into_parent);
+		debug!("created child {}", child.pid());
+
+		// If the child couldn't create its namespaces, it reports why.
+		let created = match unshared {
+			Some(unshared) => unshared.wait(),
+			None => true,
+		};
+		let configured = if created {
+			child.config.configure(&child).and_then(|_| child.cont(&handshake))
+		} else {
+			Ok(())
+		};
+		handshake.close();
+		let configured = match configured {
+			Ok(()) => errors.receive(),
+			Err(err) => {
+				errors.close();
+				Err(err)
+			}
+		};
+
+		// Don't leave a half-configured child waiting to be continued.
+		if let Err(err) = configured {
+			if !child.reaped {
+				unsafe { kill(child.pid(), SIGKILL) };
+			}
+			return Err(err);
+		}
+
+		Ok(child)
+	}
+
+	/// Raise the effective user ID to root if configuration is privileged.
+	fn elevate(&self) -> Result<Option<EffectiveRoot>> {
+		if self.prepare_as_root {
+			EffectiveRoot::acquire().map(Some)
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Configure the context of the child externally.
+	fn configure(&self, child: &Child) -> Result<()> {
+		let _root = self.elevate()?;
+		for namespace in &self.namespaces {
+			namespace.external_config(child)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Context {
+	fn default() -> Context {
+		Context::new()
+	}
+}
+
+impl Namespace for Context {
+	fn clone_flag(&self) -> c_int {
+		let created = self.namespaces.iter().fold(0, |f, n| f | n.clone_flag());
+		(created & !self.entered_flags()) | self.extra_clone_flags
+	}
+
+	fn entered_flags(&self) -> c_int {
+		self.namespaces.iter().fold(0, |f, n| f | n.entered_flags())
+	}
+
+	fn required_flags(&self) -> c_int {
+		self.namespaces.iter().fold(0, |f, n| f | n.required_flags())
+	}
+
+	fn prepare(&self) -> Result<()> {
+		let _root = self.elevate()?;
+		for ns in &self.namespaces {
+			ns.prepare()?;
+		}
+
+		Ok(())
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		for ns in &mut self.namespaces {
+			ns.internal_config()?;
+		}
+
+		Ok(())
+	}
+
+	fn external_config(&self, child: &Child) -> Result<()> {
+		let _root = self.elevate()?;
+		for ns in &self.namespaces {
+			ns.external_config(child)?;
+		}
+
+		Ok(())
+	}
+
+	fn set_root(&mut self, root: &Path) {
+		for ns in &mut self.namespaces {
+			ns.set_root(root);
+		}
+	}
+
+	fn cleanup(&mut self) -> Result<()> {
+		let _root = self.elevate()?;
+
+		// Attempt to clean up every namespace, reporting the first failure.
+		let mut result = Ok(());
+		for ns in self.namespaces.iter_mut().rev() {
+			let cleaned = ns.cleanup();
+			if result.is_ok() {
+				result = cleaned;
+			}
+		}
+
+		result
+	}
+}
+
+impl Drop for Context {
+	fn drop(&mut self) {
+		// Forcibly drop namespaces in reverse order.
+		while self.namespaces.pop().is_some() {};
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Share {
+	Shared,
+	Private
+}
+
+impl Share {
+	fn map(&self) -> c_int {
+		match *self {
+			Share::Shared => MAP_SHARED,
+			Share::Private => MAP_PRIVATE,
+		}
+	}
+
+	fn addrspace(&self) -> c_int {
+		match *self {
+			Share::Private => 0,
+			Share::Shared => CLONE_VM,
+		}
+	}
+}
+
+/// How the child process is created.
+///
+/// See [`Context::spawn_mode`](struct.Context.html#method.spawn_mode).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpawnMode {
+	/// Create the child and its namespaces together with `clone(2)`.
+	///
+	/// The child runs on a stack allocated for it, or one given with
+	/// [`Context::with_stack`](struct.Context.html#method.with_stack), and
+	/// can share the address space of the parent.
+	Clone,
+	/// Create the child with `fork(2)`, after which it creates its own
+	/// namespaces with `unshare(2)`.
+	///
+	/// The child runs on a copy of the stack of the calling thread, which
+	/// avoids the separate stack for libraries that depend on the layout of
+	/// the stacks of their threads. Any stack or stack size given to the
+	/// context is ignored. The address space can't be shared, so
+	/// [`exec_shared`](struct.Context.html#method.exec_shared) fails, and
+	/// extra clone flags other than those of namespaces are rejected.
+	///
+	/// Unlike `clone(2)`, `unshare(2)` doesn't move the calling process into
+	/// a new PID namespace; only the processes the child creates afterwards
+	/// are in it. The child itself keeps its PID in the PID namespace of the
+	/// parent, isn't the init process of the new namespace, and a `proc`
+	/// file-system it mounts shows the processes of the PID namespace of the
+	/// parent. The first process the child creates becomes the init process
+	/// of the new namespace.
+	ForkUnshare,
+}
+
+/// The stack on which a child executes.
+#[derive(Debug)]
+enum Stack {
+	/// A stack mapped by the crate, unmapped when dropped.
+	Owned {
+		base: NonNull<c_void>,
+		size: size_t,
+	},
+	/// The top of a stack provided by the caller.
+	Borrowed(NonNull<c_void>),
+}
+
+impl Stack {
+	fn from_ptr(ptr: *mut c_void, size: size_t) -> Result<Stack> {
+		match ptr as isize {
+			-1 | 0 => Err(errno!(StackAllocation)),
+			_ => unsafe {
+				Ok(Stack::Owned {
+					base: NonNull::new_unchecked(ptr),
+					size,
+				})
+			},
+		}
+	}
+
+	/// The top of the stack, where execution of the child starts.
+	fn top(&self) -> *mut c_void {
+		match *self {
+			Stack::Owned { base, size } => unsafe {
+				(base.as_ptr() as *mut u8).add(size) as *mut c_void
+			},
+			Stack::Borrowed(top) => top.as_ptr(),
+		}
+	}
+}
+
+impl Drop for Stack {
+	fn drop(&mut self) {
+		if let Stack::Owned { base, size } = *self {
+			unsafe { munmap(base.as_ptr(), size) };
+		}
+	}
+}
+
+/// A stack provided by the caller.
+#[derive(Copy, Clone, Debug)]
+struct BorrowedStack {
+	ptr: *mut u8,
+	len: size_t,
+}
+
+impl BorrowedStack {
+	fn top(&self) -> Result<Stack> {
+		let min = min_stack_size();
+		if self.len < min {
+			return Err(ErrorKind::StackTooSmall(self.len, min).into());
+		}
+
+		// The stack grows down from a 16-byte aligned address.
+		let top = (self.ptr as usize + self.len) & !(STACK_ALIGN - 1);
+		match NonNull::new(top as *mut c_void) {
+			Some(top) if top.as_ptr() as usize > self.ptr as usize => Ok(Stack::Borrowed(top)),
+			_ => Err(ErrorKind::StackAllocation(::errno::Errno(EINVAL)).into()),
+		}
+	}
+}
+
+const STACK_PAGES: size_t = 2 * 1024;
+const MIN_STACK_PAGES: size_t = 16;
+const STACK_ALIGN: usize = 16;
+const NO_FILE: c_int = -1;
+const NO_OFFSET: off_t = 0;
+
+/// Interval at which a terminated child is checked for having exited.
+const TERMINATE_POLL: Duration = Duration::from_millis(10);
+
+/// Mask of the termination signal in the flags passed to `clone(2)`.
+const CSIGNAL: c_int = 0xff;
+
+/// Flags that can't be passed to `clone(2)` with
+/// [`Context::extra_clone_flags`](struct.Context.html#method.extra_clone_flags).
+///
+/// These share state that is changed by the child or parent independently,
+/// change the parent of the child, or require arguments that aren't passed.
+const UNSUPPORTED_CLONE_FLAGS: c_int =
+	CSIGNAL |
+	CLONE_VM |
+	CLONE_FS |
+	CLONE_FILES |
+	CLONE_SIGHAND |
+	CLONE_VFORK |
+	CLONE_PARENT |
+	CLONE_THREAD |
+	CLONE_SETTLS |
+	CLONE_PARENT_SETTID |
+	CLONE_CHILD_CLEARTID |
+	CLONE_CHILD_SETTID;
+
+/// Flags that create new namespaces.
+const NAMESPACE_FLAGS: c_int =
+	CLONE_NEWCGROUP |
+	CLONE_NEWIPC |
+	CLONE_NEWNET |
+	CLONE_NEWNS |
+	CLONE_NEWPID |
+	CLONE_NEWUSER |
+	CLONE_NEWUTS;
+
+/// Create a new stack in which to execute a child function.
+fn create_stack(share: Share, size: Option<size_t>) -> Result<Stack> {
+	let prot = PROT_WRITE | PROT_READ;
+	let flags =
+		share.map() |
+		MAP_ANONYMOUS |
+		MAP_STACK;
+
+	unsafe {
+		let page_size = sysconf(_SC_PAGE_SIZE) as size_t;
+		let size = size.unwrap_or_else(|| default_stack_size(page_size));
+		let size = size.div_ceil(page_size) * page_size;
+		Stack::from_ptr(
+			mmap(ptr::null_mut(), size, prot, flags, NO_FILE, NO_OFFSET),
+			size
+		)
+	}
+}
+
+/// Determine the smallest stack that a child may be given.
+fn min_stack_size() -> size_t {
+	MIN_STACK_PAGES * unsafe { sysconf(_SC_PAGE_SIZE) } as size_t
+}
+
+/// Determine the default stack size from the stack resource limit.
+fn default_stack_size(page_size: size_t) -> size_t {
+	let mut limit = rlimit {
+		rlim_cur: 0,
+		rlim_max: 0,
+	};
+
+	match unsafe { getrlimit(RLIMIT_STACK, &mut limit) } {
+		-1 => STACK_PAGES * page_size,
+		_ if limit.rlim_cur == RLIM_INFINITY => STACK_PAGES * page_size,
+		_ => max(limit.rlim_cur as size_t, MIN_STACK_PAGES * page_size),
+	}
+}
+
+/// A pipe used to hold the child until the parent has configured it.
+///
+/// Both the parent and the child hold a copy of each end of the pipe and must
+/// close their own copies.
+#[derive(Copy, Clone, Debug)]
+struct Handshake {
+	read: RawFd,
+	write: RawFd,
+}
+
+impl Handshake {
+	fn new() -> Result<Handshake> {
+		let mut fds = [0; 2];
+		match unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } {
+			-1 => Err(errno!(Handshake)),
+			_ => Ok(Handshake {
+				read: fds[0],
+				write: fds[1],
+			}),
+		}
+	}
+
+	/// Wait in the child for the parent to finish configuration.
+	///
+	/// If the parent closes the pipe without releasing the child, the child
+	/// should not continue.
+	fn wait(self) -> bool {
+		let mut byte = 0u8;
+
+		unsafe {
+			close(self.write);
+			let released = loop {
+				match read(self.read, &mut byte as *mut u8 as *mut c_void, 1) {
+					-1 if ::errno::errno().0 == EINTR => continue,
+					n => break n == 1,
+				}
+			};
+			close(self.read);
+
+			released
+		}
+	}
+
+	/// Release the child once configuration is complete.
+	fn release(&self) -> Result<()> {
+		let byte = 0u8;
+		match unsafe { write(self.write, &byte as *const u8 as *const c_void, 1) } {
+			1 => Ok(()),
+			_ => Err(errno!(ChildContinue)),
+		}
+	}
+
+	/// Close the copy of the pipe held by this process.
+	fn close(self) {
+		unsafe {
+			close(self.read);
+			close(self.write);
+		}
+	}
+}
+
+/// A pipe used to capture the standard error of the child.
+#[derive(Copy, Clone, Debug)]
+struct StderrPipe {
+	read: RawFd,
+	write: RawFd,
+}
+
+impl StderrPipe {
+	fn new() -> Result<StderrPipe> {
+		let mut fds = [0; 2];
+		match unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } {
+			-1 => Err(errno!(CaptureStderr)),
+			_ => Ok(StderrPipe {
+				read: fds[0],
+				write: fds[1],
+			}),
+		}
+	}
+
+	/// Keep the read end of the pipe in the parent.
+	fn into_parent(self) -> File {
+		unsafe {
+			close(self.write);
+			File::from_raw_fd(self.read)
+		}
+	}
+
+	/// Replace the standard error of the child with the write end of the pipe.
+	fn into_child(self) {
+		unsafe {
+			close(self.read);
+			// The duplicated descriptor never has close-on-exec set.
+			dup2(self.write, STDERR_FILENO);
+			close(self.write);
+		}
+	}
+
+	/// Close both ends of the pipe.
+	fn close(self) {
+		unsafe {
+			close(self.read);
+			close(self.write);
+		}
+	}
+}
+
+/// A pipe used by the child to report errors while configuring itself.
+///
+/// The child closes its copy of the write end once it has been configured, so
+/// the parent reads until either an error is reported or the pipe is closed.
+#[derive(Copy, Clone, Debug)]
+struct ErrorPipe {
+	read: RawFd,
+	write: RawFd,
+}
+
+impl ErrorPipe {
+	fn new() -> Result<ErrorPipe> {
+		let mut fds = [0; 2];
+		match unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } {
+			-1 => Err(errno!(Handshake)),
+			_ => Ok(ErrorPipe {
+				read: fds[0],
+				write: fds[1],
+			}),
+		}
+	}
+
+	/// Wait in the parent for the child to finish configuring itself.
+	fn receive(self) -> Result<()> {
+		let mut message = Vec::new();
+		let mut buffer = [0u8; 256];
+
+		unsafe {
+			close(self.write);
+			loop {
+				match read(self.read, buffer.as_mut_ptr() as *mut c_void, buffer.len()) {
+					-1 if ::errno::errno().0 == EINTR => continue,
+					n if n > 0 => message.extend_from_slice(&buffer[..n as usize]),
+					_ => break,
+				}
+			}
+			close(self.read);
+		}
+
+		match message.len() {
+			0 => Ok(()),
+			_ => Err(ErrorKind::ChildSetup(String::from_utf8_lossy(&message).into_owned()).into()),
+		}
+	}
+
+	/// Report an error from the child.
+	fn report(self, err: &Error) {
+		let message = err.to_string();
+
+		unsafe {
+			close(self.read);
+			write(self.write, message.as_ptr() as *const c_void, message.len());
+			close(self.write);
+		}
+	}
+
+	/// Close the copy of the pipe held by this process.
+	fn close(self) {
+		unsafe {
+			close(self.read);
+			close(self.write);
+		}
+	}
+}
+
+/// Execute a function from a closure.
+extern "C"
+fn exec_closure<F>(closure: *mut c_void) -> c_int
+where
+	F: FnOnce()
+{
+	type Closure<F> = (Context, F, Handshake, Option<StderrPipe>, ErrorPipe, Option<Handshake>);
+	let pair: Box<Closure<F>> = unsafe {
+		Box::from_raw(closure as *mut Closure<F>)
+	};
+
+	let (mut context, close, handshake, stderr, errors, unshared) = *pair;
+
+	// A forked child creates its namespaces before the parent configures
+	// them.
+	if let Some(unshared) = unshared {
+		let flags = context.clone_flag() & NAMESPACE_FLAGS;
+		let created = match unsafe { unshare(flags) } {
+			-1 => Err(errno!(Unshare, CloneFlags(flags))),
+			_ => unshared.release(),
+		};
+		unshared.close();
+		if let Err(err) = created {
+			handshake.close();
+			errors.report(&err);
+			return EXIT_FAILURE;
+		}
+	}
+
+	// Wait for the parent to finish configuration.
+	if !handshake.wait() {
+		errors.close();
+		return EXIT_FAILURE;
+	}
+
+	if let Some(stderr) = stderr {
+		stderr.into_child();
+	}
+
+	if let Err(err) = configure_child(&mut context) {
+		errors.report(&err);
+		return EXIT_FAILURE;
+	}
+	errors.close();
+
+	close();
+	EXIT_SUCCESS
+}
+
+/// Configure the child from within.
+fn configure_child(context: &mut Context) -> Result<()> {
+	for mount in &mut context.root_propagation {
+		mount.mount()?;
+	}
+	context.internal_config()?;
+	if let Some(ref mut seal_root) = context.seal_root {
+		seal_root.mount()?;
+	}
+	let groups_set = context.count::<Groups>() != 0;
+	if let Some(ref mut switch_to) = context.switch_to {
+		if groups_set {
+			switch_to.keep_groups();
+		}
+		if let Some(ref capabilities) = context.capabilities {
+			capabilities.keep_across_switch()?;
+		}
+		switch_to.internal_config()?;
+	}
+	if let Some(ref capabilities) = context.capabilities {
+		capabilities.apply()?;
+	}
+	for hook in &context.pre_exec {
+		(hook.0.borrow_mut())()?;
+	}
+
+	Ok(())
+}
+
+/// Directories in `/proc` and `/sys` hidden by
+/// [`Context::sandbox`](struct.Context.html#method.sandbox).
+const SANDBOX_MASKED_DIRS: &[&str] = &["/proc/acpi", "/proc/scsi", "/sys/firmware"];
+
+/// Files in `/proc` hidden by [`Context::sandbox`](struct.Context.html#method.sandbox).
+const SANDBOX_MASKED_FILES: &[&str] = &[
+	"/proc/kcore",
+	"/proc/keys",
+	"/proc/latency_stats",
+	"/proc/sched_debug",
+	"/proc/timer_list",
+];
+
+/// A handler for errors that occur while cleaning up a dropped child.
+#[derive(Clone)]
+struct CleanupHandler(Rc<dyn Fn(Error)>);
+
+impl ::std::fmt::Debug for CleanupHandler {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		write!(f, "CleanupHandler")
+	}
+}
+
+/// A closure run within the child before the function it executes.
+#[derive(Clone)]
+struct PreExec(Rc<RefCell<dyn FnMut() -> Result<()>>>);
+
+impl ::std::fmt::Debug for PreExec {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		write!(f, "PreExec")
+	}
+}
+
+/// The child thread that has been started in the context.
+///
+/// When dropped, the child is sent `SIGKILL` and reaped if it hasn't already
+/// been, so that dropping it never blocks on a child that doesn't exit, such
+/// as while unwinding from a panic. A child that is still running is killed
+/// rather than waited on, so call [`wait`](#method.wait) to let it run to
+/// completion, or [`into_raw`](#method.into_raw) to leave it running. The
+/// external configuration of its context is then cleaned up. Any errors that occur while doing so are passed to the
+/// cleanup handler of the context (see
+/// [`Context::cleanup_handler`](struct.Context.html#method.cleanup_handler))
+/// or logged.
+#[derive(Debug)]
+pub struct Child {
+	pid: pid_t,
+	config: Context,
+	reaped: bool,
+	status: Option<(WaitStatus, Rusage)>,
+	cleaned: bool,
+	stderr: Option<File>,
+	stack: Option<Stack>,
+}
+
+impl Child {
+	fn new(pid: pid_t, config: Context) -> Child {
+		Child {
+			pid,
+			config,
+			reaped: false,
+			status: None,
+			cleaned: false,
+			stderr: None,
+			stack: None,
+		}
+	}
+
+	/// Wait for the child and clean up the external configuration of its
+	/// context.
+	///
+	/// This is done automatically when the child is dropped, except that the
+	/// child is first killed and any errors are only reported to the cleanup
+	/// handler of the context.
+	pub fn cleanup(mut self) -> Result<()> {
+		if !self.reaped {
+			self.reap()?;
+		}
+
+		self.cleaned = true;
+		self.config.cleanup()
+	}
+
+	/// Take the read end of the pipe connected to the standard error of the
+	/// child.
+	///
+	/// This is only available if the context was configured with
+	/// [`Context::capture_stderr`](struct.Context.html#method.capture_stderr)
+	/// and will return `None` once it has been taken.
+	pub fn stderr(&mut self) -> Option<File> {
+		self.stderr.take()
+	}
+
+	/// Wait for a the child process to exit and return its status.
+	///
+	/// If the child has already been reaped by
+	/// [`try_wait`](#method.try_wait), its status is returned immediately.
+	pub fn wait(mut self) -> Result<WaitStatus> {
+		self.reap().map(|(status, _)| status)
+	}
+
+	/// Check if the child has exited without blocking.
+	///
+	/// Once the child has exited, it is reaped and its status is returned.
+	/// The child is only reaped once, after which the same status is always
+	/// returned.
+	pub fn try_wait(&mut self) -> Result<Option<WaitStatus>> {
+		self.collect(WNOHANG).map(|exit| exit.map(|(status, _)| status))
+	}
+
+	/// Determine whether the child has already been reaped.
+	#[cfg(feature = "async")]
+	pub(crate) fn reaped(&self) -> bool {
+		self.reaped
+	}
+
+	/// Wait for the child to exit and collect its resource usage.
+	///
+	/// The resource usage covers the child and all of its descendants that
+	/// it waited on (see `wait4(2)`).
+	pub fn wait_with_usage(mut self) -> Result<(WaitStatus, Rusage)> {
+		self.reap()
+	}
+
+	/// Wait for the child and every process in its PID namespace to exit.
+	///
+	/// When the init process (PID 1) of a PID namespace exits, the kernel
+	/// sends `SIGKILL` to every other process in the namespace. This waits for
+	/// the child and then for each of these processes to be killed, so that no
+	/// descendants in the namespace outlive the call.
+	///
+	/// If the child is not in a new PID namespace, this is the same as
+	/// [`wait`](#method.wait). The status of the child is returned.
+	pub fn wait_all(self) -> Result<WaitStatus> {
+		let namespace = namespace_key(&self.proc_path("ns/pid"))?;
+		let own_namespace = namespace_key(Path::new("/proc/self/ns/pid"))?;
+
+		let status = self.wait()?;
+
+		if namespace == own_namespace {
+			return Ok(status);
+		}
+
+		while pid_namespace_occupied(namespace)? {
+			sleep(Duration::from_millis(1));
+		}
+
+		Ok(status)
+	}
+
+	/// Send a signal to the child process.
+	///
+	/// This fails without sending the signal if the child has already been
+	/// reaped, as its PID may have been reused.
+	pub fn signal(&self, signal: c_int) -> Result<()> {
+		self.ensure_running()?;
+		if unsafe { kill(self.pid, signal) } == -1 {
+			return Err(errno!(Signal, self.pid, signal));
+		}
+
+		Ok(())
+	}
+
+	/// Ask the child to exit, killing it if it doesn't exit in time.
+	///
+	/// The child is sent `SIGTERM` and given `grace` to exit, after which it
+	/// is sent `SIGKILL`. The child is reaped in either case and its status
+	/// is returned. If the child has already been reaped, its status is
+	/// returned without signalling it.
+	///
+	/// ```rust
+	/// let status = child.terminate(Duration::from_secs(5))?;
+	/// ```
+	pub fn terminate(mut self, grace: Duration) -> Result<WaitStatus> {
+		if let Some((status, _)) = self.status {
+			return Ok(status);
+		}
+
+		self.signal(SIGTERM)?;
+
+		let deadline = Instant::now() + grace;
+		while Instant::now() < deadline {
+			if let Some(status) = self.try_wait()? {
+				return Ok(status);
+			}
+			sleep(TERMINATE_POLL.min(deadline - Instant::now()));
+		}
+
+		if let Some(status) = self.try_wait()? {
+			return Ok(status);
+		}
+
+		self.signal(SIGKILL)?;
+		self.reap().map(|(status, _)| status)
+	}
+
+	/// Send a signal to a process in the PID namespace of the child.
+	///
+	/// `inner_pid` is the PID of the process as seen from within the
+	/// namespace of the child. It is translated to the PID seen by the caller
+	/// using the `NSpid` field of `/proc/<pid>/status` of each process in the
+	/// namespace. This fails if the child has already been reaped.
+	///
+	/// ```rust
+	/// // Interrupt the process with PID 2 in the namespace of the child.
+	/// child.signal_inner(2, SIGINT)?;
+	/// ```
+	pub fn signal_inner(&self, inner_pid: pid_t, signal: c_int) -> Result<()> {
+		let pid = self.host_pid(inner_pid)?;
+		if unsafe { kill(pid, signal) } == -1 {
+			return Err(errno!(Signal, pid, signal));
+		}
+
+		Ok(())
+	}
+
+	/// Translate a PID in the PID namespace of the child to the PID seen by
+	/// the caller.
+	fn host_pid(&self, inner_pid: pid_t) -> Result<pid_t> {
+		self.ensure_running()?;
+		let namespace = namespace_key(&self.proc_path("ns/pid"))?;
+
+		for entry in read_dir("/proc")? {
+			let path = entry?.path();
+
+			// Processes that have exited or are not processes are ignored.
+			match namespace_key(&path.join("ns/pid")) {
+				Ok(key) if key == namespace => {},
+				_ => continue,
+			}
+
+			let mut status = String::new();
+			if File::open(path.join("status"))
+				.and_then(|mut file| file.read_to_string(&mut status))
+				.is_err()
+			{
+				continue;
+			}
+
+			let pids: Vec<pid_t> = status.lines()
+				.find(|line| line.starts_with("NSpid:"))
+				.map(|line| line["NSpid:".len()..]
+					.split_whitespace()
+					.filter_map(|pid| pid.parse().ok())
+					.collect())
+				.unwrap_or_default();
+
+			// The first PID is in the namespace of the caller and the last in
+			// the namespace of the process.
+			if pids.last() == Some(&inner_pid) {
+				if let Some(&pid) = pids.first() {
+					return Ok(pid);
+				}
+			}
+		}
+
+		Err(ErrorKind::NoSuchInnerPid(inner_pid).into())
+	}
+
+	/// List the processes created by the child that have not been reaped.
+	///
+	/// Only the direct children of the child are listed, as seen from the PID
+	/// namespace of the caller. Processes may exit while the list is being
+	/// read, so it may be out of date once it is returned. This requires a
+	/// kernel built with `CONFIG_PROC_CHILDREN`.
+	pub fn children(&self) -> Result<Vec<pid_t>> {
+		let mut children = Vec::new();
+
+		for task in read_dir(self.proc_path("task"))? {
+			// Threads that have exited while reading are ignored.
+			let path = match task {
+				Ok(task) => task.path().join("children"),
+				Err(_) => continue,
+			};
+
+			let mut pids = String::new();
+			if File::open(path).and_then(|mut file| file.read_to_string(&mut pids)).is_err() {
+				continue;
+			}
+
+			children.extend(pids.split_whitespace().filter_map(|pid| pid.parse::<pid_t>().ok()));
+		}
+
+		Ok(children)
+	}
+
+	/// Open the namespace of the given kind of the child.
+	///
+	/// The namespace is kept alive while the file is open, even once the
+	/// child has exited, and can be joined by another context with
+	/// [`Enter::file`](namespace/struct.Enter.html#method.file).
+	///
+	/// ```rust
+	/// let user = child.namespace_fd(NsKind::User)?;
+	/// Context::new().with(Enter::file(user, NsKind::User));
+	/// ```
+	pub fn namespace_fd(&self, kind: NsKind) -> Result<File> {
+		Ok(File::open(self.proc_path("ns").join(kind.name()))?)
+	}
+
+	/// Run a function in a new process in the namespaces of the child.
+	///
+	/// The new process joins each namespace of the child that differs from
+	/// those of the caller, as with [`Enter`], in the way that `nsenter(1)`
+	/// or `docker exec` do. No new namespaces are created and no other
+	/// configuration of the context of the child is applied. The PID
+	/// namespace is joined by creating the new process from within it, so
+	/// the new process is in the PID namespace of the child. The new process
+	/// has the root directory of the mount namespace of the child rather
+	/// than any root directory the child changed to. Time namespaces are not
+	/// joined.
+	///
+	/// The new process is a child of the caller rather than of this child,
+	/// so it must be waited on through the returned `Child`. If this child
+	/// is the init process of a PID namespace, the new process is killed
+	/// when this child exits.
+	///
+	/// ```rust
+	/// let child = Context::new()
+	///     .with(User::new().map_root_user())
+	///     .with(Pid::new())
+	///     .exec_private(|| pause())?;
+	///
+	/// child.exec_in(|| { Command::new("ps").status(); })?.wait()?;
+	/// ```
+	///
+	/// [`Enter`]: namespace/struct.Enter.html
+	pub fn exec_in<F>(&self, f: F) -> Result<Child>
+	where
+		F: 'static + FnOnce()
+	{
+		let mut context = Context::new();
+		let mut pid_namespace = None;
+
+		for &kind in &EXEC_IN_ORDER {
+			let own = metadata(Path::new("/proc/self/ns").join(kind.name()))?.ino();
+			if self.namespace_id(kind)? == own {
+				continue;
+			}
+
+			let namespace = self.namespace_fd(kind)?;
+			match kind {
+				NsKind::Pid => pid_namespace = Some(namespace),
+				_ => context = context.with(Enter::file(namespace, kind)),
+			}
+		}
+
+		match pid_namespace {
+			Some(namespace) => in_pid_namespace(&namespace, || context.exec_private(f)),
+			None => context.exec_private(f),
+		}
+	}
+
+	/// Get the identifier of the namespace of the given kind of the child.
+	///
+	/// This is the inode number of `/proc/<pid>/ns/<name>`, by which the
+	/// kernel identifies the namespace and which is shown by tools such as
+	/// `lsns(8)`. The identifier is only meaningful while a process or open
+	/// file keeps the namespace alive, after which it may be reused.
+	///
+	/// ```rust
+	/// println!("network namespace: net:[{}]", child.namespace_id(NsKind::Network)?);
+	/// ```
+	pub fn namespace_id(&self, kind: NsKind) -> Result<u64> {
+		Ok(metadata(self.proc_path("ns").join(kind.name()))?.ino())
+	}
+
+	/// Read the user ID mapping of the user namespace of the child.
+	///
+	/// Each mapping is given as `(inside, outside, count)`, where `count`
+	/// IDs starting at `inside` in the namespace of the child map to those
+	/// starting at `outside` in the user namespace of the caller. This is the
+	/// mapping recorded by the kernel in `/proc/<pid>/uid_map`.
+	///
+	/// ```rust
+	/// let child = Context::new()
+	///     .with(User::new().map_root_user())
+	///     .exec_private(|| {})?;
+	/// assert_eq!(child.uid_map()?, vec![(0, getuid(), 1)]);
+	/// ```
+	pub fn uid_map(&self) -> Result<Vec<(u32, u32, u32)>> {
+		read_id_map(&self.proc_path("uid_map"))
+	}
+
+	/// Read the group ID mapping of the user namespace of the child.
+	///
+	/// This is read from `/proc/<pid>/gid_map` in the same form as
+	/// [`uid_map`](#method.uid_map).
+	pub fn gid_map(&self) -> Result<Vec<(u32, u32, u32)>> {
+		read_id_map(&self.proc_path("gid_map"))
+	}
+
+	/// Get the PID of the child process.
+	pub fn pid(&self) -> i32 {
+		self.pid
+	}
+
+	/// Release the child from its context, returning its PID.
+	///
+	/// The child keeps running and is neither waited on nor signalled. It can
+	/// later be waited on with [`from_raw`](#method.from_raw), such as by a
+	/// supervisor after it re-executes itself.
+	///
+	/// **The external configuration of the context is never cleaned up.**
+	/// Control groups, generated files, and anything else removed when the
+	/// child is dropped are left in place and must be cleaned up by the
+	/// caller. The stack of the child is leaked, as a child that shares the
+	/// address space of the parent continues to use it. Take the standard
+	/// error of the child with [`stderr`](#method.stderr) first if it was
+	/// captured, as it is otherwise closed.
+	pub fn into_raw(mut self) -> pid_t {
+		mem::forget(self.stack.take());
+		self.reaped = true;
+		self.cleaned = true;
+		self.pid
+	}
+
+	/// Take over a child released with [`into_raw`](#method.into_raw).
+	///
+	/// The returned child can be waited on and signalled but has no context,
+	/// so **nothing is cleaned up when it exits**. Waiting on it requires the
+	/// process to be a child of the caller, and the PID must not have been
+	/// reaped and reused since it was released.
+	///
+	/// ```rust
+	/// let pid = child.into_raw();
+	/// // ... re-execute the supervisor, passing on the PID ...
+	/// let status = Child::from_raw(pid).wait()?;
+	/// ```
+	pub fn from_raw(pid: pid_t) -> DetachedChild {
+		DetachedChild { pid }
+	}
+
+	/// Get the path of a file within the `/proc` directory of the child.
+	///
+	/// ```rust
+	/// let uid_map = child.proc_path("uid_map");
+	/// ```
+	pub fn proc_path(&self, file: &str) -> PathBuf {
+		Path::new("/proc").join(self.pid.to_string()).join(file)
+	}
+
+	/// Wait for the child to exit and reap it.
+	fn reap(&mut self) -> Result<(WaitStatus, Rusage)> {
+		self.collect(0).map(|exit| exit.expect("child not reaped while blocking"))
+	}
+
+	/// Reap the child with `wait4(2)` if it has exited, recording its status.
+	///
+	/// Once the child has been reaped, its recorded status is returned
+	/// without waiting on the PID again, as it may have been reused.
+	fn collect(&mut self, options: c_int) -> Result<Option<(WaitStatus, Rusage)>> {
+		if self.reaped {
+			return match self.status {
+				Some(exit) => Ok(Some(exit)),
+				None => Err(ErrorKind::ChildWait(::errno::Errno(ECHILD)).into()),
+			};
+		}
+
+		let mut wstatus = 0;
+		let mut usage: rusage = unsafe { mem::zeroed() };
+		match unsafe { wait4(self.pid, &mut wstatus, options, &mut usage) } {
+			-1 => Err(errno!(ChildWait)),
+			0 => Ok(None),
+			_ => {
+				let exit = (WaitStatus::from_raw(wstatus), Rusage::from_raw(&usage));
+				self.reaped = true;
+				self.status = Some(exit);
+				Ok(Some(exit))
+			}
+		}
+	}
+
+	/// Fail if the child has been reaped and so may no longer be identified
+	/// by its PID.
+	fn ensure_running(&self) -> Result<()> {
+		if self.reaped {
+			return Err(ErrorKind::ChildWait(::errno::Errno(ESRCH)).into());
+		}
+
+		Ok(())
+	}
+
+	/// Tell the child to continue execution.
+	///
+	/// If the child has already exited, it is reaped and an error is returned
+	/// instead.
+	fn cont(&mut self, handshake: &Handshake) -> Result<()> {
+		let mut wstatus = 0;
+		match unsafe { waitpid(self.pid, &mut wstatus, WNOHANG) } {
+			-1 => return Err(errno!(ChildWait)),
+			0 => {},
+			_ => {
+				self.reaped = true;
+				return Err(ErrorKind::ChildExited(wstatus).into());
+			}
+		}
+
+		handshake.release()
+	}
+}
+
+/// How a child exited.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaitStatus {
+	/// The child exited with the given exit status.
+	Exited(i32),
+	/// The child was terminated by the given signal.
+	Signaled(i32),
+}
+
+impl WaitStatus {
+	/// Interpret a status returned by `waitpid(2)`.
+	// The wait status macros are only safe functions in later versions of libc.
+	#[allow(unused_unsafe)]
+	fn from_raw(wstatus: c_int) -> WaitStatus {
+		unsafe {
+			if WIFSIGNALED(wstatus) {
+				WaitStatus::Signaled(WTERMSIG(wstatus))
+			} else {
+				WaitStatus::Exited(WEXITSTATUS(wstatus))
+			}
+		}
+	}
+
+	/// Determine if the child exited successfully.
+	pub fn success(&self) -> bool {
+		*self == WaitStatus::Exited(0)
+	}
+}
+
+/// Resources used by a child.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rusage {
+	/// Maximum resident set size in kilobytes.
+	pub maxrss: u64,
+	/// Time spent executing in user mode.
+	pub utime: Duration,
+	/// Time spent executing in kernel mode.
+	pub stime: Duration,
+}
+
+impl Rusage {
+	fn from_raw(usage: &rusage) -> Rusage {
+		let duration = |time: &timeval| {
+			Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+		};
+
+		Rusage {
+			maxrss: usage.ru_maxrss as u64,
+			utime: duration(&usage.ru_utime),
+			stime: duration(&usage.ru_stime),
+		}
+	}
+}
+
+impl Drop for Child {
+	fn drop(&mut self) {
+		if !self.reaped {
+			unsafe { kill(self.pid, SIGKILL) };
+			let _ = self.reap();
+			self.reaped = true;
+		}
+
+		if self.cleaned {
+			return;
+		}
+
+		if let Err(err) = self.config.cleanup() {
+			match self.config.cleanup_handler {
+				Some(ref handler) => (handler.0)(err),
+				None => warn!("failed to clean up child {}: {}", self.pid, err),
+			}
+		}
+	}
+}
+
+/// A child that has been released from its context.
+///
+/// This is created by [`Child::from_raw`](struct.Child.html#method.from_raw).
+/// Unlike a [`Child`](struct.Child.html), it is not killed or waited on when
+/// dropped and the context of the child is never cleaned up.
+#[derive(Debug)]
+pub struct DetachedChild {
+	pid: pid_t,
+}
+
+impl DetachedChild {
+	/// Get the PID of the child process.
+	pub fn pid(&self) -> pid_t {
+		self.pid
+	}
+
+	/// Wait for the child to exit and reap it.
+	pub fn wait(self) -> Result<WaitStatus> {
+		let mut wstatus = 0;
+		match unsafe { waitpid(self.pid, &mut wstatus, 0) } {
+			-1 => Err(errno!(ChildWait)),
+			_ => Ok(WaitStatus::from_raw(wstatus)),
+		}
+	}
+
+	/// Check if the child has exited without blocking, reaping it if so.
+	pub fn try_wait(&self) -> Result<Option<WaitStatus>> {
+		let mut wstatus = 0;
+		match unsafe { waitpid(self.pid, &mut wstatus, WNOHANG) } {
+			-1 => Err(errno!(ChildWait)),
+			0 => Ok(None),
+			_ => Ok(Some(WaitStatus::from_raw(wstatus))),
+		}
+	}
+
+	/// Send a signal to the child.
+	pub fn signal(&self, signal: c_int) -> Result<()> {
+		if unsafe { kill(self.pid, signal) } == -1 {
+			return Err(errno!(Signal, self.pid, signal));
+		}
+
+		Ok(())
+	}
+}
+
+/// A handle used by a child to signal that it is ready.
+///
+/// This is given to the function run by
+/// [`Context::spawn_with_ready`](struct.Context.html#method.spawn_with_ready).
+/// If the child exits or executes a program without signalling, the parent
+/// is told that it will never be ready.
+#[derive(Debug)]
+pub struct Ready {
+	fd: RawFd,
+}
+
+impl Ready {
+	/// Signal the parent that the child is ready.
+	pub fn ready(self) -> Result<()> {
+		let byte = 0u8;
+		let written = unsafe { write(self.fd, &byte as *const u8 as *const c_void, 1) };
+		let err = ::errno::errno();
+		unsafe { close(self.fd) };
+
+		match written {
+			1 => Ok(()),
+			_ => Err(ErrorKind::ReadyPipe(err).into()),
+		}
+	}
+}
+
+/// Waits in the parent for a child to signal that it is ready.
+///
+/// This is returned by
+/// [`Context::spawn_with_ready`](struct.Context.html#method.spawn_with_ready).
+#[derive(Debug)]
+pub struct ReadyWaiter {
+	fd: RawFd,
+	ready: bool,
+}
+
+impl ReadyWaiter {
+	/// Wait up to `timeout` for the child to be ready.
+	///
+	/// Returns whether the child became ready in time, after which this
+	/// always returns `true`. Returns `ChildNotReady` if the child exited or
+	/// executed a program without signalling.
+	pub fn wait_ready(&mut self, timeout: Duration) -> Result<bool> {
+		if self.ready {
+			return Ok(true);
+		}
+
+		let deadline = Instant::now() + timeout;
+		loop {
+			let remaining = deadline.saturating_duration_since(Instant::now());
+			let millis = remaining.as_millis().min(c_int::MAX as u128) as c_int;
+			let mut fd = pollfd { fd: self.fd, events: POLLIN, revents: 0 };
+
+			match unsafe { poll(&mut fd, 1, millis) } {
+				-1 if ::errno::errno().0 == EINTR => continue,
+				-1 => return Err(errno!(ReadyPipe)),
+				0 if remaining == Duration::from_secs(0) => return Ok(false),
+				0 => continue,
+				_ => break,
+			}
+		}
+
+		let mut byte = 0u8;
+		loop {
+			match unsafe { read(self.fd, &mut byte as *mut u8 as *mut c_void, 1) } {
+				-1 if ::errno::errno().0 == EINTR => continue,
+				-1 => return Err(errno!(ReadyPipe)),
+				0 => return Err(ErrorKind::ChildNotReady.into()),
+				_ => break,
+			}
+		}
+
+		self.ready = true;
+		Ok(true)
+	}
+}
+
+impl Drop for ReadyWaiter {
+	fn drop(&mut self) {
+		unsafe { close(self.fd) };
+	}
+}
+
+/// The order in which the namespaces of a child are joined by
+/// [`Child::exec_in`](struct.Child.html#method.exec_in).
+///
+/// The user namespace is joined first to gain the privileges needed to join
+/// the others.
+const EXEC_IN_ORDER: [NsKind; 7] = [
+	NsKind::User,
+	NsKind::Cgroup,
+	NsKind::Ipc,
+	NsKind::Network,
+	NsKind::Pid,
+	NsKind::Uts,
+	NsKind::Mount,
+];
+
+/// Create children of the calling thread in the given PID namespace.
+///
+/// The PID namespace of new children of the calling thread is restored once
+/// `f` returns.
+fn in_pid_namespace<F, T>(namespace: &File, f: F) -> Result<T>
+where
+	F: FnOnce() -> Result<T>
+{
+	let guard = NamespaceGuard::enter(namespace, NsKind::Pid)?;
+	let result = f();
+	guard.leave()?;
+
+	result
+}
+
+/// Resolve a path that may not exist.
+///
+/// Each component that exists is resolved as by `realpath(3)`, after which
+/// the remaining components are applied without resolving them.
+fn resolve_partial(path: &Path) -> Result<PathBuf> {
+	let mut resolved = if path.is_absolute() {
+		PathBuf::from("/")
+	} else {
+		current_dir()?
+	};
+
+	for component in path.components() {
+		match component {
+			Component::RootDir => resolved = PathBuf::from("/"),
+			Component::CurDir | Component::Prefix(_) => {}
+			Component::ParentDir => {
+				resolved.pop();
+			}
+			Component::Normal(name) => {
+				let next = resolved.join(name);
+				resolved = next.canonicalize().unwrap_or(next);
+			}
+		}
+	}
+
+	Ok(resolved)
+}
+
+/// Parse a user or group ID mapping from `/proc`.
+fn read_id_map(path: &Path) -> Result<Vec<(u32, u32, u32)>> {
+	let mut text = String::new();
+	File::open(path)?.read_to_string(&mut text)?;
+
+	text.lines()
+		.map(|line| {
+			let fields: Option<Vec<u32>> = line.split_whitespace()
+				.map(|field| field.parse().ok())
+				.collect();
+			match fields.as_deref() {
+				Some(&[inside, outside, count]) => Ok((inside, outside, count)),
+				_ => Err(ErrorKind::InvalidIdMap(line.to_owned()).into()),
+			}
+		})
+		.collect()
+}
+
+/// Determine if any living process is in the PID namespace with the given
+/// device and inode number (see `namespace_key`).
+fn pid_namespace_occupied(namespace: (u64, u64)) -> Result<bool> {
+	for entry in read_dir("/proc")? {
+		let path = entry?.path().join("ns/pid");
+
+		// Processes that have exited or are not processes are ignored.
+		match namespace_key(&path) {
+			Ok(key) if key == namespace => return Ok(true),
+			_ => continue,
+		}
+	}
+
+	Ok(false)
+}
+
+/// Identify the namespace referred to by a file in `/proc/<pid>/ns`.
+///
+/// Namespaces are only identified by the device and inode number together,
+/// as the inode number alone may be reused on another device.
+fn namespace_key(path: &Path) -> Result<(u64, u64)> {
+	let ns = metadata(path)?;
+	Ok((ns.dev(), ns.ino()))
+}
+
+/// Run a function in a child with new user and mount namespaces.
+///
+/// The child is mapped to root and given a new directory named after the
+/// test, which is removed once the child has exited. The address space is
+/// shared with the child so that the function can report why it failed.
+#[cfg(test)]
+pub(crate) fn run_in_jail<F>(name: &str, f: F) -> Result<()>
+where
+	F: 'static + FnOnce(&Path) -> Result<()>
+{
+	use std::env::temp_dir;
+	use std::fs::{create_dir_all, remove_dir_all};
+	use std::process;
+	use std::sync::{Arc, Mutex};
+	use ::namespace::{EmptyMount, User};
+
+	let dir = temp_dir().join(format!("isolate-{}-{}", name, process::id()));
+	create_dir_all(&dir)?;
+
+	let outcome = Arc::new(Mutex::new(None));
+	let (jail, result) = (dir.clone(), outcome.clone());
+	let child = Context::new()
+		.with(User::new().map_root_user().map_root_group())
+		.with(EmptyMount::new())
+		.exec_shared(move || {
+			let reported = f(&jail).map_err(|err| err.to_string());
+			*result.lock().unwrap() = Some(reported);
+		})?;
+	child.wait()?;
+	remove_dir_all(&dir)?;
+
+	let reported = outcome.lock().unwrap().take();
+	match reported {
+		Some(Ok(())) => Ok(()),
+		Some(Err(message)) => Err(message.into()),
+		None => Err(format!("{} exited without reporting a result", name).into()),
+	}
+}
+
+/// Test that a child which dies before it is released is reported.
+#[test]
+fn child_exited_early() {
+	#[derive(Clone, Debug)]
+	struct KillChild;
+
+	impl Namespace for KillChild {
+		fn clone_flag(&self) -> c_int {
+			0
+		}
+
+		fn external_config(&self, child: &Child) -> Result<()> {
+			use libc::{P_PID, WEXITED, WNOWAIT, id_t, siginfo_t, waitid};
+
+			// Wait for the child to die without reaping it.
+			let mut info: siginfo_t = unsafe { mem::zeroed() };
+			unsafe {
+				kill(child.pid(), SIGKILL);
+				waitid(P_PID, child.pid() as id_t, &mut info, WEXITED | WNOWAIT);
+			}
+			Ok(())
+		}
+	}
+
+	match Context::new().with(KillChild).exec_private(|| {}) {
+		Err(Error(ErrorKind::ChildExited(_), _)) => {}
+		result => panic!("unexpected result {:?}", result),
+	}
+}
+
+/// Test the paths of files in the `/proc` directory of a child.
+#[test]
+fn proc_path() {
+	let mut child = Child::new(1234, Context::new());
+	child.reaped = true;
+
+	assert_eq!(child.proc_path("uid_map"), Path::new("/proc/1234/uid_map"));
+	assert_eq!(child.proc_path("ns/pid"), Path::new("/proc/1234/ns/pid"));
+}
+
+/// Test the clone flags of the namespaces in a context are combined.
+#[test]
+fn clone_flag_aggregation() {
+	use libc::{CLONE_NEWPID, CLONE_NEWUTS};
+	use ::namespace::EmptyMount;
+
+	let context = Context::new()
+		.with(User::new())
+		.with(Pid::new())
+		.with(EmptyMount::new())
+		.with(Mount::remount("/").unwrap())
+		.with(Uts::new())
+		.env_clear();
+	assert_eq!(
+		context.clone_flag(),
+		CLONE_NEWUSER | CLONE_NEWPID | CLONE_NEWNS | CLONE_NEWUTS
+	);
+	assert!(context.duplicate_namespaces().is_empty());
+
+	let context = context.with(Pid::new()).with(Uts::new()).with(EmptyMount::new());
+	assert_eq!(context.duplicate_namespaces(), vec!["PID", "UTS"]);
+}
+
+/// Test that a user namespace must be added before other namespaces.
+#[test]
+fn validate_user_first() {
+	assert!(Context::new().with(User::new()).with(Uts::new()).validate().is_ok());
+	match Context::new().with(Uts::new()).with(User::new()).validate() {
+		Err(Error(ErrorKind::InvalidNamespaceOrder(_), _)) => {}
+		result => panic!("unexpected result {:?}", result),
+	}
+}
+
+/// Test that the status of a child reaped by `try_wait` is kept.
+#[test]
+fn try_wait_then_drop() {
+	let mut child = Context::new().exec_private(|| {}).unwrap();
+
+	let status = loop {
+		if let Some(status) = child.try_wait().unwrap() {
+			break status;
+		}
+		sleep(Duration::from_millis(1));
+	};
+
+	assert!(status.success());
+	assert_eq!(child.try_wait().unwrap(), Some(status));
+	assert_eq!(child.wait().unwrap(), status);
+}
+
+/// Test that a reaped child is not signalled, as its PID may be reused.
+#[test]
+fn signal_after_reaping() {
+	let mut child = Context::new().exec_private(|| {}).unwrap();
+	while child.try_wait().unwrap().is_none() {
+		sleep(Duration::from_millis(1));
+	}
+
+	for result in &[child.signal(SIGTERM), child.signal_inner(1, SIGTERM)] {
+		match *result {
+			Err(Error(ErrorKind::ChildWait(::errno::Errno(ESRCH)), _)) => {}
+			ref result => panic!("unexpected result {:?}", result),
+		}
+	}
+}
+
+/// Test that an error from a pre-exec hook is returned from the parent.
+#[test]
+fn pre_exec_error() {
+	let context = Context::new()
+		.pre_exec(|| Ok(()))
+		.pre_exec(|| Err("hook failed".into()))
+		.pre_exec(|| panic!("hook run after failure"));
+
+	match context.exec_private(|| panic!("function run after failure")) {
+		Err(Error(ErrorKind::ChildSetup(ref message), _)) => assert_eq!(message, "hook failed"),
+		result => panic!("unexpected result {:?}", result),
+	}
+}
+
+/// Test that configuration in an entered namespace is added after entering it.
+#[test]
+fn validate_enter_order() {
+	use ::namespace::{Enter, NsKind};
+
+	let context = Context::new()
+		.with(Enter::pid(1, NsKind::Mount))
+		.with(Mount::remount("/").unwrap());
+	assert!(context.validate().is_ok());
+	assert_eq!(context.clone_flag() & CLONE_NEWNS, 0);
+
+	let context = Context::new()
+		.with(Mount::remount("/").unwrap())
+		.with(Enter::pid(1, NsKind::Mount));
+	match context.validate() {
+		Err(Error(ErrorKind::InvalidNamespaceOrder(_), _)) => {}
+		result => panic!("unexpected result {:?}", result),
+	}
+}
+
+/// Test that a child ignoring `SIGTERM` is killed once the grace period ends.
+#[test]
+fn terminate_escalates() {
+	use libc::{SIG_IGN, signal};
+
+	let child = Context::new()
+		.pre_exec(|| {
+			unsafe { signal(SIGTERM, SIG_IGN) };
+			Ok(())
+		})
+		.exec_private(|| sleep(Duration::from_secs(10)))
+		.unwrap();
+
+	let status = child.terminate(Duration::from_millis(100)).unwrap();
+	assert_eq!(status, WaitStatus::Signaled(SIGKILL));
+}
+
+/// Test that the status of a child run to completion is returned.
+#[test]
+fn exec_blocking_status() {
+	let status = Context::new().exec_blocking(|| unsafe { ::libc::exit(3) }).unwrap();
+	assert_eq!(status, WaitStatus::Exited(3));
+}
+
+/// Test reading back the user and group mappings of a child.
+#[test]
+fn read_id_maps() {
+	use libc::{getgid, getuid, pause};
+
+	let child = Context::new()
+		.with(User::new().map_root_user().map_root_group())
+		.exec_private(|| loop {
+			unsafe { pause() };
+		})
+		.unwrap();
+
+	let uid = unsafe { getuid() };
+	let gid = unsafe { getgid() };
+	assert_eq!(child.uid_map().unwrap(), vec![(0, uid, 1)]);
+	assert_eq!(child.gid_map().unwrap(), vec![(0, gid, 1)]);
+	drop(child);
+}
+
+/// Test that the namespaces created for a child have new identifiers.
+#[test]
+fn namespace_ids() {
+	use libc::pause;
+
+	let child = Context::new()
+		.with(User::new())
+		.with(Uts::new())
+		.exec_private(|| loop {
+			unsafe { pause() };
+		})
+		.unwrap();
+
+	let parent = |kind: NsKind| metadata(Path::new("/proc/self/ns").join(kind.name())).unwrap().ino();
+	assert_ne!(child.namespace_id(NsKind::Uts).unwrap(), parent(NsKind::Uts));
+	assert_eq!(child.namespace_id(NsKind::Ipc).unwrap(), parent(NsKind::Ipc));
+	drop(child);
+}
+
+/// Test that stacks smaller than the minimum are rejected.
+#[test]
+fn stack_too_small() {
+	match Context::new().stack_size(0) {
+		Err(Error(ErrorKind::StackTooSmall(0, _), _)) => {}
+		result => panic!("unexpected result {:?}", result.map(|_| ())),
+	}
+
+	let mut stack = [0u8; 256];
+	match unsafe { Context::new().with_stack(&mut stack) }.exec_private(|| {}) {
+		Err(Error(ErrorKind::StackTooSmall(256, _), _)) => {}
+		result => panic!("unexpected result {:?}", result.map(|_| ())),
+	}
+
+	let size = min_stack_size();
+	assert_eq!(Context::new().stack_size(size).unwrap().exec_blocking(|| {}).unwrap(), WaitStatus::Exited(0));
+}
+
+/// Test that a released child can be taken over and waited on.
+#[test]
+fn detach_and_reattach() {
+	let child = Context::new()
+		.exec_private(|| ::std::process::exit(3))
+		.unwrap();
+
+	let pid = child.into_raw();
+	let detached = Child::from_raw(pid);
+	assert_eq!(detached.pid(), pid);
+	assert_eq!(detached.wait().unwrap(), WaitStatus::Exited(3));
+}
+
+/// Test that the parent is told when the child is ready and when it never
+/// will be.
+#[test]
+fn wait_ready() {
+	let (child, mut waiter) = Context::new()
+		.spawn_with_ready(|ready| {
+			ready.ready().unwrap();
+			sleep(Duration::from_millis(100));
+		})
+		.unwrap();
+	assert!(waiter.wait_ready(Duration::from_secs(5)).unwrap());
+	assert!(waiter.wait_ready(Duration::from_secs(0)).unwrap());
+	child.wait().unwrap();
+
+	let (child, mut waiter) = Context::new()
+		.spawn_with_ready(|_| sleep(Duration::from_millis(200)))
+		.unwrap();
+	assert!(!waiter.wait_ready(Duration::from_millis(10)).unwrap());
+	match waiter.wait_ready(Duration::from_secs(5)) {
+		Err(Error(ErrorKind::ChildNotReady, _)) => {}
+		result => panic!("unexpected result {:?}", result),
+	}
+	child.wait().unwrap();
+}
+
+/// Test that mounts made by a child with a private root propagation don't
+/// propagate back to the mount namespace of the caller.
+#[test]
+fn root_propagation_isolates() {
+	use std::fs::create_dir;
+
+	// The caller is itself a child with a shared mount so that the test
+	// doesn't depend on the propagation of the mounts of the host.
+	run_in_jail("root-propagation", |dir| {
+		Mount::new("tmpfs", dir, "tmpfs")?.mount()?;
+		Mount::shared(dir)?.mount()?;
+
+		let (leaky, isolated) = (dir.join("leaky"), dir.join("isolated"));
+		create_dir(&leaky)?;
+		create_dir(&isolated)?;
+		Context::new()
+			.with(EmptyMount::new())
+			.with(Mount::new("tmpfs", &leaky, "tmpfs")?)
+			.exec_blocking(|| {})?;
+		Context::new()
+			.with(EmptyMount::new())
+			.root_propagation(Propagation::Private)?
+			.with(Mount::new("tmpfs", &isolated, "tmpfs")?)
+			.exec_blocking(|| {})?;
+
+		let (leaky, isolated) = (leaky.canonicalize()?, isolated.canonicalize()?);
+		let mounts = ::namespace::mountinfo()?;
+		ensure!(mounts.iter().any(|info| info.mount_point == leaky), "{:?} did not propagate", leaky);
+		ensure!(!mounts.iter().any(|info| info.mount_point == isolated), "{:?} propagated", isolated);
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that raw prctl operations are applied to the child and that
+/// failures are reported.
+#[test]
+fn raw_prctl() {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use libc::{PR_GET_TIMERSLACK, PR_SET_TIMERSLACK, prctl};
+
+	static SLACK: AtomicUsize = AtomicUsize::new(0);
+
+	let child = Context::new()
+		.unisolated()
+		.prctl(PR_SET_TIMERSLACK, 12345, 0, 0, 0)
+		.exec_shared(|| {
+			let slack = unsafe { prctl(PR_GET_TIMERSLACK, 0, 0, 0, 0) };
+			SLACK.store(slack as usize, Ordering::SeqCst);
+		})
+		.unwrap();
+	child.wait().unwrap();
+	assert_eq!(SLACK.load(Ordering::SeqCst), 12345);
+
+	let result = Context::new().prctl(-1, 0, 0, 0, 0).exec_private(|| {});
+	assert!(result.is_err());
+}
+
+/// Test that mounts with targets outside a confined root are rejected.
+#[test]
+fn confine_mounts_to_root() {
+	use std::fs::create_dir;
+
+	run_in_jail("confine", |dir| {
+		let root = dir.join("root");
+		create_dir(&root)?;
+		let jail = |mount: Mount| {
+			Context::new()
+				.with(EmptyMount::new())
+				.root(&root)
+				.confine_mounts()
+				.with(mount)
+				.validate()
+		};
+
+		jail(Mount::relative("/tmp", "new/lib")?)?;
+		let escapes = [
+			Mount::relative("/tmp", "../escape")?,
+			Mount::relative("/tmp", "new/../../escape")?,
+			Mount::new("tmpfs", "/etc", "tmpfs")?,
+		];
+		for mount in escapes {
+			match jail(mount) {
+				Err(Error(ErrorKind::MountEscapesRoot(..), _)) => {}
+				result => bail!("unexpected result {:?}", result),
+			}
+		}
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that a forked child creates its own namespaces before they are
+/// configured.
+#[test]
+fn fork_unshare() {
+	use libc::getuid;
+
+	let context = Context::new()
+		.with(User::new().map_root_user())
+		.with(Uts::new().hostname("forked"))
+		.spawn_mode(SpawnMode::ForkUnshare);
+
+	let status = context
+		.exec_blocking(|| {
+			let mut hostname = String::new();
+			File::open("/proc/sys/kernel/hostname").unwrap().read_to_string(&mut hostname).unwrap();
+			let configured = hostname.trim() == "forked" && unsafe { getuid() } == 0;
+			unsafe { _exit(!configured as c_int) };
+		})
+		.unwrap();
+	assert_eq!(status, WaitStatus::Exited(0));
+
+	assert!(context.exec_shared(|| {}).is_err());
+}
+
+/// Test that a child is killed and the external configuration of its context
+/// is cleaned up when the caller panics while it is running.
+#[test]
+fn panic_while_jailed() {
+	use std::cell::Cell;
+	use std::panic::{AssertUnwindSafe, catch_unwind};
+	use libc::pause;
+	use ::namespace::Network;
+
+	let (pid, staging) = (Cell::new(0), RefCell::new(None));
+	let panicked = catch_unwind(AssertUnwindSafe(|| {
+		let child = Context::new()
+			.with(User::new().map_root_user().map_root_group())
+			.with(EmptyMount::new())
+			.with(Network::new().resolv_conf(&["10.0.0.1".parse().unwrap()]))
+			.exec_private(|| loop {
+				unsafe { pause() };
+			})
+			.unwrap();
+		pid.set(child.pid());
+		*staging.borrow_mut() = child.config.namespaces()
+			.filter_map(|ns| ns.as_any().downcast_ref::<Network>())
+			.next()
+			.and_then(Network::staging);
+		panic!("caller panicked while the child was running");
+	}));
+	assert!(panicked.is_err());
+
+	assert_eq!(unsafe { kill(pid.get(), 0) }, -1);
+	assert_eq!(::errno::errno().0, ESRCH);
+	let staging = staging.into_inner().expect("no files were staged for the child");
+	assert!(!staging.exists(), "{:?} was not removed", staging);
+}
+
+/// Test that switching user in a user namespace that denies `setgroups(2)`
+/// leaves the supplementary groups as they are rather than failing.
+#[test]
+fn switch_to_setgroups_denied() {
+	let status = Context::new()
+		.with(User::new().map_root_user().map_root_group())
+		.switch_to(0, 0)
+		.exec_blocking(|| {})
+		.unwrap();
+	assert!(status.success());
+}
+
+/// Test that the root can be sealed by a child that is only root in its own
+/// user namespace, where the flags of the root are locked.
+#[test]
+fn seal_root_unprivileged() {
+	use std::fs::{create_dir, write};
+	use ::namespace::Chroot;
+
+	run_in_jail("seal", |dir| {
+		// Mounts copied into a less privileged user namespace have their
+		// flags locked.
+		Mount::bind(dir, dir)?.mount()?;
+		Mount::remount(dir)?.as_bind().no_setuid().no_devices().mount()?;
+		create_dir(dir.join("proc"))?;
+		Mount::bind("/proc", dir.join("proc"))?.mount()?;
+
+		let status = Context::new()
+			.with(User::new().map_root_user().map_root_group())
+			.with(EmptyMount::new())
+			.with(Chroot::new(dir.to_str().unwrap())?)
+			.seal_root()?
+			.exec_blocking(|| {
+				let sealed = write("/sealed", b"").is_err();
+				unsafe { ::libc::exit(if sealed { 0 } else { 1 }) }
+			})?;
+		ensure!(status == WaitStatus::Exited(0), "root was not sealed: {:?}", status);
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that the sandbox preset can be used without privileges, including
+/// when the flags of the root are locked.
+#[test]
+fn sandbox_unprivileged() {
+	use std::fs::write;
+
+	run_in_jail("sandbox", |_| {
+		Mount::remount("/")?.as_bind().no_setuid().no_devices().mount()?;
+
+		let status = Context::sandbox()?.exec_blocking(|| {
+			let sealed = write("/sealed", b"").is_err();
+			unsafe { ::libc::exit(if sealed { 0 } else { 1 }) }
+		})?;
+		ensure!(status == WaitStatus::Exited(0), "root was not sealed: {:?}", status);
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that a root file-system is only accepted in a context with a root.
+#[test]
+fn root_fs_requires_root() {
+	let root_fs = RootFs::new("/").writable("tmp", 1 << 20).unwrap();
+	assert!(RootFs::new("/").writable("tmp", 0).is_err());
+
+	let context = Context::new().with(root_fs);
+	match context.validate() {
+		Err(Error(ErrorKind::InvalidNamespaceOrder(_), _)) => {}
+		result => panic!("unexpected result {:?}", result),
+	}
+	assert!(context.root("/tmp").validate().is_ok());
+}
+
+/// Test that allowing `setgroups(2)` without mapping the root group, where it
+/// could never be called, is rejected.
+#[test]
+fn setgroups_requires_group_map() {
+	let allowed = User::new().map_root_user().allow_setgroups();
+	match Context::new().with(allowed.clone()).validate() {
+		Err(Error(ErrorKind::InvalidNamespaceOrder(_), _)) => {}
+		result => panic!("unexpected result {:?}", result),
 	}
+	assert!(Context::new().with(allowed.map_root_group()).validate().is_ok());
 }
@@ -1,4 +1,6 @@
 #![allow(missing_docs)]
+// The error types generated by `error_chain` implement deprecated methods.
+#![allow(deprecated)]
 
 //! Errors generated by isolate.
 error_chain!{
@@ -21,10 +23,22 @@ error_chain!{
 			display("StackAllocation({})", err)
 		}
 
+		// The stack requested for a child is too small to run it.
+		StackTooSmall(size: ::libc::size_t, min: ::libc::size_t) {
+			description("Stack is too small")
+			display("StackTooSmall({} bytes, at least {})", size, min)
+		}
+
 		// A clone failed.
-		Clone(err: ::errno::Errno) {
+		Clone(err: ::errno::Errno, flags: ::namespace::CloneFlags) {
 			description("Could not create thread clone")
-			display("Clone({})", err)
+			display("Clone({}, flags={})", err, flags)
+		}
+
+		// Clone flags were given that would break the creation of the child.
+		UnsupportedCloneFlags(flags: ::namespace::CloneFlags) {
+			description("Clone flags are not supported")
+			display("UnsupportedCloneFlags({})", flags)
 		}
 
 		// Failed to wait on a child.
@@ -39,11 +53,389 @@ error_chain!{
 			display("ChildContinue({})", err)
 		}
 
+		// Failed to create the pipe used to hold the child during config.
+		Handshake(err: ::errno::Errno) {
+			description("Could not create pipe to synchronise with child")
+			display("Handshake({})", err)
+		}
+
+		// Failed to create the pipe used to capture the standard error of the child.
+		CaptureStderr(err: ::errno::Errno) {
+			description("Could not create pipe to capture standard error of child")
+			display("CaptureStderr({})", err)
+		}
+
+		// Failed to create or use the pipe used by a child to signal that it is ready.
+		ReadyPipe(err: ::errno::Errno) {
+			description("Could not signal readiness of child")
+			display("ReadyPipe({})", err)
+		}
+
+		// The child exited or executed a program without signalling that it was ready.
+		ChildNotReady {
+			description("Child exited without becoming ready")
+			display("ChildNotReady")
+		}
+
+		// The child exited before it could be configured.
+		ChildExited(wstatus: ::libc::c_int) {
+			description("Child exited before it could be configured")
+			display("ChildExited({:#x})", wstatus)
+		}
+
+		// Failed to send a signal to a process.
+		Signal(err: ::errno::Errno, pid: ::libc::pid_t, signal: ::libc::c_int) {
+			description("Could not send signal to process")
+			display("Signal({}, pid={}, signal={})", err, pid, signal)
+		}
+
+		// A PID within the PID namespace of a child did not match any process.
+		NoSuchInnerPid(pid: ::libc::pid_t) {
+			description("No process with the PID in the PID namespace of the child")
+			display("NoSuchInnerPid({})", pid)
+		}
+
+		// The child failed to configure itself.
+		ChildSetup(message: String) {
+			description("Child failed to configure itself")
+			display("ChildSetup({})", message)
+		}
+
+		// A namespace name did not match any kind of namespace.
+		UnknownNamespace(name: String) {
+			description("Unknown namespace name")
+			display("UnknownNamespace({:?})", name)
+		}
+
+		// A kind of namespace can't be created by isolate.
+		UnsupportedNamespace(name: String) {
+			description("Namespace is not supported by isolate")
+			display("UnsupportedNamespace({:?})", name)
+		}
+
 		// Failed to perform a mount.
 		Mount(err: ::errno::Errno, mount: ::namespace::Mount) {
 			description("Could not perform mount")
 			display("Mount({}, {:?})", err, mount)
 		}
+
+		// A file-system specific mount option is not valid.
+		InvalidMountData(option: String) {
+			description("Invalid mount option")
+			display("InvalidMountData({:?})", option)
+		}
+
+		// A file-system specific mount option is not supported by the kernel.
+		UnsupportedMountData(data: String) {
+			description("Mount option is not supported by the kernel")
+			display("UnsupportedMountData({:?})", data)
+		}
+
+		// The file-system specific mount options are longer than the kernel accepts.
+		MountDataTooLong(length: usize, max: usize, hint: String) {
+			description("Mount options are too long")
+			display("MountDataTooLong({} bytes, at most {}){}", length, max, hint)
+		}
+
+		// A mount was not made as specified.
+		MountVerificationFailed(target: ::std::path::PathBuf, reason: String) {
+			description("Mount was not made as specified")
+			display("MountVerificationFailed({:?}, {})", target, reason)
+		}
+
+		// The target of a mount resolves outside the root of the context.
+		MountEscapesRoot(target: ::std::path::PathBuf, root: ::std::path::PathBuf) {
+			description("Mount target is outside the root")
+			display("MountEscapesRoot({:?}, root={:?})", target, root)
+		}
+
+		// Failed to set the owner of the target of a mount.
+		TargetOwner(err: ::errno::Errno, target: ::std::path::PathBuf, uid: ::libc::uid_t, gid: ::libc::gid_t) {
+			description("Could not set owner of mount target")
+			display("TargetOwner({}, {:?}, {}:{})", err, target, uid, gid)
+		}
+
+		// Failed to resolve the target of a mount without following symbolic links.
+		ResolveTarget(err: ::errno::Errno, target: ::std::ffi::CString) {
+			description("Could not resolve mount target without following symbolic links")
+			display("ResolveTarget({}, {:?})", err, target)
+		}
+
+		// Failed to create a namespace from within the child.
+		Unshare(err: ::errno::Errno, flags: ::namespace::CloneFlags) {
+			description("Could not create namespace")
+			display("Unshare({}, flags={})", err, flags)
+		}
+
+		// The /proc directory of the child could not be found.
+		ProcNotMounted(path: ::std::path::PathBuf) {
+			description("/proc is not mounted in the mount namespace of the parent")
+			display("ProcNotMounted({}): is /proc mounted?", path.display())
+		}
+
+		// Failed to join an existing namespace.
+		Setns(err: ::errno::Errno, flags: ::namespace::CloneFlags) {
+			description("Could not join namespace")
+			display("Setns({}, flags={})", err, flags)
+		}
+
+		// Failed to fork into a joined PID namespace.
+		EnterPid(err: ::errno::Errno) {
+			description("Could not fork into joined PID namespace")
+			display("EnterPid({})", err)
+		}
+
+		// A named network namespace does not exist.
+		NoSuchNetworkNamespace(name: String) {
+			description("No such network namespace")
+			display("NoSuchNetworkNamespace({})", name)
+		}
+
+		// No loop device was free to attach an image to.
+		NoFreeLoopDevice {
+			description("No free loop device")
+			display("NoFreeLoopDevice")
+		}
+
+		// Failed to configure the loop device for an image.
+		LoopDevice(err: ::errno::Errno, image: ::std::ffi::CString) {
+			description("Could not configure loop device")
+			display("LoopDevice({}, {:?})", err, image)
+		}
+
+		// A kernel parameter does not exist.
+		NoSuchSysctl(key: String) {
+			description("No such kernel parameter")
+			display("NoSuchSysctl({})", key)
+		}
+
+		// A kernel parameter can't be written.
+		SysctlNotWritable(key: String) {
+			description("Kernel parameter is not writable")
+			display("SysctlNotWritable({})", key)
+		}
+
+		// A path to bind as a device is not a device node.
+		NotADevice(path: ::std::path::PathBuf) {
+			description("Path is not a character or block device")
+			display("NotADevice({:?})", path)
+		}
+
+		// A device could not be found for a mount.
+		NoSuchDevice(path: String) {
+			description("No such device")
+			display("NoSuchDevice({})", path)
+		}
+
+		// The namespaces of a context were added in an invalid order.
+		InvalidNamespaceOrder(reason: String) {
+			description("Invalid namespace order")
+			display("InvalidNamespaceOrder({})", reason)
+		}
+
+		// A context spec does not describe a valid context.
+		InvalidSpec(reason: String) {
+			description("Invalid context spec")
+			display("InvalidSpec({})", reason)
+		}
+
+		// Failed to set the hostname.
+		Hostname(err: ::errno::Errno) {
+			description("Could not set hostname")
+			display("Hostname({})", err)
+		}
+
+		// Failed to set the domain name.
+		Domainname(err: ::errno::Errno) {
+			description("Could not set domain name")
+			display("Domainname({})", err)
+		}
+
+		// Failed to set the supplementary groups.
+		SetGroups(err: ::errno::Errno) {
+			description("Could not set supplementary groups")
+			display("SetGroups({})", err)
+		}
+
+		// The user namespace does not permit setting supplementary groups.
+		SetGroupsDenied {
+			description("Setting supplementary groups is denied in this user namespace")
+			display("SetGroupsDenied")
+		}
+
+		// Failed to change the root directory.
+		Chroot(err: ::errno::Errno, path: ::std::ffi::CString) {
+			description("Could not change root directory")
+			display("Chroot({}, {:?})", err, path)
+		}
+
+		// Failed to pass a file descriptor to the child.
+		PreserveFd(err: ::errno::Errno, src: ::std::os::unix::io::RawFd, dest: ::std::os::unix::io::RawFd) {
+			description("Could not pass file descriptor to child")
+			display("PreserveFd({}, {} -> {})", err, src, dest)
+		}
+
+		// Failed to set the CPU affinity of the child.
+		CpuAffinity(err: ::errno::Errno) {
+			description("Could not set CPU affinity")
+			display("CpuAffinity({})", err)
+		}
+
+		// A CPU that doesn't exist was requested.
+		InvalidCpu(cpu: usize) {
+			description("No such CPU")
+			display("InvalidCpu({})", cpu)
+		}
+
+		// A network prefix is longer than the address it is given for.
+		InvalidPrefix(ip: ::std::net::IpAddr, prefix: u8) {
+			description("Network prefix is longer than the address")
+			display("InvalidPrefix({}/{})", ip, prefix)
+		}
+
+		// A network interface does not exist.
+		NoSuchInterface(name: String) {
+			description("No such network interface")
+			display("NoSuchInterface({:?})", name)
+		}
+
+		// Failed to bring an interface up.
+		InterfaceUp(err: ::errno::Errno, interface: String) {
+			description("Could not bring interface up")
+			display("InterfaceUp({}, {})", err, interface)
+		}
+
+		// Failed to assign an address to an interface.
+		Address(err: ::errno::Errno, addr: ::std::net::IpAddr) {
+			description("Could not assign address to interface")
+			display("Address({}, {})", err, addr)
+		}
+
+		// Failed to open a socket.
+		Socket(err: ::errno::Errno) {
+			description("Could not open socket")
+			display("Socket({})", err)
+		}
+
+		// An OOM score adjustment was outside of the valid range.
+		OomScoreAdjRange(adj: i32) {
+			description("OOM score adjustment must be between -1000 and 1000")
+			display("OomScoreAdjRange({})", adj)
+		}
+
+		// The user to switch to is not mapped in the user namespace.
+		UnmappedUid(uid: ::libc::uid_t) {
+			description("User is not mapped in the user namespace")
+			display("UnmappedUid({})", uid)
+		}
+
+		// The group to switch to is not mapped in the user namespace.
+		UnmappedGid(gid: ::libc::gid_t) {
+			description("Group is not mapped in the user namespace")
+			display("UnmappedGid({})", gid)
+		}
+
+		// A line of a user or group ID mapping could not be parsed.
+		InvalidIdMap(line: String) {
+			description("Invalid user or group ID mapping")
+			display("InvalidIdMap({:?})", line)
+		}
+
+		// Failed to change the user of the child.
+		SetUid(err: ::errno::Errno, uid: ::libc::uid_t) {
+			description("Could not set user")
+			display("SetUid({}, {})", err, uid)
+		}
+
+		// Failed to change the effective user of the parent.
+		SetEuid(err: ::errno::Errno, uid: ::libc::uid_t) {
+			description("Could not set effective user")
+			display("SetEuid({}, {})", err, uid)
+		}
+
+		// Failed to change the group of the child.
+		SetGid(err: ::errno::Errno, gid: ::libc::gid_t) {
+			description("Could not set group")
+			display("SetGid({}, {})", err, gid)
+		}
+
+		// Not permitted to set the login user ID of the child.
+		LoginUidDenied(uid: ::libc::uid_t) {
+			description("Not permitted to set login user ID")
+			display(
+				"LoginUidDenied({}): setting the login user ID requires CAP_AUDIT_CONTROL unless it is unset and immutable login IDs are disabled",
+				uid
+			)
+		}
+
+		// Failed to limit the number of processes of the child.
+		MaxPids(err: ::errno::Errno, pids: u64) {
+			description("Could not set process limit")
+			display("MaxPids({}, {})", err, pids)
+		}
+
+		// A prctl applied to the child failed.
+		Prctl(err: ::errno::Errno, option: ::libc::c_int) {
+			description("Could not apply prctl to child")
+			display("Prctl({}, option={})", err, option)
+		}
+
+		// Failed to reset the signal handlers or mask of the child.
+		ResetSignals(err: ::errno::Errno) {
+			description("Could not reset signals")
+			display("ResetSignals({})", err)
+		}
+
+		// Failed to get or set the capabilities of the child.
+		SetCapabilities(err: ::errno::Errno) {
+			description("Could not set capabilities")
+			display("SetCapabilities({})", err)
+		}
+
+		// Failed to drop a capability from the bounding set of the child.
+		DropCapability(err: ::errno::Errno, cap: ::Capability) {
+			description("Could not drop capability from bounding set")
+			display("DropCapability({}, {})", err, cap)
+		}
+
+		// Failed to raise a capability in the ambient set of the child.
+		AmbientCapability(err: ::errno::Errno, cap: ::Capability) {
+			description("Could not raise ambient capability")
+			display("AmbientCapability({}, {})", err, cap)
+		}
+
+		// A capability to keep is not permitted for the child.
+		CapabilityNotPermitted(cap: ::Capability) {
+			description("Capability to keep is not permitted")
+			display("CapabilityNotPermitted({})", cap)
+		}
+
+		// A scheduling priority was outside of the range of its policy.
+		SchedPriorityRange(policy: ::Policy, priority: i32) {
+			description("Scheduling priority is not supported by the policy")
+			display("SchedPriorityRange({:?}, {})", policy, priority)
+		}
+
+		// Failed to set the scheduling policy of the child.
+		SchedPolicy(err: ::errno::Errno, policy: ::Policy) {
+			description("Could not set scheduling policy")
+			display("SchedPolicy({}, {:?})", err, policy)
+		}
+
+		// Not permitted to set the scheduling policy of the child.
+		SchedPolicyDenied(policy: ::Policy) {
+			description("Not permitted to set scheduling policy")
+			display(
+				"SchedPolicyDenied({:?}): real-time policies require CAP_SYS_NICE or a sufficient RLIMIT_RTPRIO",
+				policy
+			)
+		}
+
+		// An environment variable name could not be set.
+		InvalidEnvVar(name: String) {
+			description("Environment variable names must be non-empty and not contain '=' or NUL")
+			display("InvalidEnvVar({:?})", name)
+		}
     }
 }
 
@@ -0,0 +1,197 @@
+//! Waiting on children from asynchronous code.
+
+use std::fs::File;
+use std::future::Future;
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::thread;
+
+use async_io::Async;
+use libc::{
+	EINTR,
+	ENOSYS,
+	P_PID,
+	WEXITED,
+	WNOWAIT,
+	c_int,
+	id_t,
+	pid_t,
+	siginfo_t,
+	syscall,
+	waitid,
+};
+
+use ::context::{Child, WaitStatus};
+use ::error::*;
+use ::host::SYS_PIDFD_OPEN;
+
+impl Child {
+	/// Wait for the child to exit without blocking the calling task.
+	///
+	/// This requires the `async` feature. The returned future can be awaited
+	/// from any asynchronous runtime. Once polled, a process file descriptor
+	/// for the child (see `pidfd_open(2)`) is registered with the reactor of
+	/// `async-io`, which wakes the task once the child exits. On kernels that
+	/// don't support them, a thread is started instead that waits for the
+	/// child to exit with `waitid(2)`. The child is only reaped by the
+	/// future, so the status isn't taken from any other waiter.
+	///
+	/// As when a `Child` is dropped, dropping the future before the child
+	/// exits kills the child so that its context can be cleaned up. If the
+	/// child was already reaped by [`try_wait`](#method.try_wait), its status
+	/// is returned.
+	///
+	/// ```rust
+	/// let child = Context::new().with(Pid::new()).exec_private(|| pause())?;
+	/// let status = child.wait_async().await?;
+	/// ```
+	pub fn wait_async(self) -> impl Future<Output = Result<WaitStatus>> {
+		ExitFuture {
+			child: Some(self),
+			watch: None,
+		}
+	}
+}
+
+/// The waker of the task waiting on a child, shared with the thread that
+/// wakes it.
+type SharedWaker = Arc<Mutex<Option<Waker>>>;
+
+/// How the exit of a child is watched for.
+enum Watch {
+	/// A process file descriptor registered with the reactor.
+	Pidfd(Async<File>),
+	/// A thread that wakes the task once the child exits.
+	Thread(SharedWaker),
+}
+
+/// A future that resolves once a child has exited.
+struct ExitFuture {
+	child: Option<Child>,
+	watch: Option<Watch>,
+}
+
+impl Future for ExitFuture {
+	type Output = Result<WaitStatus>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Result<WaitStatus>> {
+		let future = self.get_mut();
+		let pid = match future.child {
+			Some(ref child) if child.reaped() => {
+				let child = future.child.take().unwrap();
+				return Poll::Ready(child.wait());
+			}
+			Some(ref child) => child.pid(),
+			None => panic!("child exit polled after it completed"),
+		};
+
+		if future.watch.is_none() {
+			match watch(pid, cx.waker()) {
+				Ok(watch) => future.watch = Some(watch),
+				Err(err) => return Poll::Ready(Err(err)),
+			}
+		}
+
+		loop {
+			// The task is registered to be woken before checking on the
+			// child so that an exit in between always wakes the latest task.
+			let exited = match future.watch {
+				Some(Watch::Pidfd(ref pidfd)) => match pidfd.poll_readable(cx) {
+					Poll::Ready(Ok(())) => true,
+					Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+					Poll::Pending => false,
+				},
+				Some(Watch::Thread(ref waker)) => {
+					*waker.lock().unwrap() = Some(cx.waker().clone());
+					false
+				}
+				None => unreachable!(),
+			};
+
+			match future.child.as_mut().map(Child::try_wait) {
+				Some(Ok(None)) if exited => continue,
+				Some(Ok(None)) => return Poll::Pending,
+				Some(Ok(Some(status))) => {
+					// Dropping the reaped child cleans up its context.
+					future.child = None;
+					return Poll::Ready(Ok(status));
+				}
+				Some(Err(err)) => return Poll::Ready(Err(err)),
+				None => unreachable!(),
+			}
+		}
+	}
+}
+
+/// Watch for the child to exit, registering a process file descriptor with
+/// the reactor or starting a thread that wakes the task if process file
+/// descriptors aren't supported.
+fn watch(pid: pid_t, waker: &Waker) -> Result<Watch> {
+	match unsafe { syscall(SYS_PIDFD_OPEN, pid, 0) } {
+		-1 if ::errno::errno().0 == ENOSYS => {},
+		-1 => return Err(errno!(ChildWait)),
+		fd => {
+			let pidfd = unsafe { File::from_raw_fd(fd as c_int) };
+			return Ok(Watch::Pidfd(Async::new(pidfd)?));
+		}
+	}
+
+	let waker: SharedWaker = Arc::new(Mutex::new(Some(waker.clone())));
+	let thread_waker = waker.clone();
+	thread::Builder::new()
+		.name(format!("isolate-wait-{}", pid))
+		.spawn(move || {
+			wait_exited(pid);
+			if let Some(waker) = thread_waker.lock().unwrap().take() {
+				waker.wake();
+			}
+		})?;
+
+	Ok(Watch::Thread(waker))
+}
+
+/// Block until a child has exited without reaping it.
+fn wait_exited(pid: pid_t) {
+	let mut info: siginfo_t = unsafe { mem::zeroed() };
+	while unsafe { waitid(P_PID, pid as id_t, &mut info, WEXITED | WNOWAIT) } == -1
+		&& ::errno::errno().0 == EINTR {}
+}
+
+/// Test that a child can be waited on from a simple executor.
+#[test]
+fn wait_async() {
+	use std::task::Wake;
+	use std::thread::Thread;
+	use std::time::Duration;
+	use ::Context;
+
+	struct Unpark(Thread);
+
+	impl Wake for Unpark {
+		fn wake(self: Arc<Self>) {
+			self.0.unpark();
+		}
+	}
+
+	let child = Context::new()
+		.exec_private(|| {
+			thread::sleep(Duration::from_millis(100));
+			::std::process::exit(4);
+		})
+		.unwrap();
+
+	let waker = Waker::from(Arc::new(Unpark(thread::current())));
+	let mut cx = TaskContext::from_waker(&waker);
+	let mut future = Box::pin(child.wait_async());
+	let status = loop {
+		match future.as_mut().poll(&mut cx) {
+			Poll::Ready(status) => break status.unwrap(),
+			Poll::Pending => thread::park(),
+		}
+	};
+
+	assert_eq!(status, WaitStatus::Exited(4));
+}
@@ -0,0 +1,141 @@
+//! Detection of the isolation features supported by the host.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+
+use libc::{
+	EINVAL,
+	ENOSYS,
+	c_int,
+	c_long,
+	close,
+	getpid,
+	prctl,
+	statfs,
+	syscall,
+};
+
+use ::namespace::{NsKind, supported};
+
+/// Get the seccomp mode of the calling thread (see `prctl(2)`).
+const PR_GET_SECCOMP: c_int = 21;
+
+/// The `pidfd_open(2)` system call (Linux 5.3), which has the same number on
+/// every architecture.
+pub(crate) const SYS_PIDFD_OPEN: c_long = 434;
+
+/// File-system magic numbers (see `statfs(2)`).
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+const CGROUP2_SUPER_MAGIC: i64 = 0x6367_7270;
+
+/// The isolation features supported by the host.
+///
+/// This is a snapshot taken by [`host_capabilities`](fn.host_capabilities.html)
+/// and is not updated if the configuration of the host changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostCapabilities {
+	/// The kinds of namespace that can be created (see
+	/// [`supported`](namespace/fn.supported.html)).
+	pub namespaces: Vec<NsKind>,
+	/// Whether unprivileged users may create user namespaces.
+	pub unprivileged_user_namespaces: bool,
+	/// The control group hierarchies mounted at `/sys/fs/cgroup`.
+	pub cgroup: CgroupVersion,
+	/// Whether the kernel supports seccomp filtering.
+	pub seccomp: bool,
+	/// Whether the kernel supports process file descriptors
+	/// (`pidfd_open(2)`).
+	pub pidfd: bool,
+}
+
+impl HostCapabilities {
+	/// Determine whether namespaces of the given kind can be created.
+	pub fn supports(&self, kind: NsKind) -> bool {
+		self.namespaces.contains(&kind)
+	}
+}
+
+/// The version of the control group hierarchy provided by the host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgroupVersion {
+	/// No control group hierarchy is mounted at `/sys/fs/cgroup`.
+	None,
+	/// Only `cgroup` (v1) hierarchies are mounted.
+	V1,
+	/// `cgroup` (v1) hierarchies are mounted with a `cgroup2` hierarchy at
+	/// `/sys/fs/cgroup/unified`.
+	Hybrid,
+	/// A single `cgroup2` hierarchy is mounted.
+	V2,
+}
+
+/// Probe the isolation features supported by the host.
+///
+/// Each feature is probed when this is called by reading from `/proc` and
+/// `/sys` and by making harmless system calls. Whether a feature is supported
+/// does not mean that the caller has the privileges needed to use it.
+///
+/// ```rust
+/// let host = host_capabilities();
+/// let mut context = Context::new();
+/// if host.unprivileged_user_namespaces {
+///     context = context.with(User::new().map_root_user());
+/// }
+/// ```
+pub fn host_capabilities() -> HostCapabilities {
+	let namespaces: Vec<NsKind> = NsKind::ALL.iter().cloned().filter(|&kind| supported(kind)).collect();
+	let unprivileged_user_namespaces = namespaces.contains(&NsKind::User)
+		&& read_sysctl("kernel/unprivileged_userns_clone") != Some("0".to_owned());
+
+	HostCapabilities {
+		namespaces,
+		unprivileged_user_namespaces,
+		cgroup: cgroup_version(),
+		seccomp: unsafe { prctl(PR_GET_SECCOMP, 0, 0, 0, 0) } != -1 || ::errno::errno().0 != EINVAL,
+		pidfd: pidfd_supported(),
+	}
+}
+
+/// Read a kernel parameter, if it exists.
+fn read_sysctl(key: &str) -> Option<String> {
+	let mut value = String::new();
+	File::open(format!("/proc/sys/{}", key))
+		.and_then(|mut file| file.read_to_string(&mut value))
+		.ok()
+		.map(|_| value.trim().to_owned())
+}
+
+/// Determine the version of the control group hierarchy.
+pub(crate) fn cgroup_version() -> CgroupVersion {
+	match fs_type("/sys/fs/cgroup") {
+		Some(CGROUP2_SUPER_MAGIC) => CgroupVersion::V2,
+		Some(TMPFS_MAGIC) => match fs_type("/sys/fs/cgroup/unified") {
+			Some(CGROUP2_SUPER_MAGIC) => CgroupVersion::Hybrid,
+			_ => CgroupVersion::V1,
+		},
+		_ => CgroupVersion::None,
+	}
+}
+
+/// Get the magic number of the file-system at `path`.
+fn fs_type(path: &str) -> Option<i64> {
+	let path = CString::new(path).ok()?;
+	let mut stat: statfs = unsafe { mem::zeroed() };
+	match unsafe { statfs(path.as_ptr(), &mut stat) } {
+		-1 => None,
+		_ => Some(stat.f_type as i64),
+	}
+}
+
+/// Determine whether `pidfd_open(2)` is supported.
+fn pidfd_supported() -> bool {
+	let fd = unsafe { syscall(SYS_PIDFD_OPEN, getpid(), 0) };
+	if fd == -1 {
+		return ::errno::errno().0 != ENOSYS;
+	}
+
+	unsafe { close(fd as c_int) };
+	true
+}
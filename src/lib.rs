@@ -1,20 +1,94 @@
 //! Interface for isolation.
+//!
+//! # Logging
+//!
+//! When built with the `log` feature, the clone flags, child PID, mounts, and
+//! user mappings used to set up each child are logged at the `debug` level
+//! using the [`log`](https://docs.rs/log) crate. Errors cleaning up after a
+//! dropped child are logged at the `warn` level.
+//!
+//! # Context specs
+//!
+//! A context can be described declaratively with a
+//! [`ContextSpec`](struct.ContextSpec.html). The `spec` feature allows specs
+//! to be deserialized with [`serde`](https://docs.rs/serde).
+//!
+//! # Command line tool
+//!
+//! The `isolate` command line tool is built with the default `cli` feature.
+//! Disable default features to use the library without its dependencies.
+//!
+//! # Asynchronous waiting
+//!
+//! With the `async` feature, [`Child::wait_async`](struct.Child.html#method.wait_async)
+//! waits for a child to exit as a future that can be awaited from any
+//! asynchronous runtime. The exit of the child is watched for with the reactor
+//! of [`async-io`](https://docs.rs/async-io), which runs alongside any runtime.
+//!
+//! # Platform support
+//!
+//! Isolation is built on Linux namespaces, so the crate only supports Linux.
+//! Building it for any other target fails with a single error saying so.
+//! Crates that only use isolate on Linux should depend on it with a
+//! `[target.'cfg(target_os = "linux")'.dependencies]` section.
 
 #![warn(missing_docs)]
 #![deny(unused_must_use)]
 #![warn(missing_debug_implementations)]
+#![recursion_limit = "512"]
 
+#[cfg(feature = "async")]
+extern crate async_io;
 #[macro_use]
 extern crate error_chain;
 extern crate errno;
 extern crate libc;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "spec")]
+extern crate serde;
+#[cfg(feature = "spec")]
+#[macro_use]
+extern crate serde_derive;
 
+#[cfg(not(target_os = "linux"))]
+compile_error!("isolate is built on Linux namespaces and only supports Linux");
+
+#[cfg(target_os = "linux")]
 #[macro_use]
 mod error;
+#[cfg(target_os = "linux")]
+#[macro_use]
+mod logging;
+#[cfg(target_os = "linux")]
+mod capabilities;
+#[cfg(target_os = "linux")]
 mod context;
+#[cfg(all(target_os = "linux", feature = "async"))]
+mod future;
+#[cfg(target_os = "linux")]
+mod host;
+#[cfg(target_os = "linux")]
 pub mod namespace;
+#[cfg(target_os = "linux")]
+mod process;
+#[cfg(target_os = "linux")]
+mod spec;
 
-pub use context::{Child, Context};
+#[cfg(target_os = "linux")]
+pub use capabilities::{Capabilities, Capability};
+#[cfg(target_os = "linux")]
+pub use context::{Child, Context, DetachedChild, Ready, ReadyWaiter, Rusage, SpawnMode, WaitStatus};
+#[cfg(target_os = "linux")]
+pub use host::{CgroupVersion, HostCapabilities, host_capabilities};
+#[cfg(target_os = "linux")]
+pub use namespace::{CloneFlags, MountEntry, Propagation, mountinfo};
+#[cfg(target_os = "linux")]
+pub use process::Policy;
+#[cfg(target_os = "linux")]
+pub use spec::{ContextSpec, MountSpec};
+#[cfg(target_os = "linux")]
 pub use error::*;
 
 
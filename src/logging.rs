@@ -0,0 +1,22 @@
+//! Logging macros used when the `log` feature is disabled.
+//!
+//! These take the place of the macros from the `log` crate, type-checking
+//! their arguments without generating any code.
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+	($($arg:tt)*) => (
+		if false {
+			let _ = format_args!($($arg)*);
+		}
+	);
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! warn {
+	($($arg:tt)*) => (
+		if false {
+			let _ = format_args!($($arg)*);
+		}
+	);
+}
@@ -1,5 +1,5 @@
 //! `isolate` is a command line tool that encapsulates the behaviour provided by the
-//! [`unshare`](https://docs.rs/unshare) library.
+//! [`isolate`](https://docs.rs/isolate) library.
 //!
 //! `isolate` uses a configuration file to construct what is essentially a lightweight container
 //! for the command that it then executes.
@@ -11,28 +11,67 @@
 //!
 //! 1. `./isolate.toml`
 //! 1. `./.isolate.toml`
-//! 1. `~/.config/isolate.toml`
+//! 1. `$XDG_CONFIG_HOME/isolate.toml` (or `~/.config/isolate.toml` if `XDG_CONFIG_HOME` is
+//!    not set)
 //! 1. `~/.isolate.toml`
 //! 1. `/etc/isolate.toml`
 //!
+//! If none of these exist, the default configuration is used. The `--no-config` flag skips the
+//! configuration file entirely and runs the command in an empty context.
+//!
+//! # Profiles
+//!
+//! A configuration file can describe several contexts as named profiles, each a table under
+//! `[profiles]` with the same settings as the top level of the file (e.g. `[profiles.build]`).
+//! The `-p` or `--profile` flag selects a profile by name. Without it, the `default` profile is
+//! used if there is one, and the settings at the top level of the file otherwise. Profiles don't
+//! inherit the top-level settings.
+//!
+//! The configuration file describes a
+//! [`ContextSpec`](https://docs.rs/isolate/*/isolate/struct.ContextSpec.html). Run
+//! `isolate --default-config` for an example of each setting.
+//!
 //! # Usage
 //!
-//! `isolate [--config-file <path>] <command>`
+//! `isolate [[--config-file <path>] [--profile <name>] | --no-config] [--dry-run] [--env-clear] [-e <var>=<value>...] <command>`
+//!
+//! The `--dry-run` flag prints the context constructed from the configuration
+//! file along with the command that would be run, without running it.
+//!
+//! `isolate` exits with the exit status of the command, or 128 plus the signal number if the
+//! command was killed by a signal.
+//!
+//! # Environment
+//!
+//! The command inherits the environment of `isolate` unless `--env-clear` is given, in which case
+//! it starts from an empty environment. Variables set in the `[env]` table of the configuration
+//! file override inherited variables and variables set with `-e` or `--env` override both.
+//!
+//! To pass only some variables to the command, list them in `env_passthrough` in the
+//! configuration file. The environment is then cleared except for those variables, which are
+//! still overridden by the `[env]` table and `--env`. `--env-clear` also clears these variables.
+
+// The impl derived by `serde_derive` for `Arguments` is placed inside an
+// anonymous constant, so the lint must be allowed for the whole crate.
+#![allow(non_local_definitions)]
 
 extern crate docopt;
+extern crate isolate;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate toml;
-extern crate unshare;
 
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process::exit;
+use std::process::{self, exit};
 
 use docopt::Docopt;
+use isolate::{Context, ContextSpec, WaitStatus};
+use toml::Value;
 use toml::de::from_str;
 
 fn main() {
@@ -43,16 +82,25 @@ fn main() {
         exit(0);
     }
 
-    args.into_command().exec();
+    if args.flag_dry_run {
+        args.into_command().describe();
+    } else {
+        args.into_command().exec();
+    }
 }
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Usage:
-    isolate [--config-file <file>] <program> [<args>...]
+    isolate [[--config-file <file>] [--profile <name>] | --no-config] [--dry-run] [--env-clear] [--env <var>]... <program> [<args>...]
     isolate [-v | -h | -d]
 
 Options:
     -f <file>, --config-file <file>  Location of configuration file to use.
+    -p <name>, --profile <name>      Use the named profile from the configuration file.
+    --no-config                      Don't use a configuration file.
+    -n, --dry-run                    Print the context and command without running it.
+    -e <var>, --env <var>            Set an environment variable as <name>=<value>.
+    --env-clear                      Start the command with an empty environment.
     -h, --help                       Show this help.
     -v, --version                    Show the version.
     -d, --default-config             Dumpt the default configuration to stdout.
@@ -61,7 +109,12 @@ Options:
 #[derive(Deserialize)]
 struct Arguments {
     flag_config_file: Option<String>,
+    flag_profile: Option<String>,
     flag_default_config: bool,
+    flag_no_config: bool,
+    flag_dry_run: bool,
+    flag_env: Vec<String>,
+    flag_env_clear: bool,
     arg_program: String,
     arg_args: Vec<String>
 }
@@ -80,11 +133,16 @@ impl Arguments {
     /// Construct the command to execute.
     fn into_command(self) -> Command {
         let config = self.config();
-        Command::new(self.arg_program, self.arg_args, config)
+        let env = self.flag_env.iter().map(|var| parse_env_var(var)).collect();
+        Command::new(self.arg_program, self.arg_args, config, env, self.flag_env_clear)
     }
 
     /// Determine the path to configuration file.
     fn config(&self) -> Configuration {
+        if self.flag_no_config {
+            return Configuration::default();
+        }
+
         let text = if let Some(ref path) = self.find_config_path() {
             let mut file = File::open(path).expect("could not open configuration file");
             let mut text = String::new();
@@ -94,7 +152,10 @@ impl Arguments {
             DEFAULT_CONFIG.to_string()
         };
 
-        from_str(&text).expect("could not parse configuration")
+        select_profile(&text, self.flag_profile.as_deref()).unwrap_or_else(|err| {
+            eprintln!("invalid configuration: {}", err);
+            exit(1);
+        })
     }
 
     /// Determine the path of the configuration file.
@@ -121,8 +182,18 @@ impl Arguments {
             ".isolate.toml".to_string()
         ];
 
-        if let Ok(path) = env::var("HOME") {
-            paths.push(format!("{}/.config/isolate.toml", path));
+        let home = env::var("HOME").ok();
+
+        match env::var("XDG_CONFIG_HOME") {
+            Ok(ref path) if !path.is_empty() => {
+                paths.push(format!("{}/isolate.toml", path));
+            }
+            _ => if let Some(ref path) = home {
+                paths.push(format!("{}/.config/isolate.toml", path));
+            },
+        }
+
+        if let Some(ref path) = home {
             paths.push(format!("{}/.isolate.toml", path));
         }
 
@@ -132,36 +203,123 @@ impl Arguments {
     }
 }
 
-const DEFAULT_CONFIG: &'static str = include_str!("isolate.toml");
+const DEFAULT_CONFIG: &str = include_str!("isolate.toml");
 
-#[derive(Deserialize)]
-struct Configuration {
-}
+/// The configuration file describes the context of the command.
+type Configuration = ContextSpec;
 
 struct Command {
     program: String,
     arguments: Vec<String>,
     config: Configuration,
+    env: Vec<(String, String)>,
+    env_clear: bool,
 }
 
 impl Command {
     /// COnstruct a new command
-    fn new(program: String, args: Vec<String>, config: Configuration) -> Command {
+    fn new(
+        program: String,
+        arguments: Vec<String>,
+        config: Configuration,
+        env: Vec<(String, String)>,
+        env_clear: bool,
+    ) -> Command {
         Command {
-            program: program,
-            arguments: args,
-            config: config,
+            program,
+            arguments,
+            config,
+            env,
+            env_clear,
         }
     }
 
-    /// Execute the given command.
-    fn exec(&self) {
-        unshare::Command::new(&self.program)
-            .args(&self.arguments)
-            .spawn()
-            .expect("unable to spawn process")
-            .wait()
-            .expect("error in child process");
+    /// Construct the execution context for the command.
+    ///
+    /// Variables from the command line are applied after those from the
+    /// configuration file so that they take precedence.
+    fn context(&self) -> Context {
+        let mut spec = self.config.clone();
+
+        if self.env_clear {
+            spec.env_clear = true;
+            spec.env_keep.clear();
+            spec.env_passthrough = None;
+        }
+        spec.env.extend(self.env.iter().cloned());
+
+        Context::from_spec(&spec).unwrap_or_else(|err| {
+            eprintln!("invalid configuration: {}", err);
+            exit(1);
+        })
+    }
+
+    /// Print the context and the command that would be executed.
+    fn describe(&self) {
+        println!("{:#?}", self.context());
+        println!("{} {}", self.program, self.arguments.join(" "));
+    }
+
+    /// Execute the given command, exiting with its exit status.
+    fn exec(&self) -> ! {
+        exit(self.run())
+    }
+
+    /// Run the given command and return its exit status.
+    ///
+    /// If the command is killed by a signal, the exit status is 128 plus the
+    /// signal number, as in the shell.
+    fn run(&self) -> i32 {
+        let program = self.program.clone();
+        let arguments = self.arguments.clone();
+
+        let child = self.context()
+            .exec_private(move || {
+                let err = process::Command::new(&program)
+                    .args(&arguments)
+                    .exec();
+                eprintln!("unable to execute {}: {}", program, err);
+                exit(127);
+            })
+            .expect("unable to spawn process");
+
+        match child.wait().expect("error in child process") {
+            WaitStatus::Exited(code) => code,
+            WaitStatus::Signaled(signal) => 128 + signal,
+        }
+    }
+}
+
+/// Select the configuration of a profile from the text of a configuration file.
+///
+/// Without a profile name, the `default` profile is used if there is one and the top level of the
+/// configuration otherwise.
+fn select_profile(text: &str, profile: Option<&str>) -> Result<Configuration, String> {
+    let mut config: Value = from_str(text).map_err(|err| err.to_string())?;
+    let profiles = config.as_table_mut().and_then(|table| table.remove("profiles"));
+    let mut profiles = match profiles {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err("profiles must be a table".to_string()),
+        None => Default::default(),
+    };
+
+    let selected = match profile {
+        Some(name) => profiles.remove(name).ok_or_else(|| format!("no profile named {}", name))?,
+        None => profiles.remove("default").unwrap_or(config),
+    };
+
+    selected.try_into().map_err(|err| err.to_string())
+}
+
+/// Split an environment variable given as `<name>=<value>`.
+fn parse_env_var(var: &str) -> (String, String) {
+    let mut parts = var.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) => (name.to_string(), value.to_string()),
+        _ => {
+            eprintln!("environment variables must be given as <name>=<value>: {}", var);
+            exit(1);
+        }
     }
 }
 
@@ -180,4 +338,50 @@ fn version() -> String {
 #[test]
 fn default_config() {
     assert!(from_str::<Configuration>(DEFAULT_CONFIG).is_ok());
+    assert!(select_profile(DEFAULT_CONFIG, None).is_ok());
+}
+
+/// Test that profiles are selected by name and default to the top level.
+#[test]
+fn profile_selection() {
+    let text = "
+        hostname = \"top\"
+
+        [profiles.build]
+        hostname = \"build\"
+        namespaces = [\"uts\", \"mount\"]
+    ";
+
+    assert_eq!(select_profile(text, None).unwrap().hostname, Some("top".to_string()));
+    let build = select_profile(text, Some("build")).unwrap();
+    assert_eq!(build.hostname, Some("build".to_string()));
+    assert_eq!(build.namespaces, vec!["uts".to_string(), "mount".to_string()]);
+    assert!(select_profile(text, Some("test")).is_err());
+
+    let text = "[profiles.default]\nhostname = \"default\"\n[profiles.build]\nbogus = 1";
+    assert_eq!(select_profile(text, None).unwrap().hostname, Some("default".to_string()));
+    assert!(select_profile(text, Some("build")).is_err());
+}
+
+/// Test that variables to pass through must be given as strings.
+#[test]
+fn env_passthrough_config() {
+    let config = from_str::<Configuration>("env_passthrough = [\"PATH\", \"HOME\"]").unwrap();
+    assert_eq!(config.env_passthrough, Some(vec!["PATH".to_string(), "HOME".to_string()]));
+
+    assert!(from_str::<Configuration>("env_passthrough = [\"PATH\", 1]").is_err());
+    assert!(Context::from_spec(&from_str("env_passthrough = [\"A=B\"]").unwrap()).is_err());
+}
+
+/// Test that the exit status of the command is that of the child or 128 plus its signal.
+#[test]
+fn exit_status() {
+    let run = |script: &str| {
+        let config = from_str::<Configuration>("").unwrap();
+        let arguments = vec!["-c".to_string(), script.to_string()];
+        Command::new("sh".to_string(), arguments, config, Vec::new(), false).run()
+    };
+
+    assert_eq!(run("exit 3"), 3);
+    assert_eq!(run("kill -KILL $$"), 128 + 9);
 }
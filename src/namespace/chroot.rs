@@ -0,0 +1,54 @@
+use std::env::set_current_dir;
+use std::ffi::CString;
+
+use libc::chroot;
+
+use ::error::*;
+use super::Namespace;
+
+/// Change the root directory of the child.
+///
+/// This calls `chroot(2)` on the given path and then changes the working
+/// directory to the new root. Unlike `pivot_root(2)`, the new root does not
+/// need to be a mount point so this can be used with a directory that has
+/// been prepared with bind mounts.
+///
+/// A `chroot` on its own is **not** a security boundary. A process that is
+/// able to call `chroot(2)` (i.e. it has `CAP_SYS_CHROOT`) can escape back out
+/// of the new root. This should be combined with a [`User`](struct.User.html)
+/// namespace or the dropping of capabilities to confine the child.
+///
+/// As configuration is applied in order, this should be added to a context
+/// after any [`Mount`](struct.Mount.html)s that prepare the new root.
+///
+/// ```rust
+/// Context::new()
+///     .with(User::new().map_root_user())
+///     .with(Mount::recursive_bind("/usr", "/tmp/jail/usr")?)
+///     .with(Chroot::new("/tmp/jail")?);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Chroot {
+	path: CString,
+}
+
+impl Chroot {
+	/// Change the root directory to `path`.
+	pub fn new(path: &str) -> Result<Chroot> {
+		Ok(Chroot {
+			path: CString::new(path.to_owned())?,
+		})
+	}
+}
+
+impl Namespace for Chroot {
+	fn internal_config(&mut self) -> Result<()> {
+		if unsafe { chroot(self.path.as_ptr()) } == -1 {
+			return Err(errno!(Chroot, self.path.clone()));
+		}
+
+		set_current_dir("/")?;
+
+		Ok(())
+	}
+}
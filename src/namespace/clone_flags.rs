@@ -0,0 +1,97 @@
+use std::fmt;
+
+use libc::{
+	CLONE_CHILD_CLEARTID,
+	CLONE_CHILD_SETTID,
+	CLONE_DETACHED,
+	CLONE_FILES,
+	CLONE_FS,
+	CLONE_IO,
+	CLONE_NEWCGROUP,
+	CLONE_NEWIPC,
+	CLONE_NEWNET,
+	CLONE_NEWNS,
+	CLONE_NEWPID,
+	CLONE_NEWUSER,
+	CLONE_NEWUTS,
+	CLONE_PARENT,
+	CLONE_PARENT_SETTID,
+	CLONE_PTRACE,
+	CLONE_SETTLS,
+	CLONE_SIGHAND,
+	CLONE_SYSVSEM,
+	CLONE_THREAD,
+	CLONE_UNTRACED,
+	CLONE_VFORK,
+	CLONE_VM,
+	c_int,
+};
+
+/// A set of flags passed to `clone(2)`.
+///
+/// This is displayed symbolically, e.g. `CLONE_NEWUSER|CLONE_NEWNS`, with
+/// any unrecognised bits displayed in hexadecimal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CloneFlags(pub c_int);
+
+const FLAG_NAMES: &[(c_int, &str)] = &[
+	(CLONE_VM, "CLONE_VM"),
+	(CLONE_FS, "CLONE_FS"),
+	(CLONE_FILES, "CLONE_FILES"),
+	(CLONE_SIGHAND, "CLONE_SIGHAND"),
+	(CLONE_PTRACE, "CLONE_PTRACE"),
+	(CLONE_VFORK, "CLONE_VFORK"),
+	(CLONE_PARENT, "CLONE_PARENT"),
+	(CLONE_THREAD, "CLONE_THREAD"),
+	(CLONE_NEWNS, "CLONE_NEWNS"),
+	(CLONE_SYSVSEM, "CLONE_SYSVSEM"),
+	(CLONE_SETTLS, "CLONE_SETTLS"),
+	(CLONE_PARENT_SETTID, "CLONE_PARENT_SETTID"),
+	(CLONE_CHILD_CLEARTID, "CLONE_CHILD_CLEARTID"),
+	(CLONE_DETACHED, "CLONE_DETACHED"),
+	(CLONE_UNTRACED, "CLONE_UNTRACED"),
+	(CLONE_CHILD_SETTID, "CLONE_CHILD_SETTID"),
+	(CLONE_NEWCGROUP, "CLONE_NEWCGROUP"),
+	(CLONE_NEWUTS, "CLONE_NEWUTS"),
+	(CLONE_NEWIPC, "CLONE_NEWIPC"),
+	(CLONE_NEWUSER, "CLONE_NEWUSER"),
+	(CLONE_NEWPID, "CLONE_NEWPID"),
+	(CLONE_NEWNET, "CLONE_NEWNET"),
+	(CLONE_IO, "CLONE_IO"),
+];
+
+impl fmt::Display for CloneFlags {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut remaining = self.0;
+		let mut first = true;
+
+		for &(flag, name) in FLAG_NAMES {
+			if remaining & flag != 0 {
+				if !first {
+					write!(f, "|")?;
+				}
+				write!(f, "{}", name)?;
+				remaining &= !flag;
+				first = false;
+			}
+		}
+
+		match (remaining, first) {
+			(0, true) => write!(f, "0"),
+			(0, false) => Ok(()),
+			(remaining, true) => write!(f, "{:#x}", remaining),
+			(remaining, false) => write!(f, "|{:#x}", remaining),
+		}
+	}
+}
+
+/// Test that flags are displayed symbolically.
+#[test]
+fn display_flags() {
+	assert_eq!(CloneFlags(0).to_string(), "0");
+	assert_eq!(
+		CloneFlags(CLONE_NEWUSER | CLONE_NEWNS).to_string(),
+		"CLONE_NEWNS|CLONE_NEWUSER"
+	);
+	assert_eq!(CloneFlags(CLONE_VM | 0x11).to_string(), "CLONE_VM|0x11");
+}
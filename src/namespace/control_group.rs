@@ -1,11 +1,19 @@
+use std::cell::RefCell;
+use std::fs::{OpenOptions, create_dir, remove_dir};
+use std::io::{ErrorKind as IoErrorKind, Write};
+use std::path::{Path, PathBuf};
+
 use libc::{
 	CLONE_NEWCGROUP,
+	CLONE_NEWNS,
 	c_int,
+	unshare,
 };
 
 use ::error::*;
+use ::host::{CgroupVersion, cgroup_version};
 use ::Child;
-use super::Namespace;
+use super::{CloneFlags, Mount, Namespace};
 
 /// Control group namespace representation.
 ///
@@ -13,18 +21,340 @@ use super::Namespace;
 /// assigned resource limits. This ensures that the total amount of resources,
 /// such as CPU time and system memory, used by all of the process in the group
 /// is limited.
-#[derive(Clone)]
-pub struct ControlGroup {}
+///
+/// This combines a [`CgroupNamespace`](struct.CgroupNamespace.html), which
+/// changes the view of the control group hierarchy, with optional
+/// [`CgroupLimits`](struct.CgroupLimits.html), which place the child in a
+/// control group with resource limits. When both are used, the namespace is
+/// created once the child has been placed in the limited control group so that
+/// the control group becomes the root of the view of the child.
+#[derive(Clone, Debug)]
+pub struct ControlGroup {
+	namespace: CgroupNamespace,
+	limits: Option<CgroupLimits>,
+}
 
 impl ControlGroup {
 	/// Configure a new Control Group namespace for creation.
 	pub fn new() -> ControlGroup {
-		ControlGroup {}
+		ControlGroup {
+			namespace: CgroupNamespace::new(),
+			limits: None,
+		}
+	}
+
+	/// Mount a new `cgroup2` file-system at `target` within the namespace.
+	///
+	/// See [`CgroupNamespace::mount_cgroup2`].
+	///
+	/// [`CgroupNamespace::mount_cgroup2`]: struct.CgroupNamespace.html#method.mount_cgroup2
+	pub fn mount_cgroup2(mut self, target: &str) -> Result<ControlGroup> {
+		self.namespace = self.namespace.mount_cgroup2(target)?;
+		Ok(self)
+	}
+
+	/// Place the child in a control group with the given limits before
+	/// creating the namespace.
+	pub fn limits(mut self, limits: CgroupLimits) -> ControlGroup {
+		self.namespace.deferred = true;
+		self.limits = Some(limits);
+		self
+	}
+}
+
+impl Default for ControlGroup {
+	fn default() -> ControlGroup {
+		ControlGroup::new()
 	}
 }
 
 impl Namespace for ControlGroup {
 	fn clone_flag(&self) -> c_int {
-		CLONE_NEWCGROUP
+		self.namespace.clone_flag()
 	}
+
+	fn prepare(&self) -> Result<()> {
+		match self.limits {
+			Some(ref limits) => limits.prepare(),
+			None => Ok(()),
+		}
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		self.namespace.internal_config()
+	}
+
+	fn external_config(&self, child: &Child) -> Result<()> {
+		match self.limits {
+			Some(ref limits) => limits.external_config(child),
+			None => Ok(()),
+		}
+	}
+
+	fn cleanup(&mut self) -> Result<()> {
+		match self.limits {
+			Some(ref mut limits) => limits.cleanup(),
+			None => Ok(()),
+		}
+	}
+}
+
+/// A new control group namespace.
+///
+/// The namespace virtualises the view of the control group hierarchy so that
+/// the control group of the child appears as the root of the hierarchy. No
+/// limits are applied to the child.
+#[derive(Clone, Debug, Default)]
+pub struct CgroupNamespace {
+	mount: Option<Mount>,
+	deferred: bool,
+}
+
+impl CgroupNamespace {
+	/// Configure a new control group namespace for creation.
+	pub fn new() -> CgroupNamespace {
+		Default::default()
+	}
+
+	/// Mount a new `cgroup2` file-system at `target` within the namespace.
+	///
+	/// The file-system is mounted after the namespace has been entered so
+	/// that it shows only the subtree of the control group hierarchy rooted at
+	/// the control group of the child. The mount is made in a new mount
+	/// namespace and without support for set-user-ID programs, devices, or
+	/// execution.
+	///
+	/// ```rust
+	/// CgroupNamespace::new().mount_cgroup2("/sys/fs/cgroup")?;
+	/// ```
+	pub fn mount_cgroup2(mut self, target: &str) -> Result<CgroupNamespace> {
+		self.mount = Some(
+			Mount::new("cgroup2", target, "cgroup2")?
+				.no_setuid()
+				.no_devices()
+				.no_execute()
+		);
+		Ok(self)
+	}
+}
+
+impl Namespace for CgroupNamespace {
+	fn clone_flag(&self) -> c_int {
+		let namespace = if self.deferred { 0 } else { CLONE_NEWCGROUP };
+		match self.mount {
+			Some(_) => namespace | CLONE_NEWNS,
+			None => namespace,
+		}
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		// The child has been moved to its control group by the parent.
+		if self.deferred && unsafe { unshare(CLONE_NEWCGROUP) } == -1 {
+			return Err(errno!(Unshare, CloneFlags(CLONE_NEWCGROUP)));
+		}
+
+		if let Some(ref mut mount) = self.mount {
+			mount.mount()?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Resource limits applied to the child through control groups.
+///
+/// The control group is created before the child, if it does not already
+/// exist, and the limits are written to it before the child is moved into it.
+/// A control group created this way is removed once the child has been
+/// waited on. No new namespace is created.
+///
+/// Limits are given as for a `cgroup2` control group. If the host only has
+/// `cgroup` (v1) hierarchies, a path under `/sys/fs/cgroup` is instead taken
+/// as the path of a control group in the hierarchy of each controller that
+/// is limited, and the child is placed in each of them. The limits set with
+/// [`memory_max`](#method.memory_max), [`pids_max`](#method.pids_max), and
+/// [`cpu_max`](#method.cpu_max) are translated to their v1 equivalents, and
+/// other interface files are written to the hierarchy of the controller
+/// that they are prefixed with.
+///
+/// ```rust
+/// // Uses /sys/fs/cgroup/memory/jail and /sys/fs/cgroup/pids/jail on v1.
+/// CgroupLimits::new("/sys/fs/cgroup/jail")
+///     .memory_max(256 * 1024 * 1024)
+///     .pids_max(64);
+/// ```
+#[derive(Clone, Debug)]
+pub struct CgroupLimits {
+	path: PathBuf,
+	limits: Vec<(String, String)>,
+	created: RefCell<Vec<PathBuf>>,
+}
+
+impl CgroupLimits {
+	/// Apply limits using the control group at `path`.
+	pub fn new(path: &str) -> CgroupLimits {
+		CgroupLimits {
+			path: PathBuf::from(path),
+			limits: Vec::new(),
+			created: RefCell::new(Vec::new()),
+		}
+	}
+
+	/// Write `value` to the interface file `file` of the control group.
+	pub fn limit(mut self, file: &str, value: &str) -> CgroupLimits {
+		self.limits.push((file.to_owned(), value.to_owned()));
+		self
+	}
+
+	/// Limit the memory usage of the control group in bytes (`memory.max`).
+	pub fn memory_max(self, bytes: u64) -> CgroupLimits {
+		self.limit("memory.max", &bytes.to_string())
+	}
+
+	/// Limit the number of processes in the control group (`pids.max`).
+	pub fn pids_max(self, pids: u64) -> CgroupLimits {
+		self.limit("pids.max", &pids.to_string())
+	}
+
+	/// Limit the group to `quota` microseconds of CPU time every `period`
+	/// microseconds (`cpu.max`).
+	pub fn cpu_max(self, quota: u64, period: u64) -> CgroupLimits {
+		self.limit("cpu.max", &format!("{} {}", quota, period))
+	}
+
+	/// Find the control groups to limit and the limits to write to each.
+	fn groups(&self) -> Vec<(PathBuf, Vec<(String, String)>)> {
+		let relative = match cgroup_version() {
+			CgroupVersion::V1 | CgroupVersion::Hybrid => self.path.strip_prefix(CGROUP_ROOT).ok(),
+			CgroupVersion::V2 | CgroupVersion::None => None,
+		};
+
+		let relative = match relative {
+			Some(relative) => relative,
+			None => return vec![(self.path.clone(), self.limits.clone())],
+		};
+
+		let mut groups: Vec<(PathBuf, Vec<(String, String)>)> = Vec::new();
+		for (file, value) in &self.limits {
+			for (file, value) in v1_limits(file, value) {
+				let controller = file.split('.').next().unwrap_or_default().to_owned();
+				let path = Path::new(CGROUP_ROOT).join(controller).join(relative);
+				match groups.iter_mut().find(|group| group.0 == path) {
+					Some(group) => group.1.push((file, value)),
+					None => groups.push((path, vec![(file, value)])),
+				}
+			}
+		}
+
+		groups
+	}
+}
+
+/// Write a value to an interface file of a control group.
+fn write(path: &Path, file: &str, value: &str) -> Result<()> {
+	debug!("writing {:?} to {:?}", value, path.join(file));
+	let mut interface = OpenOptions::new()
+		.write(true)
+		.open(path.join(file))?;
+	interface.write_all(value.as_bytes())?;
+
+	Ok(())
+}
+
+/// Translate a `cgroup2` limit to the equivalent `cgroup` (v1) limits.
+fn v1_limits(file: &str, value: &str) -> Vec<(String, String)> {
+	let unlimited = |value: &str| match value {
+		"max" => "-1".to_owned(),
+		value => value.to_owned(),
+	};
+
+	match file {
+		"memory.max" => vec![("memory.limit_in_bytes".to_owned(), unlimited(value))],
+		"cpu.max" => {
+			let mut parts = value.split_whitespace();
+			let quota = parts.next().unwrap_or("max");
+			let mut limits = Vec::new();
+			if let Some(period) = parts.next() {
+				limits.push(("cpu.cfs_period_us".to_owned(), period.to_owned()));
+			}
+			limits.push(("cpu.cfs_quota_us".to_owned(), unlimited(quota)));
+			limits
+		}
+		_ => vec![(file.to_owned(), value.to_owned())],
+	}
+}
+
+/// The directory at which the control group hierarchies are mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+impl Namespace for CgroupLimits {
+	fn prepare(&self) -> Result<()> {
+		for (path, _) in self.groups() {
+			if !path.is_dir() {
+				create_dir(&path)?;
+				self.created.borrow_mut().push(path);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn external_config(&self, child: &Child) -> Result<()> {
+		for (path, limits) in self.groups() {
+			for (file, value) in &limits {
+				write(&path, file, value)?;
+			}
+
+			write(&path, "cgroup.procs", &child.pid().to_string())?;
+		}
+
+		Ok(())
+	}
+
+	fn cleanup(&mut self) -> Result<()> {
+		// A group is only forgotten once it is gone, so that a failed
+		// cleanup can be retried.
+		let created = self.created.get_mut();
+		while let Some(path) = created.last() {
+			match remove_dir(path) {
+				Err(ref err) if err.kind() == IoErrorKind::NotFound => {}
+				result => result?,
+			}
+			created.pop();
+		}
+
+		Ok(())
+	}
+}
+
+/// Test the translation of limits to `cgroup` (v1) interface files.
+#[test]
+fn translate_v1_limits() {
+	let pair = |file: &str, value: &str| (file.to_owned(), value.to_owned());
+	assert_eq!(v1_limits("memory.max", "max"), vec![pair("memory.limit_in_bytes", "-1")]);
+	assert_eq!(v1_limits("cpu.max", "50000 100000"), vec![
+		pair("cpu.cfs_period_us", "100000"),
+		pair("cpu.cfs_quota_us", "50000"),
+	]);
+	assert_eq!(v1_limits("pids.max", "16"), vec![pair("pids.max", "16")]);
+}
+
+/// Test that a control group that can't be removed is kept so that cleaning
+/// up can be retried.
+#[test]
+fn cleanup_retries() {
+	use std::fs::{File, remove_file};
+
+	let path = ::std::env::temp_dir().join(format!("isolate-cgroup-{}", ::std::process::id()));
+	let mut limits = CgroupLimits::new(path.to_str().unwrap());
+	limits.prepare().unwrap();
+
+	File::create(path.join("cgroup.procs")).unwrap();
+	assert!(limits.cleanup().is_err());
+	assert!(path.is_dir());
+
+	remove_file(path.join("cgroup.procs")).unwrap();
+	limits.cleanup().unwrap();
+	assert!(!path.exists());
+	limits.cleanup().unwrap();
 }
@@ -0,0 +1,188 @@
+use std::cell::Cell;
+use std::fs::{OpenOptions, create_dir, create_dir_all, metadata, remove_dir};
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use libc::{
+	CLONE_NEWNS,
+	c_int,
+	c_uint,
+	major,
+	minor,
+};
+
+use ::error::*;
+use ::Child;
+use super::{Mount, Namespace};
+
+/// Host devices made available to the child.
+///
+/// Each device node is bound to the same path within the root of the context
+/// (see [`Context::root`](../struct.Context.html#method.root)), or over itself
+/// if the context has no root. This requires a new mount namespace.
+///
+/// If a control group is given, the child is placed in that group of the
+/// `devices` controller of cgroup v1. Access to every device is denied in the
+/// group and only the bound devices are allowed, so the devices the child
+/// can use are kept consistent with the devices bound for it. The control
+/// group is created if it does not exist and is removed once the child has
+/// been waited on if it was created.
+///
+/// ```rust
+/// Context::new()
+///     .root("/tmp/jail")
+///     .with(Devices::new()
+///         .cgroup("/sys/fs/cgroup/devices/jail")
+///         .bind("/dev/null")?
+///         .bind("/dev/urandom")?);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Devices {
+	devices: Vec<Device>,
+	cgroup: Option<PathBuf>,
+	created: Cell<bool>,
+	root: Option<PathBuf>,
+}
+
+/// A device node on the host.
+#[derive(Clone, Debug)]
+struct Device {
+	path: PathBuf,
+	kind: char,
+	major: c_uint,
+	minor: c_uint,
+}
+
+impl Devices {
+	/// Make no devices available to the child.
+	pub fn new() -> Devices {
+		Default::default()
+	}
+
+	/// Restrict the child to the bound devices using the control group at
+	/// `path` in the `devices` controller.
+	pub fn cgroup(mut self, path: &str) -> Devices {
+		self.cgroup = Some(PathBuf::from(path));
+		self
+	}
+
+	/// Bind the device node at `path` into the root of the child.
+	///
+	/// Returns an error if `path` is not a character or block device.
+	// The device number functions are only safe in later versions of libc.
+	#[allow(unused_unsafe)]
+	pub fn bind<P: AsRef<Path>>(mut self, path: P) -> Result<Devices> {
+		let path = path.as_ref();
+		let not_a_device = || -> Error { ErrorKind::NotADevice(path.to_owned()).into() };
+
+		let device = metadata(path).map_err(|_| not_a_device())?;
+		let kind = if device.file_type().is_char_device() {
+			'c'
+		} else if device.file_type().is_block_device() {
+			'b'
+		} else {
+			return Err(not_a_device());
+		};
+
+		let rdev = device.rdev();
+		self.devices.push(Device {
+			path: path.to_owned(),
+			kind,
+			major: unsafe { major(rdev) },
+			minor: unsafe { minor(rdev) },
+		});
+		Ok(self)
+	}
+
+	/// Write a value to an interface file of the control group.
+	fn write(&self, cgroup: &Path, file: &str, value: &str) -> Result<()> {
+		debug!("writing {:?} to {:?}", value, cgroup.join(file));
+		let mut interface = OpenOptions::new()
+			.write(true)
+			.open(cgroup.join(file))?;
+		interface.write_all(value.as_bytes())?;
+
+		Ok(())
+	}
+}
+
+impl Namespace for Devices {
+	fn clone_flag(&self) -> c_int {
+		CLONE_NEWNS
+	}
+
+	fn required_flags(&self) -> c_int {
+		CLONE_NEWNS
+	}
+
+	fn prepare(&self) -> Result<()> {
+		if let Some(ref cgroup) = self.cgroup {
+			if !cgroup.is_dir() {
+				create_dir(cgroup)?;
+				self.created.set(true);
+			}
+		}
+
+		Ok(())
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		for device in &self.devices {
+			let target = match self.root {
+				Some(ref root) => root.join(device.path.strip_prefix("/").unwrap_or(&device.path)),
+				None => device.path.clone(),
+			};
+
+			// Device nodes can only be bound over files.
+			if !target.exists() {
+				if let Some(parent) = target.parent() {
+					create_dir_all(parent)?;
+				}
+				OpenOptions::new().write(true).create(true).truncate(false).open(&target)?;
+			}
+
+			Mount::bind(&device.path, &target)?.mount()?;
+		}
+
+		Ok(())
+	}
+
+	fn external_config(&self, child: &Child) -> Result<()> {
+		let cgroup = match self.cgroup {
+			Some(ref cgroup) => cgroup,
+			None => return Ok(()),
+		};
+
+		self.write(cgroup, "devices.deny", "a")?;
+		for device in &self.devices {
+			let rule = format!("{} {}:{} rwm", device.kind, device.major, device.minor);
+			self.write(cgroup, "devices.allow", &rule)?;
+		}
+
+		self.write(cgroup, "cgroup.procs", &child.pid().to_string())
+	}
+
+	fn set_root(&mut self, root: &Path) {
+		self.root = Some(root.to_owned());
+	}
+
+	fn cleanup(&mut self) -> Result<()> {
+		if let Some(ref cgroup) = self.cgroup {
+			if self.created.get() {
+				self.created.set(false);
+				remove_dir(cgroup)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Test that the device numbers of a bound device are read from its node.
+#[test]
+fn device_numbers() {
+	let devices = Devices::new().bind("/dev/null").unwrap();
+	assert_eq!((devices.devices[0].major, devices.devices[0].minor), (1, 3));
+	assert!(Devices::new().bind("/dev").is_err());
+}
@@ -0,0 +1,322 @@
+use std::fs::{File, read_dir};
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use libc::{
+	EINTR,
+	EXIT_FAILURE,
+	PR_SET_PDEATHSIG,
+	SIGKILL,
+	SIG_DFL,
+	SIG_SETMASK,
+	STDERR_FILENO,
+	WEXITSTATUS,
+	WIFSIGNALED,
+	WTERMSIG,
+	_SC_OPEN_MAX,
+	_exit,
+	c_int,
+	close,
+	fork,
+	pid_t,
+	prctl,
+	raise,
+	setns,
+	signal,
+	sigemptyset,
+	sigprocmask,
+	sigset_t,
+	sysconf,
+	waitpid,
+};
+
+use ::error::*;
+use super::{CloneFlags, Namespace, NsKind};
+
+/// Join an existing namespace.
+///
+/// Rather than creating a new namespace, the child joins an existing
+/// namespace from within the child before any configuration added after it is
+/// applied. Any configuration that requires a namespace of the same kind, such
+/// as a [`Mount`](struct.Mount.html), is then applied within the joined
+/// namespace and no new namespace of that kind is created. Joining a
+/// namespace requires `CAP_SYS_ADMIN` in the user namespace that owns it.
+///
+/// ```rust
+/// // Bind a directory into the mount namespace of a running container.
+/// Context::new()
+///     .with(Enter::pid(container, NsKind::Mount))
+///     .with(Mount::bind("/srv/data", "/data")?);
+/// ```
+///
+/// # Sharing a user namespace
+///
+/// Several children can share the identity mapping of one user namespace by
+/// creating it once and having the other children enter it. The namespace
+/// is kept open with [`Child::namespace_fd`] so that it outlives the child
+/// that created it. Namespaces are created by `clone(2)` before the user
+/// namespace is entered, so any new namespaces would be owned by the user
+/// namespace of the caller rather than the shared one; the other namespaces
+/// of the first child should be entered along with its user namespace.
+///
+/// ```rust
+/// let first = Context::new()
+///     .with(User::new().map_root_user().map_root_group())
+///     .with(EmptyMount::new())
+///     .exec_private(|| pause())?;
+///
+/// let user = first.namespace_fd(NsKind::User)?;
+/// let mount = first.namespace_fd(NsKind::Mount)?;
+/// let second = Context::new()
+///     .with(Enter::file(user, NsKind::User))
+///     .with(Enter::file(mount, NsKind::Mount))
+///     .exec_private(|| pause())?;
+/// ```
+///
+/// # PID namespaces
+///
+/// Unlike other kinds of namespace, joining a PID namespace doesn't move the
+/// calling process into it; only the children it creates afterwards are
+/// placed in it. When a PID namespace is entered, the child forks once it
+/// has joined the namespace and the rest of the configuration and the
+/// function are run in the new process, which is in the joined namespace.
+/// The original child closes its descriptors, waits for the new process and
+/// exits in the same way, and the new process is killed if the original
+/// child is. The returned [`Child`] refers to the original child, so its
+/// PID and namespaces are those of the original child. The new process has
+/// a copy of the address space, so a function run with
+/// [`exec_shared`](../struct.Context.html#method.exec_shared) no longer
+/// shares memory with the caller.
+///
+/// ```rust
+/// Context::new()
+///     .with(Enter::pid(container, NsKind::User))
+///     .with(Enter::pid(container, NsKind::Pid))
+///     .exec_blocking(|| println!("{}", process::id()))?;
+/// ```
+///
+/// [`Child::namespace_fd`]: ../struct.Child.html#method.namespace_fd
+/// [`Child`]: ../struct.Child.html
+#[derive(Clone, Debug)]
+pub struct Enter {
+	namespace: Target,
+	kind: NsKind,
+}
+
+/// The namespace to join.
+#[derive(Clone, Debug)]
+enum Target {
+	/// A path that refers to the namespace.
+	Path(PathBuf),
+	/// An open file that refers to the namespace.
+	File(Rc<File>),
+}
+
+impl Enter {
+	/// Join the namespace of the given kind of the process `pid`.
+	pub fn pid(pid: pid_t, kind: NsKind) -> Enter {
+		let path = Path::new("/proc")
+			.join(pid.to_string())
+			.join("ns")
+			.join(kind.name());
+		Enter::path(path, kind)
+	}
+
+	/// Join the namespace of the given kind referred to by `path`.
+	///
+	/// The path may be a file in `/proc/<pid>/ns` or a bind mount of one,
+	/// such as those created by `ip netns add`.
+	pub fn path<P: AsRef<Path>>(path: P, kind: NsKind) -> Enter {
+		Enter {
+			namespace: Target::Path(path.as_ref().to_owned()),
+			kind,
+		}
+	}
+
+	/// Join the namespace of the given kind referred to by an open file.
+	///
+	/// The namespace is kept alive as long as the file is open, even if no
+	/// processes remain in it. The copy of the descriptor inherited by the
+	/// child is closed once the namespace has been joined. See
+	/// [`Child::namespace_fd`](../struct.Child.html#method.namespace_fd).
+	pub fn file(file: File, kind: NsKind) -> Enter {
+		Enter {
+			namespace: Target::File(Rc::new(file)),
+			kind,
+		}
+	}
+}
+
+impl Namespace for Enter {
+	fn entered_flags(&self) -> c_int {
+		self.kind.clone_flag()
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		debug!("joining {:?} namespace {:?}", self.kind, self.namespace);
+		let fd = match self.namespace {
+			Target::Path(ref path) => File::open(path)?.into_raw_fd(),
+			// The child has its own copy of the descriptor and never drops
+			// the last reference to the file, so closing the copy leaves the
+			// file of the parent open.
+			Target::File(ref file) => file.as_raw_fd(),
+		};
+
+		// Close the namespace as soon as it has been joined so that it isn't
+		// inherited by the closure and kept alive by it.
+		let joined = unsafe { setns(fd, self.kind.clone_flag()) };
+		let err = ::errno::errno();
+		unsafe { close(fd) };
+		if joined == -1 {
+			::errno::set_errno(err);
+			return Err(errno!(Setns, CloneFlags(self.kind.clone_flag())));
+		}
+
+		match self.kind {
+			NsKind::Pid => fork_into_namespace(),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Continue in a new process created in the joined PID namespace.
+///
+/// The calling process never returns; it waits for the new process and
+/// exits in the same way.
+fn fork_into_namespace() -> Result<()> {
+	match unsafe { fork() } {
+		-1 => Err(errno!(EnterPid)),
+		0 => match unsafe { prctl(PR_SET_PDEATHSIG, SIGKILL) } {
+			-1 => Err(errno!(EnterPid)),
+			_ => Ok(()),
+		},
+		pid => {
+			// The parent waits for the pipes it shares with the child to be
+			// closed, so none of them may be held open here.
+			close_descriptors();
+			unsafe { _exit(forward_exit(pid)) }
+		}
+	}
+}
+
+/// Close all descriptors other than the standard streams.
+fn close_descriptors() {
+	let fds: Vec<c_int> = match read_dir("/proc/self/fd") {
+		Ok(entries) => entries
+			.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+			.collect(),
+		Err(_) => (0..unsafe { sysconf(_SC_OPEN_MAX) } as c_int).collect(),
+	};
+
+	for fd in fds.into_iter().filter(|&fd| fd > STDERR_FILENO) {
+		unsafe { close(fd) };
+	}
+}
+
+/// Wait for a process and get the status with which to exit in the same way.
+///
+/// If the process was killed by a signal, the same signal is raised first.
+fn forward_exit(pid: pid_t) -> c_int {
+	let mut wstatus = 0;
+	loop {
+		match unsafe { waitpid(pid, &mut wstatus, 0) } {
+			-1 if ::errno::errno().0 == EINTR => continue,
+			-1 => return EXIT_FAILURE,
+			_ => break,
+		}
+	}
+
+	unsafe {
+		if !WIFSIGNALED(wstatus) {
+			return WEXITSTATUS(wstatus);
+		}
+
+		let sig = WTERMSIG(wstatus);
+		let mut empty: sigset_t = ::std::mem::zeroed();
+		sigemptyset(&mut empty);
+		sigprocmask(SIG_SETMASK, &empty, ::std::ptr::null_mut());
+		signal(sig, SIG_DFL);
+		raise(sig);
+		128 + sig
+	}
+}
+
+/// Test that the descriptors of joined namespaces are not left open in the
+/// child.
+#[test]
+fn enter_closes_namespaces() {
+	use std::fs::read_dir;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use libc::{SIGKILL, pause};
+	use ::namespace::{User, Uts};
+	use ::Context;
+
+	static LINGERING: AtomicUsize = AtomicUsize::new(1);
+
+	let first = Context::new()
+		.with(User::new().map_root_user())
+		.with(Uts::new())
+		.exec_private(|| loop {
+			unsafe { pause() };
+		})
+		.unwrap();
+
+	// Other tests may have descriptors open when the child is created, so
+	// only the joined namespaces are looked for.
+	let joined = [
+		format!("user:[{}]", first.namespace_id(NsKind::User).unwrap()),
+		format!("uts:[{}]", first.namespace_id(NsKind::Uts).unwrap()),
+	];
+
+	let user = first.namespace_fd(NsKind::User).unwrap();
+	let child = Context::new()
+		.with(Enter::file(user, NsKind::User))
+		.with(Enter::pid(first.pid(), NsKind::Uts))
+		.exec_shared(move || {
+			let lingering = read_dir("/proc/self/fd").unwrap()
+				.filter_map(|entry| entry.ok()?.path().read_link().ok())
+				.filter(|target| joined.iter().any(|ns| target.to_string_lossy() == ns.as_str()))
+				.count();
+			LINGERING.store(lingering, Ordering::SeqCst);
+		})
+		.unwrap();
+	child.wait().unwrap();
+	first.signal(SIGKILL).unwrap();
+
+	assert_eq!(LINGERING.load(Ordering::SeqCst), 0);
+}
+
+/// Test that the function is run in an entered PID namespace.
+#[test]
+fn enter_pid_forks() {
+	use std::io::Read;
+	use libc::pause;
+	use ::namespace::{Pid, User};
+	use ::{Context, WaitStatus};
+
+	let first = Context::new()
+		.with(User::new().map_root_user())
+		.with(Pid::new())
+		.exec_private(|| loop {
+			unsafe { pause() };
+		})
+		.unwrap();
+
+	// The exit status is the depth of the PID namespace of the function.
+	let status = Context::new()
+		.with(Enter::pid(first.pid(), NsKind::User))
+		.with(Enter::pid(first.pid(), NsKind::Pid))
+		.exec_blocking(|| {
+			let mut status = String::new();
+			File::open("/proc/self/status").unwrap().read_to_string(&mut status).unwrap();
+			let depth = status.lines()
+				.find(|line| line.starts_with("NStgid:"))
+				.map_or(0, |line| line.split_whitespace().count() - 1);
+			unsafe { _exit(depth as c_int) };
+		})
+		.unwrap();
+	first.signal(SIGKILL).unwrap();
+
+	assert_eq!(status, WaitStatus::Exited(2));
+}
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use libc::setns;
+
+use ::error::*;
+use super::{CloneFlags, NsKind};
+
+/// Temporarily join a namespace from the calling thread.
+///
+/// The namespace of the given kind of the calling thread is opened before
+/// the new one is joined and is rejoined when the guard is dropped, so that
+/// the caller returns to its own namespace even if an error occurs while it
+/// is in the other one. This allows the parent to configure a child from
+/// inside its namespaces, such as configuring a network interface in the
+/// network namespace of the child. Use [`leave`](#method.leave) to find out
+/// whether the original namespace was rejoined; a failure when the guard is
+/// dropped is only logged.
+///
+/// ```rust
+/// let netns = child.namespace_fd(NsKind::Network)?;
+/// let guard = NamespaceGuard::enter(&netns, NsKind::Network)?;
+/// Command::new("ip").args(&["link", "set", "veth1", "up"]).status()?;
+/// guard.leave()?;
+/// ```
+///
+/// Namespaces are joined by the calling thread alone. As for
+/// [`Enter`](struct.Enter.html), joining a PID or time namespace only
+/// changes the namespace of the children the thread creates afterwards. A
+/// mount namespace can't be joined by a thread that shares its file-system
+/// attributes with other threads, which includes every thread of a
+/// multi-threaded process. A user namespace can't be joined, as the
+/// original user namespace could not be rejoined.
+#[derive(Debug)]
+pub struct NamespaceGuard {
+	original: Option<File>,
+	kind: NsKind,
+}
+
+impl NamespaceGuard {
+	/// Join the namespace of the given kind referred to by an open file until
+	/// the guard is dropped.
+	pub fn enter(namespace: &File, kind: NsKind) -> Result<NamespaceGuard> {
+		if kind == NsKind::User {
+			return Err(ErrorKind::UnsupportedNamespace(kind.name().to_owned()).into());
+		}
+
+		// The namespace that children are created in is restored for the
+		// kinds of namespace that the thread itself never leaves.
+		let name = match kind {
+			NsKind::Pid => "pid_for_children",
+			NsKind::Time => "time_for_children",
+			_ => kind.name(),
+		};
+		let original = File::open(Path::new("/proc/thread-self/ns").join(name))?;
+		join(namespace, kind)?;
+
+		Ok(NamespaceGuard {
+			original: Some(original),
+			kind,
+		})
+	}
+
+	/// Rejoin the original namespace of the calling thread.
+	pub fn leave(mut self) -> Result<()> {
+		match self.original.take() {
+			Some(original) => join(&original, self.kind),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Drop for NamespaceGuard {
+	fn drop(&mut self) {
+		if let Some(original) = self.original.take() {
+			if let Err(err) = join(&original, self.kind) {
+				warn!("failed to rejoin original {} namespace: {}", self.kind.name(), err);
+			}
+		}
+	}
+}
+
+/// Join the namespace referred to by an open file.
+fn join(namespace: &File, kind: NsKind) -> Result<()> {
+	match unsafe { setns(namespace.as_raw_fd(), kind.clone_flag()) } {
+		-1 => Err(errno!(Setns, CloneFlags(kind.clone_flag()))),
+		_ => Ok(()),
+	}
+}
+
+/// Test that the original namespace is rejoined when the guard is left or
+/// dropped.
+#[test]
+fn guard_rejoins_namespace() {
+	use std::fs::metadata;
+	use std::os::unix::fs::MetadataExt;
+	use libc::{SIGKILL, pause};
+	use ::namespace::Uts;
+	use ::Context;
+
+	let current = || metadata("/proc/thread-self/ns/uts").unwrap().ino();
+	let original = current();
+
+	let child = Context::new()
+		.with(Uts::new())
+		.exec_private(|| loop {
+			unsafe { pause() };
+		})
+		.unwrap();
+	let uts = child.namespace_fd(NsKind::Uts).unwrap();
+	let joined = child.namespace_id(NsKind::Uts).unwrap();
+
+	let guard = NamespaceGuard::enter(&uts, NsKind::Uts).unwrap();
+	let entered = current();
+	guard.leave().unwrap();
+	let left = current();
+
+	let guard = NamespaceGuard::enter(&uts, NsKind::Uts).unwrap();
+	drop(guard);
+	let dropped = current();
+	child.signal(SIGKILL).unwrap();
+
+	assert_eq!(entered, joined);
+	assert_eq!(left, original);
+	assert_eq!(dropped, original);
+}
@@ -3,8 +3,6 @@ use libc::{
 	c_int,
 };
 
-use ::error::*;
-use ::Child;
 use super::Namespace;
 
 /// Inter-Process Communication.
@@ -14,7 +12,7 @@ use super::Namespace;
 /// within an IPC namespace can connect to any other process in the same IPC
 /// namespace that exposes one of these mechanisms without having any
 /// information of these processes existing.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Ipc {}
 
 impl Ipc {
@@ -24,6 +22,12 @@ impl Ipc {
 	}
 }
 
+impl Default for Ipc {
+	fn default() -> Ipc {
+		Ipc::new()
+	}
+}
+
 impl Namespace for Ipc {
 	fn clone_flag(&self) -> c_int {
 		CLONE_NEWIPC
@@ -0,0 +1,183 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use libc::{
+	CLONE_NEWCGROUP,
+	CLONE_NEWIPC,
+	CLONE_NEWNET,
+	CLONE_NEWNS,
+	CLONE_NEWPID,
+	CLONE_NEWUSER,
+	CLONE_NEWUTS,
+	c_int,
+};
+
+use ::error::*;
+use super::{ControlGroup, EmptyMount, Ipc, Namespace, Network, Pid, User, Uts};
+
+/// Create a new time namespace (not currently in libc).
+const CLONE_NEWTIME: c_int = 0x80;
+
+/// The kinds of namespace provided by Linux.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NsKind {
+	/// Control group namespace.
+	Cgroup,
+	/// Inter-process communication namespace.
+	Ipc,
+	/// Mount namespace.
+	Mount,
+	/// Network namespace.
+	Network,
+	/// Process ID namespace.
+	Pid,
+	/// Time namespace (Linux 5.6).
+	Time,
+	/// User namespace.
+	User,
+	/// Unix Timesharing System namespace.
+	Uts,
+}
+
+impl NsKind {
+	/// Every kind of namespace.
+	pub const ALL: [NsKind; 8] = [
+		NsKind::Cgroup,
+		NsKind::Ipc,
+		NsKind::Mount,
+		NsKind::Network,
+		NsKind::Pid,
+		NsKind::Time,
+		NsKind::User,
+		NsKind::Uts,
+	];
+
+	/// The flag used to create the namespace with `clone(2)`.
+	///
+	/// The flag for time namespaces overlaps the termination signal passed to
+	/// `clone(2)`, so it can only be used with `unshare(2)` and `setns(2)`.
+	pub fn clone_flag(&self) -> c_int {
+		match *self {
+			NsKind::Cgroup => CLONE_NEWCGROUP,
+			NsKind::Ipc => CLONE_NEWIPC,
+			NsKind::Mount => CLONE_NEWNS,
+			NsKind::Network => CLONE_NEWNET,
+			NsKind::Pid => CLONE_NEWPID,
+			NsKind::Time => CLONE_NEWTIME,
+			NsKind::User => CLONE_NEWUSER,
+			NsKind::Uts => CLONE_NEWUTS,
+		}
+	}
+
+	/// The name of the namespace in `/proc/<pid>/ns`.
+	pub fn name(&self) -> &'static str {
+		match *self {
+			NsKind::Cgroup => "cgroup",
+			NsKind::Ipc => "ipc",
+			NsKind::Mount => "mnt",
+			NsKind::Network => "net",
+			NsKind::Pid => "pid",
+			NsKind::Time => "time",
+			NsKind::User => "user",
+			NsKind::Uts => "uts",
+		}
+	}
+}
+
+/// Parse the kind of a namespace from its name.
+///
+/// Namespaces may be named by their clone flag (`CLONE_NEWPID`), their name in
+/// `/proc/<pid>/ns` (`pid`, `mnt`, `net`), or the name of their type (`mount`,
+/// `network`). Names are case-sensitive.
+impl<'a> TryFrom<&'a str> for NsKind {
+	type Error = Error;
+
+	fn try_from(name: &'a str) -> Result<NsKind> {
+		Ok(match name {
+			"CLONE_NEWCGROUP" | "cgroup" => NsKind::Cgroup,
+			"CLONE_NEWIPC" | "ipc" => NsKind::Ipc,
+			"CLONE_NEWNS" | "mnt" | "mount" => NsKind::Mount,
+			"CLONE_NEWNET" | "net" | "network" => NsKind::Network,
+			"CLONE_NEWPID" | "pid" => NsKind::Pid,
+			"CLONE_NEWTIME" | "time" => NsKind::Time,
+			"CLONE_NEWUSER" | "user" => NsKind::User,
+			"CLONE_NEWUTS" | "uts" => NsKind::Uts,
+			_ => return Err(ErrorKind::UnknownNamespace(name.to_owned()).into()),
+		})
+	}
+}
+
+/// Create a namespace with its default configuration from its name.
+///
+/// Names are parsed as for [`NsKind`](enum.NsKind.html). Time namespaces are
+/// recognised but can't be created.
+///
+/// ```rust
+/// let namespaces = ["pid", "CLONE_NEWNET", "uts"]
+///     .iter()
+///     .map(|name| Box::<dyn Namespace>::try_from(*name))
+///     .collect::<Result<Vec<_>>>()?;
+/// ```
+impl<'a> TryFrom<&'a str> for Box<dyn Namespace> {
+	type Error = Error;
+
+	fn try_from(name: &'a str) -> Result<Box<dyn Namespace>> {
+		Ok(match NsKind::try_from(name)? {
+			NsKind::Cgroup => Box::new(ControlGroup::new()),
+			NsKind::Ipc => Box::new(Ipc::new()),
+			NsKind::Mount => Box::new(EmptyMount::new()),
+			NsKind::Network => Box::new(Network::new()),
+			NsKind::Pid => Box::new(Pid::new()),
+			NsKind::User => Box::new(User::new()),
+			NsKind::Uts => Box::new(Uts::new()),
+			NsKind::Time => return Err(ErrorKind::UnsupportedNamespace(name.to_owned()).into()),
+		})
+	}
+}
+
+/// Determine if new namespaces of the given kind can be created.
+///
+/// A namespace is supported if the kernel provides it (it appears in
+/// `/proc/self/ns`) and creating it has not been disabled by setting
+/// `/proc/sys/user/max_<name>_namespaces` to zero. This only reads from
+/// `/proc` so is cheap to call. It does not check whether the caller has the
+/// privileges needed to create the namespace.
+///
+/// ```rust
+/// if !supported(NsKind::User) {
+///     eprintln!("user namespaces are not available");
+/// }
+/// ```
+pub fn supported(kind: NsKind) -> bool {
+	if !Path::new("/proc/self/ns").join(kind.name()).exists() {
+		return false;
+	}
+
+	let max = format!("/proc/sys/user/max_{}_namespaces", kind.name());
+	let mut text = String::new();
+	match File::open(max).and_then(|mut file| file.read_to_string(&mut text)) {
+		Ok(_) => text.trim() != "0",
+		Err(_) => true,
+	}
+}
+
+/// Test parsing the names of namespaces.
+#[test]
+fn parse_kind() {
+	assert_eq!(NsKind::try_from("CLONE_NEWPID").unwrap(), NsKind::Pid);
+	assert_eq!(NsKind::try_from("CLONE_NEWNS").unwrap(), NsKind::Mount);
+	assert_eq!(NsKind::try_from("net").unwrap(), NsKind::Network);
+	assert_eq!(NsKind::try_from("mount").unwrap(), NsKind::Mount);
+
+	for name in &["", "PID", "NEWPID", "CLONE_NEWpid", "CLONE_NEWFOO", "process"] {
+		match NsKind::try_from(*name) {
+			Err(Error(ErrorKind::UnknownNamespace(_), _)) => {}
+			result => panic!("unexpected result {:?} for {:?}", result, name),
+		}
+	}
+
+	assert_eq!(Box::<dyn Namespace>::try_from("uts").unwrap().clone_flag(), CLONE_NEWUTS);
+	assert!(Box::<dyn Namespace>::try_from("time").is_err());
+}
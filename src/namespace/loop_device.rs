@@ -0,0 +1,103 @@
+//! Loop devices used to mount image files (see `loop(4)`).
+
+use std::ffi::{CString, OsStr};
+use std::fs::{File, OpenOptions};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+
+use libc::{
+	EBUSY,
+	c_ulong,
+	ioctl,
+};
+
+use ::error::*;
+
+// Loop device requests (see `loop(4)`).
+const LOOP_SET_FD: c_ulong = 0x4c00;
+const LOOP_CLR_FD: c_ulong = 0x4c01;
+const LOOP_SET_STATUS64: c_ulong = 0x4c04;
+const LOOP_CTL_GET_FREE: c_ulong = 0x4c82;
+
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_NAME_SIZE: usize = 64;
+const LO_KEY_SIZE: usize = 32;
+
+/// Attempts to attach an image before giving up on finding a free device.
+const ATTACH_ATTEMPTS: usize = 8;
+
+/// The status of a loop device.
+#[repr(C)]
+struct LoopInfo64 {
+	lo_device: u64,
+	lo_inode: u64,
+	lo_rdevice: u64,
+	lo_offset: u64,
+	lo_sizelimit: u64,
+	lo_number: u32,
+	lo_encrypt_type: u32,
+	lo_encrypt_key_size: u32,
+	lo_flags: u32,
+	lo_file_name: [u8; LO_NAME_SIZE],
+	lo_crypt_name: [u8; LO_NAME_SIZE],
+	lo_encrypt_key: [u8; LO_KEY_SIZE],
+	lo_init: [u64; 2],
+}
+
+/// A loop device with an image attached.
+#[derive(Clone, Debug)]
+pub struct LoopDevice {
+	path: CString,
+}
+
+impl LoopDevice {
+	/// Attach an image to a free loop device, read-only.
+	///
+	/// The device is detached automatically once the returned file and every
+	/// mount of the device have been closed.
+	pub fn attach(image: &CString) -> Result<(LoopDevice, File)> {
+		let image_file = File::open(OsStr::from_bytes(image.as_bytes()))?;
+		let control = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open("/dev/loop-control")?;
+
+		// Another process may claim the free device before it is attached.
+		for _ in 0..ATTACH_ATTEMPTS {
+			let number = unsafe { ioctl(control.as_raw_fd(), LOOP_CTL_GET_FREE) };
+			if number == -1 {
+				return Err(ErrorKind::NoFreeLoopDevice.into());
+			}
+
+			let path = format!("/dev/loop{}", number);
+			let device = File::open(&path)?;
+			if unsafe { ioctl(device.as_raw_fd(), LOOP_SET_FD, image_file.as_raw_fd()) } == -1 {
+				if ::errno::errno().0 == EBUSY {
+					continue;
+				}
+				return Err(errno!(LoopDevice, image.clone()));
+			}
+
+			// The device is read-only because the image is opened read-only;
+			// `LOOP_SET_STATUS64` can't change whether it is read-only.
+			let mut info: LoopInfo64 = unsafe { mem::zeroed() };
+			info.lo_flags = LO_FLAGS_AUTOCLEAR;
+			if unsafe { ioctl(device.as_raw_fd(), LOOP_SET_STATUS64, &info) } == -1 {
+				let err = errno!(LoopDevice, image.clone());
+				unsafe { ioctl(device.as_raw_fd(), LOOP_CLR_FD) };
+				return Err(err);
+			}
+
+			debug!("attached {:?} to {}", image, path);
+			return Ok((LoopDevice { path: CString::new(path)? }, device));
+		}
+
+		Err(ErrorKind::NoFreeLoopDevice.into())
+	}
+
+	/// The path of the device.
+	pub fn path(&self) -> &CString {
+		&self.path
+	}
+}
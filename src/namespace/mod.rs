@@ -13,24 +13,80 @@
 //! * [Process IDs](struct.Pid.html)
 //! * [Users and Groups](struct.User.html)
 //! * [Unix Timesharing System](struct.Uts.html)
+//!
+//! # Custom namespaces
+//!
+//! Anything that implements [`Namespace`](trait.Namespace.html) (and `Clone`)
+//! can be added to a context with
+//! [`Context::with`](../struct.Context.html#method.with). Each configuration
+//! added to a context is applied in the following phases, in the order they
+//! were added to the context:
+//!
+//! 1. [`prepare`](trait.Namespace.html#method.prepare) in the parent before
+//!    the child is created;
+//! 1. [`external_config`](trait.Namespace.html#method.external_config) in
+//!    the parent once the child has been created with the combined
+//!    [`clone_flag`](trait.Namespace.html#method.clone_flag)s;
+//! 1. [`internal_config`](trait.Namespace.html#method.internal_config) in
+//!    the child before it runs; and
+//! 1. [`cleanup`](trait.Namespace.html#method.cleanup) in the parent once
+//!    the child has been dropped.
+//!
+//! ```rust
+//! #[derive(Clone, Debug)]
+//! struct Hello;
+//!
+//! impl Namespace for Hello {
+//!     fn internal_config(&mut self) -> Result<()> {
+//!         println!("hello from the child");
+//!         Ok(())
+//!     }
+//! }
+//!
+//! Context::new().with(Hello).exec_private(|| {})?.wait()?;
+//! ```
+//!
+//! See `examples/custom_namespace.rs` for a complete example.
 
+mod chroot;
+mod clone_flags;
 mod control_group;
+mod devices;
+mod enter;
+mod guard;
 mod ipc;
+mod kind;
+mod loop_device;
 mod mount;
+mod mountinfo;
 mod network;
 mod pid;
+mod resolve;
+mod sysctl;
 mod user;
 mod uts;
 
+use std::any::Any;
+use std::fmt::Debug;
+use std::path::Path;
+
 use libc::{
 	c_int,
 };
 
-pub use self::control_group::ControlGroup;
+pub use self::chroot::Chroot;
+pub use self::clone_flags::CloneFlags;
+pub use self::control_group::{CgroupLimits, CgroupNamespace, ControlGroup};
+pub use self::devices::Devices;
+pub use self::enter::Enter;
+pub use self::guard::NamespaceGuard;
 pub use self::ipc::Ipc;
-pub use self::mount::{Mount, EmptyMount};
+pub use self::kind::{NsKind, supported};
+pub use self::mount::{Mount, EmptyMount, RootFs, UnmountFlags};
+pub use self::mountinfo::{MountEntry, Propagation, mounts as mountinfo};
 pub use self::network::Network;
 pub use self::pid::Pid;
+pub use self::sysctl::Sysctl;
 pub use self::user::User;
 pub use self::uts::Uts;
 
@@ -42,7 +98,7 @@ use ::Child;
 /// This configures the environment before a namespace is entered and after is
 /// has been entered and also provides flags for the `clone` syscall to create a
 /// new instance of a given namespace.
-pub trait Namespace: NamespaceClone {
+pub trait Namespace: NamespaceClone + Debug {
 	/// Get the flag needed for clone to create new namespace.
 	///
 	/// See `clone(2)` and `namespaces(7)` for more information.
@@ -50,6 +106,25 @@ pub trait Namespace: NamespaceClone {
 		0
 	}
 
+	/// Get the flags of the namespaces this configuration depends on.
+	///
+	/// These namespaces must be created or entered by the context containing
+	/// this configuration, either by this configuration or another, for it to
+	/// be applied in isolation from the parent. See
+	/// [`Context::validate`](../struct.Context.html#method.validate).
+	fn required_flags(&self) -> c_int {
+		0
+	}
+
+	/// Get the flags of the existing namespaces this configuration joins.
+	///
+	/// Configuration added to a context after this configuration may require
+	/// these namespaces instead of new ones, and no new namespaces of these
+	/// kinds are created by the context.
+	fn entered_flags(&self) -> c_int {
+		0
+	}
+
 	/// Configure system prior to creating the namespace.
 	///
 	/// This executes all of the changes needed to be made external to the
@@ -73,27 +148,57 @@ pub trait Namespace: NamespaceClone {
 	fn external_config(&self, _child: &Child) -> Result<()> {
 		Ok(())
 	}
+
+	/// Set the root directory that relative paths are resolved against.
+	///
+	/// This is called on each namespace in a context that has a root
+	/// directory before it is prepared. See
+	/// [`Context::root`](../struct.Context.html#method.root).
+	fn set_root(&mut self, _root: &Path) {}
+
+	/// Undo any external configuration once the child has exited.
+	///
+	/// This is run from the parent when the child is dropped, including while
+	/// unwinding from a panic, and may be run more than once. Implementations
+	/// must be idempotent and must not panic.
+	fn cleanup(&mut self) -> Result<()> {
+		Ok(())
+	}
 }
 
-/// This is a trait that allows for a `Namespace` to clone itself into a new
-/// box.
+/// Clone a boxed namespace, which allows for cloning of `Context`s.
 ///
-/// This is needed to allow for cloning of `Context`s.
+/// This is implemented for every `Namespace` that is also `Clone`.
 pub trait NamespaceClone {
-	fn box_clone(&self) -> Box<Namespace>;
+	/// Clone the namespace into a new box.
+	fn box_clone(&self) -> Box<dyn Namespace>;
+
+	/// Get the namespace as `Any` so that it can be downcast.
+	fn as_any(&self) -> &dyn Any;
+
+	/// Get the namespace as mutable `Any` so that it can be downcast.
+	fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 impl<N> NamespaceClone for N
 where
 	N: Namespace + Clone + 'static
 {
-	fn box_clone(&self) -> Box<Namespace> {
+	fn box_clone(&self) -> Box<dyn Namespace> {
 		Box::new(self.clone())
 	}
+
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		self
+	}
 }
 
-impl Clone for Box<Namespace> {
-	fn clone(&self) -> Box<Namespace> {
+impl Clone for Box<dyn Namespace> {
+	fn clone(&self) -> Box<dyn Namespace> {
 		self.box_clone()
 	}
 }
@@ -1,10 +1,19 @@
-use std::ffi::CString;
-use std::fs::create_dir_all;
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::fs::{File, Permissions, create_dir_all, set_permissions};
+use std::mem;
+use std::ops::BitOr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::ptr;
 
 use libc::{
 	CLONE_NEWNS,
+	MNT_DETACH,
+	MNT_EXPIRE,
+	MNT_FORCE,
 	MS_BIND,
 	MS_DIRSYNC,
 	MS_MANDLOCK,
@@ -25,17 +34,28 @@ use libc::{
 	MS_STRICTATIME,
 	MS_SYNCHRONOUS,
 	MS_UNBINDABLE,
+	_SC_PAGE_SIZE,
 	c_int,
 	c_ulong,
 	c_char,
+	c_void,
+	chown,
+	gid_t,
 	mount,
-	umount,
+	sysconf,
+	uid_t,
+	umount2,
+	uname,
+	utsname,
 };
 
 // TODO: MS_LAZYATIME (not currently in libc)
 
 use ::error::*;
 use super::Namespace;
+use super::loop_device::LoopDevice;
+use super::mountinfo::{self, Propagation};
+use super::resolve;
 
 /// A new mount namespace with no immediate mounts.
 ///
@@ -52,6 +72,12 @@ impl EmptyMount {
 	}
 }
 
+impl Default for EmptyMount {
+	fn default() -> EmptyMount {
+		EmptyMount::new()
+	}
+}
+
 impl Namespace for EmptyMount {
 	fn clone_flag(&self) -> c_int {
 		CLONE_NEWNS
@@ -72,7 +98,7 @@ impl Namespace for EmptyMount {
 /// ```rust
 /// DirMount::bind("/proc", "/tmp/jail/proc").read_only().mount();
 /// ```
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Mount {
 	src: Option<CString>,
 	target: CString,
@@ -81,6 +107,28 @@ pub struct Mount {
 	mk_target: bool,
 	umount: bool,
 	mounted: Option<CString>,
+	relative: bool,
+	extra: Option<Box<Extra>>,
+	verify: bool,
+}
+
+/// Settings used by few mounts, kept separately to keep mounts small.
+#[derive(Clone, Debug, Default)]
+struct Extra {
+	data: Vec<String>,
+	loop_image: Option<LoopImage>,
+	target_mode: Option<u32>,
+	target_owner: Option<(uid_t, gid_t)>,
+	unmount_flags: UnmountFlags,
+	preserve_flags: bool,
+	no_symlinks: bool,
+}
+
+/// An image file mounted through a loop device.
+#[derive(Clone, Debug)]
+struct LoopImage {
+	image: CString,
+	device: Option<LoopDevice>,
 }
 
 impl Mount {
@@ -89,52 +137,244 @@ impl Mount {
 	/// The file system type must be explicitly provided as along with the
 	/// target and the source.
 	///
+	/// A `tmpfs` mounted this way may grow to half of the memory of the host
+	/// unless a `size` option is given with [`data`](#method.data), so a
+	/// child can exhaust the memory of the host by filling it. Use
+	/// [`Mount::tmpfs`](#method.tmpfs) for a bounded `tmpfs`.
+	///
 	/// ```rust
 	/// Mount::new("/dev/sda1", "/mnt", "ext4").mount();
 	/// ```
-	pub fn new(src: &str, target: &str, fstype: &str) -> Result<Mount> {
+	pub fn new<S: AsRef<Path>, T: AsRef<Path>>(src: S, target: T, fstype: &str) -> Result<Mount> {
 		Ok(Mount {
-			src: Some(CString::new(src.to_owned())?),
-			target: CString::new(target.to_owned())?,
+			src: Some(path_cstring(src)?),
+			target: path_cstring(target)?,
 			fstype: Some(CString::new(fstype.to_owned())?),
 			flags: 0,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
+	/// Mount the device with the given file-system UUID to `target`.
+	///
+	/// The device is resolved through `/dev/disk/by-uuid` when the mount is
+	/// constructed, returning an error if no such device exists.
+	///
+	/// ```rust
+	/// Mount::by_uuid("0a3407de-014b-458b-b5c1-848e92a327a3", "/mnt", "ext4")?.mount();
+	/// ```
+	pub fn by_uuid<T: AsRef<Path>>(uuid: &str, target: T, fstype: &str) -> Result<Mount> {
+		let device = resolve_device("by-uuid", uuid)?;
+		Mount::new(&device, target, fstype)
+	}
+
+	/// Mount the device with the given file-system label to `target`.
+	///
+	/// The device is resolved through `/dev/disk/by-label` when the mount is
+	/// constructed, returning an error if no such device exists.
+	///
+	/// ```rust
+	/// Mount::by_label("data", "/mnt", "ext4")?.mount();
+	/// ```
+	pub fn by_label<T: AsRef<Path>>(label: &str, target: T, fstype: &str) -> Result<Mount> {
+		let device = resolve_device("by-label", &udev_escape(label))?;
+		Mount::new(&device, target, fstype)
+	}
+
+	/// Bind a directory to a mount point relative to the root of the context.
+	///
+	/// The target is joined onto the directory given to
+	/// [`Context::root`](../struct.Context.html#method.root) when the child is
+	/// created. If the context has no root directory, the target is used as
+	/// given.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .root("/tmp/jail")
+	///     .with(Mount::relative("/lib", "lib")?.read_only());
+	/// ```
+	pub fn relative<S: AsRef<Path>, T: AsRef<Path>>(src: S, target: T) -> Result<Mount> {
+		let mut mount = Mount::bind(src, target)?;
+		mount.relative = true;
+		Ok(mount)
+	}
+
+	/// Mount an image file read-only at `target` using a loop device.
+	///
+	/// A free loop device is attached to the image when the mount is made.
+	/// The kernel detaches it once it is no longer mounted anywhere, rather
+	/// than it being detached by path, which could detach a device that has
+	/// since been reused for another image. Attaching a loop device
+	/// requires `CAP_SYS_ADMIN` in the initial user namespace.
+	///
+	/// ```rust
+	/// Mount::loop_image("/srv/app.squashfs", "/tmp/jail", "squashfs")?.mount();
+	/// ```
+	pub fn loop_image<I: AsRef<Path>, T: AsRef<Path>>(image: I, target: T, fstype: &str) -> Result<Mount> {
+		let mut mount = Mount::new(image.as_ref(), target, fstype)?.read_only();
+		mount.extra_mut().loop_image = Some(LoopImage {
+			image: path_cstring(image)?,
+			device: None,
+		});
+		Ok(mount)
+	}
+
+	/// Mount a new `proc` file-system at `target`.
+	///
+	/// The file-system shows the processes of the PID namespace of the
+	/// process that mounts it and is mounted without support for set-user-ID
+	/// programs, devices, or execution. Use [`hidepid`](#method.hidepid) and
+	/// [`subset_pid`](#method.subset_pid) to limit what it exposes.
+	///
+	/// ```rust
+	/// Mount::proc("/tmp/jail/proc")?.hidepid(2)?.subset_pid()?.mount();
+	/// ```
+	pub fn proc<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		Ok(Mount::new("proc", target, "proc")?
+			.no_setuid()
+			.no_devices()
+			.no_execute())
+	}
+
+	/// Mount a new `tmpfs` of at most `size` bytes at `target`.
+	///
+	/// The file-system is writable by every user, with the sticky bit set as
+	/// for `/tmp`. Writes that would grow it beyond `size`, rounded up to a
+	/// whole page, fail with `ENOSPC`. A size of zero would leave the
+	/// file-system unbounded, so is rejected with
+	/// [`InvalidMountData`](../enum.ErrorKind.html#variant.InvalidMountData).
+	///
+	/// ```rust
+	/// Mount::tmpfs("/tmp/jail/tmp", 64 << 20)?.no_setuid().no_devices().mount();
+	/// ```
+	pub fn tmpfs<T: AsRef<Path>>(target: T, size: u64) -> Result<Mount> {
+		if size == 0 {
+			return Err(ErrorKind::InvalidMountData("size=0".to_owned()).into());
+		}
+
+		Ok(Mount::new("tmpfs", target, "tmpfs")?
+			.data(&format!("size={}", size))
+			.data("mode=1777"))
+	}
+
+	/// Mount a new instance of the `devpts` file-system at `target`.
+	///
+	/// Pseudo-terminals allocated through the `ptmx` device of the instance
+	/// are separate from those of the host and of any other instance, so
+	/// interactive programs in the jail get their own terminals. The `ptmx`
+	/// device of the instance can be opened by every user and new terminals
+	/// are only writable by their group. Since Linux 4.7 every mount of
+	/// `devpts` is a new instance, so the deprecated `newinstance` option is
+	/// only given on older kernels. The file-system is mounted without
+	/// support for set-user-ID programs or execution.
+	///
+	/// Use [`Mount::ptmx`](#method.ptmx) so that `/dev/ptmx` allocates
+	/// terminals from the instance.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(User::new().map_root_user())
+	///     .with(EmptyMount::new())
+	///     .with(Mount::devpts("/dev/pts")?)
+	///     .with(Mount::ptmx("/dev/pts", "/dev/ptmx")?);
+	/// ```
+	pub fn devpts<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		let mut mount = Mount::new("devpts", target, "devpts")?
+			.no_setuid()
+			.no_execute();
+		if !devpts_always_new() {
+			mount = mount.data("newinstance");
+		}
+
+		Ok(mount.data("ptmxmode=0666").data("mode=0620"))
+	}
+
+	/// Bind the `ptmx` device of the `devpts` file-system mounted at
+	/// `devpts` to `target`.
+	///
+	/// The target, usually `/dev/ptmx`, must already exist. On kernels before
+	/// Linux 4.7, opening `/dev/ptmx` always allocates a terminal from the
+	/// `devpts` instance of the host, so the device of the new instance must
+	/// be bound over it.
+	pub fn ptmx<S: AsRef<Path>, T: AsRef<Path>>(devpts: S, target: T) -> Result<Mount> {
+		Mount::bind(devpts.as_ref().join("ptmx"), target)
+	}
+
+	/// Hide the contents of the directory at `target`.
+	///
+	/// An empty, read-only `tmpfs` limited to a single page is mounted over
+	/// the directory, without support for set-user-ID programs, devices, or
+	/// execution, so that nothing beneath it can be seen from the namespace.
+	/// The target is created if it does not exist.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(EmptyMount::new())
+	///     .with(Mount::mask("/root")?)
+	///     .with(Mount::mask("/var/secrets")?);
+	/// ```
+	pub fn mask<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		Ok(Mount::new("tmpfs", target, "tmpfs")?
+			.read_only()
+			.no_setuid()
+			.no_devices()
+			.no_execute()
+			.make_target_dir()
+			.data("size=4k"))
+	}
+
 	/// Update the mount flags on an existing mount.
 	///
+	/// The kernel replaces the per-mount flags of the mount with those given,
+	/// so any flags that are not given are cleared. Use
+	/// [`preserve_flags`](#method.preserve_flags) to keep the existing flags.
+	///
 	/// ```rust
 	/// Mount::remount("/home").read_only().mount();
 	/// ```
-	pub fn remount(target: &str) -> Result<Mount> {
+	pub fn remount<T: AsRef<Path>>(target: T) -> Result<Mount> {
 		Ok(Mount {
 			src: None,
-			target: CString::new(target.to_owned())?,
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_REMOUNT,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
 	/// Bind a directory to a new mount point.
 	///
+	/// The kernel ignores per-mount flags, such as
+	/// [`read_only`](#method.read_only) and
+	/// [`no_access_time`](#method.no_access_time), when binding, so if any are
+	/// given the new mount is remounted with them along with the flags it
+	/// inherited from its source.
+	///
 	/// ```rust
 	/// Mount::bind("/lib", "/tmp/jail/lib").mount();
 	/// ```
-	pub fn bind(src: &str, target: &str) -> Result<Mount> {
+	pub fn bind<S: AsRef<Path>, T: AsRef<Path>>(src: S, target: T) -> Result<Mount> {
 		Ok(Mount {
-			src: Some(CString::new(src.to_owned())?),
-			target: CString::new(target.to_owned())?,
+			src: Some(path_cstring(src)?),
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_BIND,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -144,15 +384,18 @@ impl Mount {
 	/// ```rust
 	/// Mount::recursive_bind("/proc", "/tmp/jail/proc").mount();
 	/// ```
-	pub fn recursive_bind(src: &str, target: &str) -> Result<Mount> {
+	pub fn recursive_bind<S: AsRef<Path>, T: AsRef<Path>>(src: S, target: T) -> Result<Mount> {
 		Ok(Mount {
-			src: Some(CString::new(src.to_owned())?),
-			target: CString::new(target.to_owned())?,
+			src: Some(path_cstring(src)?),
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_BIND | MS_REC,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -161,15 +404,18 @@ impl Mount {
 	/// This ensures that _mount_ and _unmount_ events that occur within the
 	/// subtree of this mount point may propogate to peer mounts within the
 	/// namespace.
-	pub fn shared(target: &str) -> Result<Mount> {
+	pub fn shared<T: AsRef<Path>>(target: T) -> Result<Mount> {
 		Ok(Mount {
 			src: None,
-			target: CString::new(target.to_owned())?,
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_SHARED,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -179,15 +425,18 @@ impl Mount {
 	/// This ensures that _mount_ and _unmount_ events that occur within the
 	/// subtree of this mountpoint will not propogate to peer mounts within the
 	/// namespace.
-	pub fn private(target: &str) -> Result<Mount> {
+	pub fn private<T: AsRef<Path>>(target: T) -> Result<Mount> {
 		Ok(Mount {
 			src: None,
-			target: CString::new(target.to_owned())?,
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_PRIVATE,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -195,15 +444,18 @@ impl Mount {
 	///
 	/// This ensures that _mount_ and _unmount_ events never propogate out of
 	/// the subtree from the mount point but events will propogate into it.
-	pub fn slave(target: &str) -> Result<Mount> {
+	pub fn slave<T: AsRef<Path>>(target: T) -> Result<Mount> {
 		Ok(Mount {
 			src: None,
-			target: CString::new(target.to_owned())?,
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_SLAVE,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -212,28 +464,82 @@ impl Mount {
 	/// This has the same effect as [`Mount::private`](#method.provate) but
 	/// also ensures the mount point, and its children, can't be mounted as a
 	/// bind. Recursive bind mounts will simply have _unbindable_ mounts pruned.
-	pub fn unbindable(target: &str) -> Result<Mount> {
+	pub fn unbindable<T: AsRef<Path>>(target: T) -> Result<Mount> {
 		Ok(Mount {
 			src: None,
-			target: CString::new(target.to_owned())?,
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_UNBINDABLE,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
+	/// Update an existing mount point and all mounts in its subtree to be
+	/// _shared_.
+	///
+	/// This is the same as [`Mount::shared`](#method.shared) applied to
+	/// every mount in the subtree at once.
+	pub fn recursive_shared<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		let mut mount = Mount::shared(target)?;
+		mount.flags |= MS_REC;
+		Ok(mount)
+	}
+
+	/// Update an existing mount point and all mounts in its subtree to be
+	/// _private_.
+	///
+	/// This is the usual first step in isolating a new mount namespace, so
+	/// that nothing mounted within it propagates back to the parent.
+	///
+	/// ```rust
+	/// Mount::recursive_private("/")?.mount();
+	/// ```
+	pub fn recursive_private<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		let mut mount = Mount::private(target)?;
+		mount.flags |= MS_REC;
+		Ok(mount)
+	}
+
+	/// Update an existing mount point and all mounts in its subtree to be
+	/// _slaves_.
+	///
+	/// This is the same as [`Mount::slave`](#method.slave) applied to every
+	/// mount in the subtree at once.
+	pub fn recursive_slave<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		let mut mount = Mount::slave(target)?;
+		mount.flags |= MS_REC;
+		Ok(mount)
+	}
+
+	/// Update an existing mount point and all mounts in its subtree to be
+	/// _unbindable_.
+	///
+	/// This is the same as [`Mount::unbindable`](#method.unbindable) applied
+	/// to every mount in the subtree at once.
+	pub fn recursive_unbindable<T: AsRef<Path>>(target: T) -> Result<Mount> {
+		let mut mount = Mount::unbindable(target)?;
+		mount.flags |= MS_REC;
+		Ok(mount)
+	}
+
 	/// Move a mount from an existing mount point to a new mount point.
-	pub fn relocate(src: &str, target: &str) -> Result<Mount> {
+	pub fn relocate<S: AsRef<Path>, T: AsRef<Path>>(src: S, target: T) -> Result<Mount> {
 		Ok(Mount {
-			src: Some(CString::new(src.to_owned())?),
-			target: CString::new(target.to_owned())?,
+			src: Some(path_cstring(src)?),
+			target: path_cstring(target)?,
 			fstype: None,
 			flags: MS_MOVE,
 			mk_target: false,
 			umount: false,
 			mounted: None,
+			relative: false,
+			extra: None,
+			verify: false,
 		})
 	}
 
@@ -269,19 +575,18 @@ impl Mount {
 	/// Examples  of  workloads  where  this  option  could be of significant
 	/// benefit include frequent random writes to preallocated files, as well as
 	/// cases where the MS_STRICTATIME mount option is also enabled.
-	#[cfg(not(any))]
 	pub fn lazy_access_time(self) -> Mount {
 		// self.flags |= MS_LAZYATIME;
 		self
 	}
 
-	/// Do not update access times for (all types of) files on this mount.
+	/// Allow mandatory locks on files on this mount.
 	pub fn mandatory_locking(mut self) -> Mount {
 		self.flags |= MS_MANDLOCK;
 		self
 	}
 
-	/// Do not allow access to devices (special files) on this mount.
+	/// Do not update access times for (all types of) files on this mount.
 	pub fn no_access_time(mut self) -> Mount {
 		self.flags |= MS_NOATIME;
 		self
@@ -356,42 +661,341 @@ impl Mount {
 		self
 	}
 
+	/// Set the permissions of the target directory when it is created.
+	///
+	/// This only applies if the target is created by
+	/// [`make_target_dir`](#method.make_target_dir), in which case the
+	/// permissions are set before mounting, regardless of the umask.
+	///
+	/// ```rust
+	/// Mount::tmpfs("/tmp/jail/tmp", 64 << 20)?
+	///     .make_target_dir()
+	///     .target_mode(0o1777);
+	/// ```
+	pub fn target_mode(mut self, mode: u32) -> Mount {
+		self.extra_mut().target_mode = Some(mode);
+		self
+	}
+
+	/// Set the owner of the target directory when it is created.
+	///
+	/// This only applies if the target is created by
+	/// [`make_target_dir`](#method.make_target_dir), in which case the owner
+	/// is set before mounting.
+	pub fn target_owner(mut self, uid: uid_t, gid: gid_t) -> Mount {
+		self.extra_mut().target_owner = Some((uid, gid));
+		self
+	}
+
 	/// Unmount the target when finished.
+	///
+	/// A mount that was moved with [`relocate`](#method.relocate) is moved
+	/// back to its source instead. Mounts that only change an existing mount,
+	/// such as a [`remount`](#method.remount), are left in place.
 	pub fn unmount(mut self) -> Mount {
 		self.umount = true;
 		self
 	}
 
+	/// Set the flags used to unmount the target when finished.
+	///
+	/// The flags are passed to `umount2(2)` when a mount made with
+	/// [`unmount`](#method.unmount) is unmounted. By default, no flags are
+	/// used.
+	///
+	/// ```rust
+	/// Mount::new("server:/export", "/mnt", "nfs")?
+	///     .unmount()
+	///     .unmount_flags(UnmountFlags::FORCE | UnmountFlags::DETACH);
+	/// ```
+	pub fn unmount_flags(mut self, flags: UnmountFlags) -> Mount {
+		self.extra_mut().unmount_flags = flags;
+		self
+	}
+
+	/// Pass a file-system specific option to the mount.
+	///
+	/// Options are joined with commas and passed as the data argument of
+	/// `mount(2)`. Options that the file-system rejects are reported by
+	/// `mount(2)`, usually with `EINVAL`, in a
+	/// [`Mount`](../enum.ErrorKind.html#variant.Mount) error. The joined
+	/// options must fit within a page, including a terminating NUL, or
+	/// mounting fails with
+	/// [`MountDataTooLong`](../enum.ErrorKind.html#variant.MountDataTooLong)
+	/// before `mount(2)` is called.
+	///
+	/// ```rust
+	/// Mount::new("tmpfs", "/tmp/jail/tmp", "tmpfs")?.data("size=64m").data("mode=1777");
+	/// ```
+	pub fn data(mut self, option: &str) -> Mount {
+		self.extra_mut().data.push(option.to_owned());
+		self
+	}
+
+	/// Hide the processes of other users in a `proc` file-system.
+	///
+	/// At level `1`, the `/proc/<pid>` directories of processes of other
+	/// users can be listed but not accessed. At level `2`, they are hidden
+	/// entirely. Level `0` is the default, where every process is visible.
+	pub fn hidepid(self, level: u8) -> Result<Mount> {
+		if level > 2 {
+			return Err(ErrorKind::InvalidMountData(format!("hidepid={}", level)).into());
+		}
+
+		Ok(self.data(&format!("hidepid={}", level)))
+	}
+
+	/// Only show the processes in a `proc` file-system.
+	///
+	/// Every file and directory in `/proc` that is not specific to a process,
+	/// such as `/proc/sys` and `/proc/kcore`, is hidden. This requires Linux
+	/// 5.8 or later and fails with
+	/// [`UnsupportedMountData`](../enum.ErrorKind.html#variant.UnsupportedMountData)
+	/// on older kernels.
+	pub fn subset_pid(self) -> Result<Mount> {
+		if !kernel_at_least(5, 8) {
+			return Err(ErrorKind::UnsupportedMountData("subset=pid".to_owned()).into());
+		}
+
+		Ok(self.data("subset=pid"))
+	}
+
+	/// Check that the mount was made as specified after mounting.
+	///
+	/// Some mounts succeed without applying every flag, such as a bind mount
+	/// made read-only without a separate remount. Once mounted, the mount at
+	/// the target is looked up in `/proc/self/mountinfo` and its per-mount
+	/// options and propagation type are compared with the flags of the
+	/// mount, returning an error if they differ.
+	///
+	/// ```rust
+	/// Mount::bind("/lib", "/tmp/jail/lib")?.read_only().verify().mount()?;
+	/// ```
+	pub fn verify(mut self) -> Mount {
+		self.verify = true;
+		self
+	}
+
+	/// Keep the existing flags of the mount when it is remounted.
+	///
+	/// When the mount is made, the options of the mount at the target are
+	/// read from `/proc/self/mountinfo` and the corresponding flags are added
+	/// to those of the remount. This prevents, for example, making a
+	/// `noexec` mount read-only from allowing execution again. Flags can only
+	/// be added in this way; an existing access time setting is only kept if
+	/// no other access time setting is given. This has no effect on mounts
+	/// that are not remounts.
+	///
+	/// ```rust
+	/// Mount::remount("/tmp/jail")?.as_bind().read_only().preserve_flags().mount()?;
+	/// ```
+	pub fn preserve_flags(mut self) -> Mount {
+		self.extra_mut().preserve_flags = true;
+		self
+	}
+
+	/// Refuse to follow symbolic links in the target when mounting.
+	///
+	/// The target is opened with `openat2(2)` and `RESOLVE_NO_SYMLINKS`, and
+	/// the mount is made on the opened file through `/proc/self/fd`, so a
+	/// component of the target can't be replaced with a symbolic link
+	/// between it being resolved and mounted on. This protects mounts into
+	/// directory trees that are not trusted, such as the root file-system of
+	/// a jail. Mounting fails with
+	/// [`ResolveTarget`](../enum.ErrorKind.html#variant.ResolveTarget) if any
+	/// component of the target is a symbolic link. Targets created with
+	/// [`make_target_dir`](#method.make_target_dir) are created before they
+	/// are resolved.
+	///
+	/// Requires `/proc` to be mounted and `openat2(2)` (Linux 5.6). On older
+	/// kernels, mounting fails with `ResolveTarget` and `ENOSYS` rather than
+	/// mounting on the target by path.
+	///
+	/// ```rust
+	/// Mount::bind("/lib", "/tmp/jail/lib")?.no_symlinks().mount()?;
+	/// ```
+	pub fn no_symlinks(mut self) -> Mount {
+		self.extra_mut().no_symlinks = true;
+		self
+	}
+
 	/// Mount using the given specification.
 	pub fn mount(&mut self) -> Result<()> {
-		let target = self.target.to_str()?;
-		if self.mk_target {
-			create_dir_all(target)?;
+		let data = match self.extra {
+			Some(ref extra) if !extra.data.is_empty() => Some(CString::new(extra.data.join(","))?),
+			_ => None,
+		};
+
+		if let Some(ref data) = data {
+			self.check_data_length(data)?;
 		}
 
-		unsafe {
-			match mount(
-				self.src(),
-				self.target(),
-				self.fstype(),
-				self.flags,
-				ptr::null()
-			) {
-				-1 => return Err(errno!(Mount, self.clone())),
-				_ => ()
+		let target = cstring_path(&self.target).to_owned();
+		if self.mk_target && !target.exists() {
+			create_dir_all(&target)?;
+			self.set_target_attributes(&target)?;
+		}
+
+		// Hold the device open until it is mounted so that it isn't detached.
+		let loop_image = self.extra.as_mut().and_then(|extra| extra.loop_image.as_mut());
+		let _device = match loop_image {
+			Some(image) if image.device.is_none() => {
+				let (device, file) = LoopDevice::attach(&image.image)?;
+				self.src = Some(device.path().clone());
+				image.device = Some(device);
+				Some(file)
+			}
+			_ => None,
+		};
+
+		// Hold the target open so that it can't be replaced once resolved.
+		let resolved = self.resolve_target()?;
+		let mount_target = match resolved {
+			Some(ref file) => CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?,
+			None => self.target.clone(),
+		};
+
+		let flags = self.mount_flags(&target)?;
+		debug!(
+			"mounting {:?} on {:?} (type {:?}, flags {:#x}, data {:?})",
+			self.src, self.target, self.fstype, flags, data
+		);
+
+		let data_ptr = data.as_ref().map_or(ptr::null(), |data| data.as_ptr() as *const c_void);
+		if unsafe { mount(self.src(), mount_target.as_ptr(), self.fstype(), flags, data_ptr) } == -1 {
+			return Err(errno!(Mount, self.clone()));
+		}
+
+		let canonical_target = target.canonicalize()?;
+		self.mounted = Some(path_cstring(&canonical_target)?);
+
+		if self.flags & (MS_BIND | MS_REMOUNT) == MS_BIND && self.flags & OPTION_FLAGS_MASK != 0 {
+			self.remount_bind(&canonical_target)?;
+		}
+
+		if self.verify {
+			self.check_mounted(&canonical_target)?;
+		}
+
+		Ok(())
+	}
+
+	/// Open the target without following symbolic links if requested.
+	fn resolve_target(&self) -> Result<Option<File>> {
+		match self.extra {
+			Some(ref extra) if extra.no_symlinks => {},
+			_ => return Ok(None),
+		}
+
+		resolve::open_no_symlinks(&self.target).map(Some)
+	}
+
+	/// Ensure that the file-system specific options fit within the single
+	/// page that the kernel copies them into.
+	fn check_data_length(&self, data: &CString) -> Result<()> {
+		// The page must also hold the terminating NUL.
+		let max = unsafe { sysconf(_SC_PAGE_SIZE) } as usize - 1;
+		let length = data.as_bytes().len();
+		if length <= max {
+			return Ok(());
+		}
+
+		let hint = match self.fstype.as_ref().map(|fstype| fstype.as_bytes()) {
+			Some(b"overlay") => concat!(
+				"; bind the lower directories to shorter paths or give them relative to ",
+				"the working directory, or add them individually with lowerdir+ using fsconfig(2)",
+			),
+			_ => "",
+		};
+		Err(ErrorKind::MountDataTooLong(length, max, hint.to_owned()).into())
+	}
+
+	/// Get the flags to mount with, including those of the existing mount if
+	/// they are preserved.
+	fn mount_flags(&self, target: &Path) -> Result<c_ulong> {
+		match self.extra {
+			Some(ref extra) if extra.preserve_flags && self.flags & MS_REMOUNT != 0 => {},
+			_ => return Ok(self.flags),
+		}
+
+		with_existing_flags(self.flags, &target.canonicalize()?)
+	}
+
+	/// Apply the per-mount flags to a new bind mount.
+	///
+	/// The flags inherited from the source are kept, as they may be locked
+	/// if the source was mounted in another user namespace.
+	fn remount_bind(&self, target: &Path) -> Result<()> {
+		let flags = with_existing_flags(MS_REMOUNT | MS_BIND | (self.flags & OPTION_FLAGS_MASK), target)?;
+		let target = path_cstring(target)?;
+		debug!("remounting bind mount {:?} (flags {:#x})", target, flags);
+
+		if unsafe { mount(ptr::null(), target.as_ptr(), ptr::null(), flags, ptr::null()) } == -1 {
+			return Err(errno!(Mount, self.clone()));
+		}
+
+		Ok(())
+	}
+
+	/// Set the owner and permissions of a newly created target.
+	fn set_target_attributes(&self, target: &Path) -> Result<()> {
+		let extra = match self.extra {
+			Some(ref extra) => extra,
+			None => return Ok(()),
+		};
+
+		if let Some((uid, gid)) = extra.target_owner {
+			if unsafe { chown(self.target(), uid, gid) } == -1 {
+				return Err(errno!(TargetOwner, target.to_owned(), uid, gid));
 			}
+		}
+
+		// Set after the owner as changing the owner clears set-ID bits.
+		if let Some(mode) = extra.target_mode {
+			set_permissions(target, Permissions::from_mode(mode))?;
+		}
+
+		Ok(())
+	}
+
+	/// Compare the mount at the target with the flags of the mount.
+	fn check_mounted(&self, target: &Path) -> Result<()> {
+		let failed = |reason: String| -> Error {
+			ErrorKind::MountVerificationFailed(target.to_owned(), reason).into()
 		};
 
-		let canonical_target = Path::new(target)
-			.canonicalize()?
-			.to_string_lossy()
-			.as_ref()
-			.to_owned();
-		self.mounted = Some(CString::new(canonical_target)?);
+		// The last mount at a mount point is the one that is visible.
+		let info = mountinfo::mounts()?
+			.into_iter()
+			.rev()
+			.find(|info| info.mount_point == target)
+			.ok_or_else(|| failed("no mount at target".to_owned()))?;
+
+		for &(flag, option) in OPTION_FLAGS {
+			if self.flags & flag != 0 && !info.has_option(option) {
+				return Err(failed(format!("missing option {}", option)));
+			}
+		}
+
+		let propagation = match (self.flags & (MS_SHARED | MS_PRIVATE | MS_SLAVE | MS_UNBINDABLE), info.propagation) {
+			(MS_SHARED, Propagation::Shared(_)) | (MS_SHARED, Propagation::SharedAndSlave(..)) => true,
+			(MS_SLAVE, Propagation::Slave(_)) | (MS_SLAVE, Propagation::SharedAndSlave(..)) => true,
+			(MS_UNBINDABLE, Propagation::Unbindable) => true,
+			(MS_PRIVATE, Propagation::Private) | (MS_PRIVATE, Propagation::Unbindable) => true,
+			(MS_SHARED, _) | (MS_SLAVE, _) | (MS_UNBINDABLE, _) | (MS_PRIVATE, _) => false,
+			_ => true,
+		};
+		if !propagation {
+			return Err(failed(format!("unexpected propagation {:?}", info.propagation)));
+		}
 
 		Ok(())
 	}
 
+	fn extra_mut(&mut self) -> &mut Extra {
+		self.extra.get_or_insert_with(Default::default)
+	}
+
 	fn src(&self) -> *const c_char {
 		match self.src {
 			Some(ref src) => src.as_ptr() as *const c_char,
@@ -409,6 +1013,171 @@ impl Mount {
 			None => ptr::null(),
 		}
 	}
+
+	/// Get the target of the mount, joined onto `root` if it is relative to
+	/// the root of the context.
+	pub(crate) fn target_under(&self, root: &Path) -> PathBuf {
+		let target = cstring_path(&self.target);
+		if self.relative {
+			root.join(target.strip_prefix("/").unwrap_or(target))
+		} else {
+			target.to_owned()
+		}
+	}
+}
+
+/// A read-only root file-system with writable directories.
+///
+/// The base directory is bound read-only onto the root of the context (see
+/// [`Context::root`](../struct.Context.html#method.root)) and a new bounded
+/// `tmpfs` is mounted over each writable directory. Writable directories are
+/// given relative to the root and must already exist in the base directory.
+/// The context must have a root directory.
+///
+/// ```rust
+/// Context::new()
+///     .root("/tmp/jail")
+///     .with(RootFs::new("/srv/rootfs")
+///         .writable("tmp", 64 << 20)?
+///         .writable("var", 16 << 20)?);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RootFs {
+	base: PathBuf,
+	writable: Vec<Mount>,
+	root: Option<PathBuf>,
+}
+
+impl RootFs {
+	/// Use `base` as the read-only root file-system.
+	pub fn new<P: AsRef<Path>>(base: P) -> RootFs {
+		RootFs {
+			base: base.as_ref().to_owned(),
+			writable: Vec::new(),
+			root: None,
+		}
+	}
+
+	/// Mount a writable `tmpfs` of at most `size` bytes over `dir` within the
+	/// root.
+	///
+	/// The file-system is mounted without support for set-user-ID programs
+	/// or devices. See [`Mount::tmpfs`](struct.Mount.html#method.tmpfs).
+	pub fn writable<P: AsRef<Path>>(mut self, dir: P, size: u64) -> Result<RootFs> {
+		let mut tmpfs = Mount::tmpfs(dir, size)?
+			.no_setuid()
+			.no_devices();
+		tmpfs.relative = true;
+		self.writable.push(tmpfs);
+		Ok(self)
+	}
+
+	/// Construct the mounts for the root file-system in the order they must
+	/// be mounted.
+	///
+	/// The targets of the mounts are relative to the root of the context.
+	pub fn mounts(&self) -> Result<Vec<Mount>> {
+		// Bind mounts ignore flags other than MS_REC so must be remounted to
+		// be made read-only.
+		let mut base_ro = Mount::remount("")?.as_bind().read_only();
+		base_ro.relative = true;
+
+		let mut mounts = vec![Mount::relative(&self.base, "")?, base_ro];
+		mounts.extend(self.writable.iter().cloned());
+
+		Ok(mounts)
+	}
+}
+
+impl Namespace for RootFs {
+	fn clone_flag(&self) -> c_int {
+		CLONE_NEWNS
+	}
+
+	fn required_flags(&self) -> c_int {
+		CLONE_NEWNS
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		for mut mount in self.mounts()? {
+			if let Some(ref root) = self.root {
+				mount.set_root(root);
+			}
+			mount.mount()?;
+		}
+
+		Ok(())
+	}
+
+	fn set_root(&mut self, root: &Path) {
+		self.root = Some(root.to_owned());
+	}
+}
+
+/// Resolve a device through a directory of symlinks in `/dev/disk`.
+fn resolve_device(dir: &str, name: &str) -> Result<PathBuf> {
+	let no_device = || -> Error {
+		ErrorKind::NoSuchDevice(format!("/dev/disk/{}/{}", dir, name)).into()
+	};
+
+	if name.is_empty() || name.contains('/') {
+		return Err(no_device());
+	}
+
+	Path::new("/dev/disk")
+		.join(dir)
+		.join(name)
+		.canonicalize()
+		.map_err(|_| no_device())
+}
+
+/// Convert a path to a C string without requiring it to be valid UTF-8.
+fn path_cstring<P: AsRef<Path>>(path: P) -> Result<CString> {
+	Ok(CString::new(path.as_ref().as_os_str().as_bytes())?)
+}
+
+/// View a C string as a path.
+fn cstring_path(path: &CString) -> &Path {
+	Path::new(OsStr::from_bytes(path.as_bytes()))
+}
+
+/// Determine whether every mount of `devpts` is a new instance, which is the
+/// case since Linux 4.7.
+fn devpts_always_new() -> bool {
+	kernel_at_least(4, 7)
+}
+
+/// Determine whether the running kernel is at least the given version.
+fn kernel_at_least(major: u32, minor: u32) -> bool {
+	let mut name: utsname = unsafe { mem::zeroed() };
+	if unsafe { uname(&mut name) } == -1 {
+		return false;
+	}
+
+	let release = unsafe { CStr::from_ptr(name.release.as_ptr()) }.to_string_lossy();
+	let version: Vec<u32> = release
+		.split(|c: char| !c.is_ascii_digit())
+		.take(2)
+		.filter_map(|part| part.parse().ok())
+		.collect();
+	match version.as_slice() {
+		&[running_major, running_minor] => (running_major, running_minor) >= (major, minor),
+		_ => false,
+	}
+}
+
+/// Escape a label in the same way as the links created by udev.
+fn udev_escape(label: &str) -> String {
+	let mut escaped = String::new();
+	for c in label.chars() {
+		match c {
+			'0'..='9' | 'A'..='Z' | 'a'..='z' |
+			'#' | '+' | '-' | '.' | ':' | '=' | '@' | '_' => escaped.push(c),
+			_ if !c.is_ascii() => escaped.push(c),
+			_ => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+		}
+	}
+	escaped
 }
 
 impl Namespace for Mount {
@@ -416,18 +1185,379 @@ impl Namespace for Mount {
 		CLONE_NEWNS
 	}
 
+	fn required_flags(&self) -> c_int {
+		CLONE_NEWNS
+	}
+
 	fn internal_config(&mut self) -> Result<()> {
 		self.mount()
 	}
+
+	fn set_root(&mut self, root: &Path) {
+		if !self.relative {
+			return;
+		}
+
+		if let Ok(target) = path_cstring(self.target_under(root)) {
+			self.target = target;
+			self.relative = false;
+		}
+	}
 }
 
 impl Drop for Mount {
 	fn drop(&mut self) {
-		match (&self.mounted, self.umount) {
-			(Some(ref path), true) => unsafe {
-				umount(path.as_ptr());
-			},
-			_ => {}
+		let path = match (&self.mounted, self.umount) {
+			(Some(ref path), true) => path,
+			_ => return,
+		};
+
+		unsafe {
+			if self.flags & MS_MOVE != 0 {
+				// A moved mount is returned to where it came from.
+				if let Some(ref src) = self.src {
+					mount(path.as_ptr(), src.as_ptr(), ptr::null(), MS_MOVE, ptr::null());
+				}
+			} else if self.flags & CHANGE_FLAGS == 0 {
+				let flags = self.extra.as_ref().map_or(0, |extra| extra.unmount_flags.0);
+				umount2(path.as_ptr(), flags);
+			}
 		}
 	}
 }
+
+impl fmt::Debug for Mount {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Mount")
+			.field("src", &self.src)
+			.field("target", &self.target)
+			.field("fstype", &self.fstype)
+			.field("flags", &MountFlags(self.flags))
+			.field("mk_target", &self.mk_target)
+			.field("umount", &self.umount)
+			.field("mounted", &self.mounted)
+			.field("relative", &self.relative)
+			.field("extra", &self.extra)
+			.field("verify", &self.verify)
+			.finish()
+	}
+}
+
+/// Flags passed to `mount(2)`, shown symbolically, e.g. `BIND|RDONLY`.
+struct MountFlags(c_ulong);
+
+const MOUNT_FLAG_NAMES: &[(c_ulong, &str)] = &[
+	(MS_RDONLY, "RDONLY"),
+	(MS_NOSUID, "NOSUID"),
+	(MS_NODEV, "NODEV"),
+	(MS_NOEXEC, "NOEXEC"),
+	(MS_SYNCHRONOUS, "SYNCHRONOUS"),
+	(MS_REMOUNT, "REMOUNT"),
+	(MS_MANDLOCK, "MANDLOCK"),
+	(MS_DIRSYNC, "DIRSYNC"),
+	(MS_NOATIME, "NOATIME"),
+	(MS_NODIRATIME, "NODIRATIME"),
+	(MS_BIND, "BIND"),
+	(MS_MOVE, "MOVE"),
+	(MS_REC, "REC"),
+	(MS_SILENT, "SILENT"),
+	(MS_UNBINDABLE, "UNBINDABLE"),
+	(MS_PRIVATE, "PRIVATE"),
+	(MS_SLAVE, "SLAVE"),
+	(MS_SHARED, "SHARED"),
+	(MS_RELATIME, "RELATIME"),
+	(MS_STRICTATIME, "STRICTATIME"),
+];
+
+impl fmt::Debug for MountFlags {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut remaining = self.0;
+		let mut first = true;
+
+		for &(flag, name) in MOUNT_FLAG_NAMES {
+			if remaining & flag != 0 {
+				if !first {
+					write!(f, "|")?;
+				}
+				write!(f, "{}", name)?;
+				remaining &= !flag;
+				first = false;
+			}
+		}
+
+		match (remaining, first) {
+			(0, true) => write!(f, "0"),
+			(0, false) => Ok(()),
+			(remaining, true) => write!(f, "{:#x}", remaining),
+			(remaining, false) => write!(f, "|{:#x}", remaining),
+		}
+	}
+}
+
+/// Flags passed to `umount2(2)` when unmounting a mount.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnmountFlags(pub c_int);
+
+impl UnmountFlags {
+	/// Force the unmount even if the file-system is busy or unreachable.
+	pub const FORCE: UnmountFlags = UnmountFlags(MNT_FORCE);
+	/// Detach the mount immediately and clean it up once it is no longer
+	/// busy.
+	pub const DETACH: UnmountFlags = UnmountFlags(MNT_DETACH);
+	/// Mark the mount as expired rather than unmounting it if it is unused.
+	pub const EXPIRE: UnmountFlags = UnmountFlags(MNT_EXPIRE);
+	/// Don't follow the target if it is a symbolic link.
+	pub const NOFOLLOW: UnmountFlags = UnmountFlags(UMOUNT_NOFOLLOW);
+}
+
+impl BitOr for UnmountFlags {
+	type Output = UnmountFlags;
+
+	fn bitor(self, other: UnmountFlags) -> UnmountFlags {
+		UnmountFlags(self.0 | other.0)
+	}
+}
+
+/// Don't dereference the target of an unmount if it is a symbolic link (not
+/// currently in libc).
+const UMOUNT_NOFOLLOW: c_int = 0x8;
+
+/// Flags that are shown as per-mount options in `/proc/self/mountinfo`.
+const OPTION_FLAGS: &[(c_ulong, &str)] = &[
+	(MS_RDONLY, "ro"),
+	(MS_NOSUID, "nosuid"),
+	(MS_NODEV, "nodev"),
+	(MS_NOEXEC, "noexec"),
+	(MS_NOATIME, "noatime"),
+	(MS_NODIRATIME, "nodiratime"),
+	(MS_RELATIME, "relatime"),
+];
+
+/// Add the per-mount flags of the mount at `target` to `flags`.
+///
+/// The access time flags of the mount are only added if none are given.
+fn with_existing_flags(flags: c_ulong, target: &Path) -> Result<c_ulong> {
+	// Without an existing mount the remount fails regardless.
+	let info = match mountinfo::mounts()?.into_iter().rev().find(|info| info.mount_point == target) {
+		Some(info) => info,
+		None => return Ok(flags),
+	};
+
+	let mut merged = flags;
+	for &(flag, option) in OPTION_FLAGS {
+		let atime_given = flag & ATIME_FLAGS != 0 && flags & ATIME_FLAGS != 0;
+		if info.has_option(option) && !atime_given {
+			merged |= flag;
+		}
+	}
+
+	// Strict access times aren't listed, and a remount otherwise defaults to
+	// relative access times.
+	if merged & ATIME_FLAGS == 0 {
+		merged |= MS_STRICTATIME;
+	}
+
+	Ok(merged)
+}
+
+/// All of the flags that are shown as per-mount options.
+const OPTION_FLAGS_MASK: c_ulong = MS_RDONLY | MS_NOSUID | MS_NODEV | MS_NOEXEC
+	| MS_NOATIME | MS_NODIRATIME | MS_RELATIME | MS_STRICTATIME;
+
+/// Mutually exclusive flags that set how access times are updated.
+const ATIME_FLAGS: c_ulong = MS_NOATIME | MS_RELATIME | MS_STRICTATIME;
+
+/// Flags that change an existing mount rather than creating a new one.
+const CHANGE_FLAGS: c_ulong = MS_REMOUNT | MS_SHARED | MS_PRIVATE | MS_SLAVE | MS_UNBINDABLE;
+
+/// Test that a relocated mount is moved back rather than unmounted.
+#[test]
+fn relocate_cleanup() {
+	use std::fs::{File, create_dir_all};
+	use std::io::Read;
+	use ::context::run_in_jail;
+
+	run_in_jail("relocate", |dir| {
+		let mounted = |path: &Path| -> Result<bool> {
+			let mut mountinfo = String::new();
+			File::open("/proc/self/mountinfo")?.read_to_string(&mut mountinfo)?;
+			Ok(mountinfo.lines().any(|line| line.split(' ').nth(4) == path.to_str()))
+		};
+
+		let (src, target) = (dir.join("src"), dir.join("target"));
+		create_dir_all(&src)?;
+		create_dir_all(&target)?;
+
+		let mut tmpfs = Mount::new("tmpfs", &src, "tmpfs")?;
+		tmpfs.mount()?;
+		{
+			let mut relocated = Mount::relocate(&src, &target)?.unmount();
+			relocated.mount()?;
+			ensure!(mounted(&target)? && !mounted(&src)?, "{:?} was not moved to {:?}", src, target);
+		}
+
+		ensure!(mounted(&src)? && !mounted(&target)?, "{:?} was not moved back from {:?}", src, target);
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that paths that are not valid UTF-8 are mounted unchanged.
+#[test]
+fn non_utf8_target() {
+	use std::ffi::OsString;
+	use std::os::unix::ffi::OsStringExt;
+	use ::context::run_in_jail;
+
+	run_in_jail("non-utf8", |dir| {
+		let target = dir.join(OsString::from_vec(b"target-\xff\xfe".to_vec()));
+		let mut tmpfs = Mount::new("tmpfs", &target, "tmpfs")?.make_target_dir();
+		tmpfs.mount()?;
+
+		ensure!(target.is_dir(), "{:?} was not created", target);
+		let mounted = tmpfs.mounted.as_ref().map(cstring_path);
+		ensure!(mounted == Some(target.as_path()), "mounted on {:?} rather than {:?}", mounted, target);
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that options longer than a page are rejected before mounting.
+#[test]
+fn data_too_long() {
+	let lowerdirs = vec!["/a/long/lower/directory"; 256].join(":");
+	let mut mount = Mount::new("overlay", "/nonexistent/target", "overlay")
+		.unwrap()
+		.data(&format!("lowerdir={}", lowerdirs));
+
+	match mount.mount() {
+		Err(Error(ErrorKind::MountDataTooLong(length, max, ref hint), _)) => {
+			assert!(length > max);
+			assert!(hint.contains("lowerdir+"));
+		}
+		result => panic!("unexpected result {:?}", result),
+	}
+}
+
+/// Test that the version of the running kernel is compared with the version
+/// required.
+#[test]
+fn kernel_version() {
+	assert!(kernel_at_least(2, 6));
+	assert!(!kernel_at_least(1000, 0));
+}
+
+/// Test that mount flags are shown symbolically.
+#[test]
+fn debug_flags() {
+	assert_eq!(format!("{:?}", MountFlags(0)), "0");
+	assert_eq!(format!("{:?}", MountFlags(MS_RDONLY | MS_NOEXEC)), "RDONLY|NOEXEC");
+	assert_eq!(format!("{:?}", MountFlags(MS_BIND | 1 << 30)), "BIND|0x40000000");
+
+	let mount = Mount::bind("/proc", "/tmp/proc").unwrap().read_only();
+	assert!(format!("{:?}", mount).contains("flags: RDONLY|BIND,"));
+}
+
+/// Test that per-mount flags are applied to bind mounts by remounting them.
+#[test]
+fn bind_remount_flags() {
+	use std::fs::create_dir_all;
+	use ::context::run_in_jail;
+
+	run_in_jail("bind-flags", |dir| {
+		let (src, target) = (dir.join("src"), dir.join("target"));
+		create_dir_all(&src)?;
+		create_dir_all(&target)?;
+
+		// The access time flags of mounts from the parent namespace are
+		// locked in a user namespace, so bind a mount made within it.
+		Mount::new("tmpfs", &src, "tmpfs")?.mount()?;
+		Mount::bind(&src, &target)?.read_only().no_access_time().mount()?;
+
+		let target = target.canonicalize()?;
+		let info = mountinfo::mounts()?.into_iter().rev().find(|info| info.mount_point == target);
+		let info = match info {
+			Some(info) => info,
+			None => bail!("{:?} is not mounted", target),
+		};
+		for option in &["ro", "noatime"] {
+			ensure!(info.has_option(option), "{:?} is not mounted {}", target, option);
+		}
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that recursive propagation changes apply to the whole subtree.
+#[test]
+fn recursive_propagation() {
+	use std::fs::create_dir;
+	use ::context::run_in_jail;
+
+	run_in_jail("recursive", |dir| {
+		let sub = dir.join("sub");
+		Mount::new("tmpfs", dir, "tmpfs")?.mount()?;
+		Mount::shared(dir)?.mount()?;
+		create_dir(&sub)?;
+		Mount::new("tmpfs", &sub, "tmpfs")?.mount()?;
+		Mount::shared(&sub)?.mount()?;
+		Mount::recursive_private(dir)?.mount()?;
+
+		let dir = dir.canonicalize()?;
+		let mounts: Vec<_> = mountinfo::mounts()?.into_iter()
+			.filter(|info| info.mount_point.starts_with(&dir))
+			.collect();
+		ensure!(mounts.len() == 2, "found {} mounts under {:?} rather than 2", mounts.len(), dir);
+		for info in mounts {
+			ensure!(
+				info.propagation == Propagation::Private,
+				"{:?} is {:?} rather than private", info.mount_point, info.propagation
+			);
+		}
+		Ok(())
+	}).unwrap();
+}
+
+/// Test that a bounded tmpfs can't be filled beyond its size.
+#[test]
+fn bounded_tmpfs() {
+	use std::fs::{metadata, write};
+	use std::os::unix::fs::PermissionsExt;
+	use ::context::run_in_jail;
+
+	assert!(Mount::tmpfs("/tmp", 0).is_err());
+
+	run_in_jail("tmpfs", |dir| {
+		Mount::tmpfs(dir, 64 << 10)?.mount()?;
+		let mode = metadata(dir)?.permissions().mode() & 0o7777;
+		ensure!(mode == 0o1777, "mounted with mode {:o} rather than 1777", mode);
+
+		match write(dir.join("fill"), vec![0u8; 1 << 20]) {
+			Err(ref err) if err.raw_os_error() == Some(::libc::ENOSPC) => Ok(()),
+			result => bail!("filling the tmpfs returned {:?} rather than ENOSPC", result),
+		}
+	}).unwrap();
+}
+
+/// Test that a new devpts instance only holds the terminals allocated
+/// through it.
+#[test]
+fn devpts_instance() {
+	use std::fs::{OpenOptions, create_dir_all, read_dir};
+	use ::context::run_in_jail;
+
+	run_in_jail("devpts", |dir| {
+		let (pts, ptmx) = (dir.join("pts"), dir.join("ptmx"));
+		create_dir_all(&pts)?;
+		File::create(&ptmx)?;
+
+		Mount::devpts(&pts)?.mount()?;
+		Mount::ptmx(&pts, &ptmx)?.mount()?;
+		let _master = OpenOptions::new().read(true).write(true).open(&ptmx)?;
+
+		let mut entries = Vec::new();
+		for entry in read_dir(&pts)? {
+			entries.push(entry?.file_name().to_string_lossy().into_owned());
+		}
+		entries.sort();
+		ensure!(entries == ["0", "ptmx"], "devpts instance holds {:?}", entries);
+		Ok(())
+	}).unwrap();
+}
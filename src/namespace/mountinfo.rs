@@ -0,0 +1,206 @@
+//! Parsing of the mounts visible to a process (see `proc(5)`).
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+use ::error::*;
+
+/// A mount listed in `/proc/<pid>/mountinfo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountEntry {
+	/// Unique ID of the mount.
+	pub mount_id: u32,
+	/// ID of the parent mount.
+	pub parent_id: u32,
+	/// Root of the mount within the file-system.
+	pub root: PathBuf,
+	/// Mount point relative to the root of the process.
+	pub mount_point: PathBuf,
+	/// Per-mount options, such as `ro` and `nosuid`.
+	pub options: Vec<String>,
+	/// Optional fields, such as `shared:1` and `master:2`.
+	pub optional: Vec<String>,
+	/// Type of the file-system.
+	pub fstype: String,
+	/// File-system specific source of the mount.
+	pub source: String,
+	/// Per-superblock options.
+	pub super_options: Vec<String>,
+	/// Propagation type of the mount, from the optional fields.
+	pub propagation: Propagation,
+}
+
+/// The propagation type of a mount (see `mount_namespaces(7)`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Propagation {
+	/// Mount events are not propagated to or from the mount.
+	Private,
+	/// Mount events are propagated to and from the peer group with the ID.
+	Shared(u32),
+	/// Mount events are propagated from the master peer group with the ID.
+	Slave(u32),
+	/// Mount events are propagated from the master peer group and to and
+	/// from the peer group of the mount, given by ID as `(peer, master)`.
+	SharedAndSlave(u32, u32),
+	/// The mount is private and cannot be bind mounted.
+	Unbindable,
+}
+
+impl Propagation {
+	/// Determine the propagation type from the optional fields of a mount.
+	fn from_optional(optional: &[String]) -> Propagation {
+		let peer_group = |tag: &str| optional.iter()
+			.filter_map(|field| {
+				let mut parts = field.splitn(2, ':');
+				match (parts.next(), parts.next()) {
+					(Some(name), Some(id)) if name == tag => id.parse().ok(),
+					_ => None,
+				}
+			})
+			.next();
+
+		match (peer_group("shared"), peer_group("master")) {
+			(Some(peer), Some(master)) => Propagation::SharedAndSlave(peer, master),
+			(Some(peer), None) => Propagation::Shared(peer),
+			(None, Some(master)) => Propagation::Slave(master),
+			(None, None) if optional.iter().any(|field| field == "unbindable") => Propagation::Unbindable,
+			(None, None) => Propagation::Private,
+		}
+	}
+}
+
+impl MountEntry {
+	/// Determine if the mount has the given per-mount option.
+	pub fn has_option(&self, option: &str) -> bool {
+		self.options.iter().any(|o| o == option)
+	}
+
+	/// Determine if the mount has an optional field with the given tag.
+	pub fn has_tag(&self, tag: &str) -> bool {
+		self.optional.iter().any(|field| field.split(':').next() == Some(tag))
+	}
+
+	/// Parse a single line of `mountinfo`.
+	fn parse(line: &str) -> Option<MountEntry> {
+		let mut fields = line.split(' ');
+		let mount_id = fields.next()?.parse().ok()?;
+		let parent_id = fields.next()?.parse().ok()?;
+		let _device = fields.next()?;
+		let root = unescape(fields.next()?);
+		let mount_point = unescape(fields.next()?);
+		let options = list(fields.next()?);
+
+		let mut optional = Vec::new();
+		loop {
+			match fields.next()? {
+				"-" => break,
+				field => optional.push(field.to_owned()),
+			}
+		}
+
+		Some(MountEntry {
+			mount_id,
+			parent_id,
+			root,
+			mount_point,
+			options,
+			propagation: Propagation::from_optional(&optional),
+			optional,
+			fstype: fields.next()?.to_owned(),
+			source: unescape_str(fields.next()?),
+			super_options: list(fields.next()?),
+		})
+	}
+}
+
+/// Read the mounts visible to the calling process.
+///
+/// Each line of `/proc/self/mountinfo` is parsed into a
+/// [`MountEntry`](struct.MountEntry.html), in the order that the mounts were
+/// made. Malformed lines are skipped.
+///
+/// ```rust
+/// for entry in isolate::mountinfo()? {
+///     println!("{} on {:?} ({:?})", entry.fstype, entry.mount_point, entry.propagation);
+/// }
+/// ```
+pub fn mounts() -> Result<Vec<MountEntry>> {
+	let mut text = String::new();
+	File::open("/proc/self/mountinfo")?.read_to_string(&mut text)?;
+	Ok(parse(&text))
+}
+
+/// Parse the contents of a `mountinfo` file, ignoring malformed lines.
+pub fn parse(text: &str) -> Vec<MountEntry> {
+	text.lines().filter_map(MountEntry::parse).collect()
+}
+
+/// Split a comma-separated list of options.
+fn list(field: &str) -> Vec<String> {
+	field.split(',').map(str::to_owned).collect()
+}
+
+/// Decode the octal escapes in a field that is not a path.
+fn unescape_str(field: &str) -> String {
+	unescape(field).to_string_lossy().into_owned()
+}
+
+/// Decode the octal escapes used for whitespace and backslashes in paths.
+fn unescape(field: &str) -> PathBuf {
+	let bytes = field.as_bytes();
+	let mut path = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+
+	while i < bytes.len() {
+		let escape = bytes.get(i + 1..i + 4)
+			.filter(|_| bytes[i] == b'\\')
+			.and_then(|digits| ::std::str::from_utf8(digits).ok())
+			.and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+		match escape {
+			Some(byte) => {
+				path.push(byte);
+				i += 4;
+			}
+			None => {
+				path.push(bytes[i]);
+				i += 1;
+			}
+		}
+	}
+
+	PathBuf::from(OsString::from_vec(path))
+}
+
+/// Test parsing of escaped paths, optional fields, and malformed lines.
+#[test]
+fn parse_mountinfo() {
+	let text = concat!(
+		"22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n",
+		"35 22 0:32 / /mnt/my\\040files rw,nosuid shared:5 master:2 - tmpfs my\\040tmp rw,size=4k\n",
+		"36 22 0:33 /sub\\134dir /tmp/tab\\011name ro unbindable - fuse.sshfs host:/ ro\n",
+		"37 22 0:34 / /proc rw - proc proc rw\n",
+		"not a mount\n",
+	);
+
+	let entries = parse(text);
+	assert_eq!(entries.len(), 4);
+	assert_eq!(entries[0].propagation, Propagation::Shared(1));
+
+	assert_eq!(entries[1].parent_id, 22);
+	assert_eq!(entries[1].mount_point, PathBuf::from("/mnt/my files"));
+	assert_eq!(entries[1].source, "my tmp");
+	assert_eq!(entries[1].super_options, vec!["rw", "size=4k"]);
+	assert_eq!(entries[1].propagation, Propagation::SharedAndSlave(5, 2));
+	assert!(entries[1].has_option("nosuid"));
+
+	assert_eq!(entries[2].root, PathBuf::from("/sub\\dir"));
+	assert_eq!(entries[2].mount_point, PathBuf::from("/tmp/tab\tname"));
+	assert_eq!(entries[2].fstype, "fuse.sshfs");
+	assert_eq!(entries[2].propagation, Propagation::Unbindable);
+
+	assert_eq!(entries[3].propagation, Propagation::Private);
+}
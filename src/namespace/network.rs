@@ -1,11 +1,44 @@
+use std::cell::RefCell;
+use std::env::temp_dir;
+use std::ffi::{CString, OsString};
+use std::fmt;
+use std::fs::{File, OpenOptions, remove_dir_all};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Write};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
 use libc::{
+	AF_INET,
+	AF_INET6,
 	CLONE_NEWNET,
+	CLONE_NEWNS,
+	IFF_UP,
+	IFNAMSIZ,
+	SOCK_CLOEXEC,
+	SOCK_DGRAM,
+	c_char,
 	c_int,
+	c_short,
+	c_uint,
+	c_ulong,
+	if_indextoname,
+	if_nametoindex,
+	in6_addr,
+	in_addr,
+	ioctl,
+	mkdtemp,
+	setns,
+	sa_family_t,
+	sockaddr_in,
+	socket,
 };
 
 use ::error::*;
-use ::Child;
-use super::Namespace;
+use super::{CloneFlags, Mount, Namespace};
 
 /// Networking
 ///
@@ -18,18 +51,514 @@ use super::Namespace;
 /// isolate them from networking or to provide some filtered access to the
 /// global networking namespace (and external network) using virtual network
 /// devices.
-#[derive(Clone)]
-pub struct Network {}
+#[derive(Clone, Debug)]
+pub struct Network {
+	addresses: Vec<Address>,
+	named: Option<PathBuf>,
+	loopback_up: bool,
+	files: Vec<EtcFile>,
+	staging: RefCell<Option<PathBuf>>,
+}
 
 impl Network {
 	/// Configure a new IPC namespace for creation.
 	pub fn new() -> Network {
-		Network {}
+		Network {
+			addresses: Vec::new(),
+			named: None,
+			loopback_up: false,
+			files: Vec::new(),
+			staging: RefCell::new(None),
+		}
+	}
+
+	/// Join the persistent network namespace created by `ip netns add`.
+	///
+	/// Rather than creating a new network namespace, the child joins the
+	/// namespace bound at `/run/netns/<name>` from within the child. Joining
+	/// the namespace requires `CAP_SYS_ADMIN` in the user namespace that owns
+	/// it, so this can't be combined with a new [`User`](struct.User.html)
+	/// namespace unless that namespace owns it.
+	///
+	/// ```rust
+	/// Context::new().with(Network::named("blue")?);
+	/// ```
+	pub fn named(name: &str) -> Result<Network> {
+		let path = Path::new(NETNS_RUN_DIR).join(name);
+		if name.is_empty() || name.contains('/') || !path.exists() {
+			return Err(ErrorKind::NoSuchNetworkNamespace(name.to_owned()).into());
+		}
+
+		Ok(Network {
+			addresses: Vec::new(),
+			named: Some(path),
+			loopback_up: false,
+			files: Vec::new(),
+			staging: RefCell::new(None),
+		})
+	}
+
+	/// Bring the loopback interface (`lo`) up within the namespace.
+	///
+	/// The loopback interface of a new network namespace is down, so without
+	/// this the child can't connect even to itself through `localhost`.
+	pub fn loopback_up(mut self) -> Network {
+		self.loopback_up = true;
+		self
+	}
+
+	/// Use the given name servers within the namespace.
+	///
+	/// A `resolv.conf` listing the servers is written to a new private
+	/// directory before the child is created and is bound over
+	/// `/etc/resolv.conf` from within the child, so that names can be
+	/// resolved through servers that are reachable from the namespace. The
+	/// directory is removed once the child has exited. The file is only
+	/// readable by its owner. `/etc/resolv.conf` is resolved relative to the
+	/// root of the context (see
+	/// [`Context::root`](../struct.Context.html#method.root)) and must exist.
+	/// This requires a new mount namespace.
+	///
+	/// ```rust
+	/// Context::new()
+	///     .with(EmptyMount::new())
+	///     .with(Network::new().resolv_conf(&["10.0.0.1".parse()?]));
+	/// ```
+	pub fn resolv_conf(mut self, servers: &[IpAddr]) -> Network {
+		let contents: String = servers.iter()
+			.map(|server| format!("nameserver {}\n", server))
+			.collect();
+		self.files.push(EtcFile::new(RESOLV_CONF, self.files.len(), contents));
+		self
+	}
+
+	/// Use the given host names within the namespace.
+	///
+	/// A `hosts` file mapping `localhost` to the loopback addresses followed
+	/// by each of the given hosts is bound over `/etc/hosts` in the same way
+	/// as [`resolv_conf`](#method.resolv_conf).
+	///
+	/// ```rust
+	/// Network::new().hosts(&[("10.0.0.1".parse()?, "gateway")]);
+	/// ```
+	pub fn hosts(mut self, hosts: &[(IpAddr, &str)]) -> Network {
+		let mut contents = String::from("127.0.0.1\tlocalhost\n::1\tlocalhost\n");
+		for &(ip, name) in hosts {
+			contents.push_str(&format!("{}\t{}\n", ip, name));
+		}
+		self.files.push(EtcFile::new(HOSTS, self.files.len(), contents));
+		self
+	}
+
+	/// Assign an address to the named interface within the namespace.
+	///
+	/// The address is assigned with a network prefix of `prefix` bits, e.g.
+	/// `24` for `10.0.0.2/24`, which must be no longer than the address.
+	/// Link-local IPv6 addresses are scoped to the interface they are
+	/// assigned to.
+	///
+	/// The interface name is resolved from within the namespace.
+	///
+	/// ```rust
+	/// Network::new().address("10.0.0.2".parse()?, 24, "veth0")?;
+	/// ```
+	pub fn address(self, ip: IpAddr, prefix: u8, interface: &str) -> Result<Network> {
+		self.add_address(ip, prefix, Interface::Name(interface.to_owned()))
+	}
+
+	/// Assign an address to the interface with the given index within the
+	/// namespace.
+	///
+	/// The address is given in the same form as for
+	/// [`address`](#method.address).
+	pub fn address_by_index(self, ip: IpAddr, prefix: u8, index: u32) -> Result<Network> {
+		self.add_address(ip, prefix, Interface::Index(index))
+	}
+
+	/// Assign an address to an interface, checking the prefix length.
+	fn add_address(mut self, ip: IpAddr, prefix: u8, interface: Interface) -> Result<Network> {
+		let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+		if prefix > max_prefix {
+			return Err(ErrorKind::InvalidPrefix(ip, prefix).into());
+		}
+
+		self.addresses.push(Address {
+			ip,
+			prefix,
+			interface,
+		});
+		Ok(self)
+	}
+
+	/// The directory that the files bound over `/etc` were written to.
+	#[cfg(test)]
+	pub(crate) fn staging(&self) -> Option<PathBuf> {
+		self.staging.borrow().clone()
+	}
+}
+
+impl Default for Network {
+	fn default() -> Network {
+		Network::new()
 	}
 }
 
 impl Namespace for Network {
 	fn clone_flag(&self) -> c_int {
-		CLONE_NEWNET
+		match self.named {
+			Some(_) => 0,
+			None => CLONE_NEWNET,
+		}
+	}
+
+	fn required_flags(&self) -> c_int {
+		match self.files.len() {
+			0 => self.clone_flag(),
+			_ => self.clone_flag() | CLONE_NEWNS,
+		}
+	}
+
+	fn entered_flags(&self) -> c_int {
+		match self.named {
+			Some(_) => CLONE_NEWNET,
+			None => 0,
+		}
+	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		if let Some(ref path) = self.named {
+			debug!("joining network namespace {:?}", path);
+			let netns = File::open(path)?;
+			if unsafe { setns(netns.as_raw_fd(), CLONE_NEWNET) } == -1 {
+				return Err(errno!(Setns, CloneFlags(CLONE_NEWNET)));
+			}
+		}
+
+		if self.loopback_up {
+			Interface::Name(LOOPBACK.to_owned()).up()?;
+		}
+
+		for address in &self.addresses {
+			address.assign()?;
+		}
+
+		if let Some(ref dir) = *self.staging.borrow() {
+			for file in &self.files {
+				file.bind(dir)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn prepare(&self) -> Result<()> {
+		if self.files.is_empty() {
+			return Ok(());
+		}
+
+		let dir = staging_dir()?;
+		*self.staging.borrow_mut() = Some(dir.clone());
+		for file in &self.files {
+			file.write(&dir)?;
+		}
+
+		Ok(())
+	}
+
+	fn set_root(&mut self, root: &Path) {
+		for file in &mut self.files {
+			file.target = root.join(file.target.strip_prefix("/").unwrap_or(&file.target));
+		}
+	}
+
+	fn cleanup(&mut self) -> Result<()> {
+		let staging = self.staging.borrow().clone();
+		if let Some(dir) = staging {
+			debug!("removing {:?}", dir);
+			match remove_dir_all(&dir) {
+				Err(ref err) if err.kind() == IoErrorKind::NotFound => {}
+				result => result?,
+			}
+			*self.staging.borrow_mut() = None;
+		}
+
+		Ok(())
+	}
+}
+
+/// Create a new directory in which to write the files bound over `/etc`.
+///
+/// The directory is given an unpredictable name and is only accessible by
+/// its owner so that other users can't replace the files written within it.
+fn staging_dir() -> Result<PathBuf> {
+	let template = temp_dir().join("isolate-etc-XXXXXX").into_os_string();
+	let mut template = CString::new(template.into_vec())?.into_bytes_with_nul();
+	if unsafe { mkdtemp(template.as_mut_ptr() as *mut c_char) }.is_null() {
+		return Err(IoError::last_os_error().into());
+	}
+
+	template.pop();
+	Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+/// A generated file bound over a file in `/etc`.
+#[derive(Clone, Debug)]
+struct EtcFile {
+	target: PathBuf,
+	name: String,
+	contents: String,
+}
+
+impl EtcFile {
+	/// Bind a new file with the given contents over `target`.
+	///
+	/// The index distinguishes the file from others bound over the same
+	/// target.
+	fn new(target: &str, index: usize, contents: String) -> EtcFile {
+		let name = Path::new(target).file_name().unwrap_or_default().to_string_lossy();
+
+		EtcFile {
+			target: PathBuf::from(target),
+			name: format!("{}-{}", index, name),
+			contents,
+		}
+	}
+
+	/// Write the contents of the file to a new file in `dir`.
+	fn write(&self, dir: &Path) -> Result<()> {
+		let source = dir.join(&self.name);
+		debug!("writing {:?} to bind over {:?}", source, self.target);
+		OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.mode(0o600)
+			.open(&source)?
+			.write_all(self.contents.as_bytes())?;
+		Ok(())
 	}
+
+	/// Bind the file written to `dir` over its target.
+	fn bind(&self, dir: &Path) -> Result<()> {
+		Mount::bind(dir.join(&self.name), &self.target)?.mount()
+	}
+}
+
+/// The locations of the name resolution files.
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const HOSTS: &str = "/etc/hosts";
+
+/// An address to be assigned to an interface.
+#[derive(Clone)]
+struct Address {
+	ip: IpAddr,
+	prefix: u8,
+	interface: Interface,
+}
+
+impl fmt::Debug for Address {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.interface {
+			Interface::Name(ref name) => write!(f, "{}/{} dev {}", self.ip, self.prefix, name),
+			Interface::Index(index) => write!(f, "{}/{} dev #{}", self.ip, self.prefix, index),
+		}
+	}
+}
+
+/// An interface identified by either name or index.
+#[derive(Clone, Debug)]
+enum Interface {
+	Name(String),
+	Index(u32),
+}
+
+impl Interface {
+	/// Determine the index of the interface.
+	fn index(&self) -> Result<c_uint> {
+		match *self {
+			Interface::Index(index) => Ok(index),
+			Interface::Name(ref name) => {
+				let c_name = CString::new(name.clone())?;
+				match unsafe { if_nametoindex(c_name.as_ptr()) } {
+					0 => Err(ErrorKind::NoSuchInterface(name.clone()).into()),
+					index => Ok(index),
+				}
+			}
+		}
+	}
+
+	/// Determine the name of the interface.
+	fn name(&self) -> Result<[c_char; IFNAMSIZ]> {
+		let mut name = [0; IFNAMSIZ];
+		let index = self.index()?;
+		if unsafe { if_indextoname(index, name.as_mut_ptr()) }.is_null() {
+			return Err(ErrorKind::NoSuchInterface(format!("{}", index)).into());
+		}
+
+		Ok(name)
+	}
+
+	/// Bring the interface up.
+	fn up(&self) -> Result<()> {
+		let interface = match *self {
+			Interface::Name(ref name) => name.clone(),
+			Interface::Index(index) => format!("{}", index),
+		};
+		let socket = open_socket(AF_INET)?;
+		let mut req = IfReqFlags {
+			name: self.name()?,
+			flags: 0,
+			_pad: [0; 22],
+		};
+
+		unsafe {
+			if ioctl(socket.as_raw_fd(), SIOCGIFFLAGS, &mut req) == -1 {
+				return Err(errno!(InterfaceUp, interface));
+			}
+			req.flags |= IFF_UP as c_short;
+			if ioctl(socket.as_raw_fd(), SIOCSIFFLAGS, &mut req) == -1 {
+				return Err(errno!(InterfaceUp, interface));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// The directory in which `ip netns` binds persistent network namespaces.
+const NETNS_RUN_DIR: &str = "/run/netns";
+
+/// The name of the loopback interface.
+const LOOPBACK: &str = "lo";
+
+// Interface configuration requests (see `netdevice(7)`).
+const SIOCGIFFLAGS: c_ulong = 0x8913;
+const SIOCSIFFLAGS: c_ulong = 0x8914;
+const SIOCSIFADDR: c_ulong = 0x8916;
+const SIOCSIFNETMASK: c_ulong = 0x891c;
+
+/// An interface request carrying an IPv4 address.
+#[repr(C)]
+struct IfReqAddr {
+	name: [c_char; IFNAMSIZ],
+	addr: sockaddr_in,
+	_pad: [u8; 8],
+}
+
+/// An interface request carrying the flags of the interface.
+#[repr(C)]
+struct IfReqFlags {
+	name: [c_char; IFNAMSIZ],
+	flags: c_short,
+	_pad: [u8; 22],
+}
+
+/// An interface request carrying an IPv6 address (see `ipv6(7)`).
+#[repr(C)]
+struct In6IfReq {
+	addr: in6_addr,
+	prefix_len: u32,
+	index: c_int,
+}
+
+impl Address {
+	/// Assign the address to its interface.
+	fn assign(&self) -> Result<()> {
+		match self.ip {
+			IpAddr::V4(ip) => self.assign_v4(ip),
+			IpAddr::V6(ip) => self.assign_v6(ip),
+		}
+	}
+
+	fn assign_v4(&self, ip: Ipv4Addr) -> Result<()> {
+		let socket = open_socket(AF_INET)?;
+		let name = self.interface.name()?;
+		let mask = u32::MAX
+			.checked_shl(32 - self.prefix as u32)
+			.unwrap_or(0);
+
+		for &(request, addr) in &[(SIOCSIFADDR, ip), (SIOCSIFNETMASK, Ipv4Addr::from(mask))] {
+			let mut req = IfReqAddr {
+				name,
+				addr: unsafe { mem::zeroed() },
+				_pad: [0; 8],
+			};
+			req.addr.sin_family = AF_INET as sa_family_t;
+			req.addr.sin_addr = in_addr {
+				s_addr: u32::from(addr).to_be(),
+			};
+
+			if unsafe { ioctl(socket.as_raw_fd(), request, &mut req) } == -1 {
+				return Err(errno!(Address, self.ip));
+			}
+		}
+
+		Ok(())
+	}
+
+	fn assign_v6(&self, ip: Ipv6Addr) -> Result<()> {
+		let socket = open_socket(AF_INET6)?;
+		let mut req = In6IfReq {
+			addr: unsafe { mem::zeroed() },
+			prefix_len: self.prefix as u32,
+			index: self.interface.index()? as c_int,
+		};
+		req.addr.s6_addr = ip.octets();
+
+		if unsafe { ioctl(socket.as_raw_fd(), SIOCSIFADDR, &mut req) } == -1 {
+			return Err(errno!(Address, self.ip));
+		}
+
+		Ok(())
+	}
+}
+
+/// Open a socket in the given address family for interface requests.
+fn open_socket(family: c_int) -> Result<File> {
+	match unsafe { socket(family, SOCK_DGRAM | SOCK_CLOEXEC, 0) } {
+		-1 => Err(errno!(Socket)),
+		fd => Ok(unsafe { File::from_raw_fd(fd) }),
+	}
+}
+
+/// Test that prefixes longer than the address are rejected.
+#[test]
+fn prefix_length() {
+	let v4: IpAddr = "10.0.0.2".parse().unwrap();
+	let v6: IpAddr = "fd00::2".parse().unwrap();
+	assert!(Network::new().address(v4, 32, "lo").is_ok());
+	assert!(Network::new().address_by_index(v6, 128, 1).is_ok());
+
+	for &(ip, prefix) in &[(v4, 33), (v6, 129)] {
+		match Network::new().address(ip, prefix, "lo") {
+			Err(Error(ErrorKind::InvalidPrefix(bad_ip, bad_prefix), _)) => {
+				assert_eq!((bad_ip, bad_prefix), (ip, prefix));
+			}
+			result => panic!("{}/{} gave {:?}", ip, prefix, result),
+		}
+	}
+}
+
+/// Test that the files bound over `/etc` are written to a private directory
+/// that is removed on cleanup.
+#[test]
+fn staged_files() {
+	use std::fs::{metadata, read_to_string};
+	use std::os::unix::fs::PermissionsExt;
+
+	let mut network = Network::new()
+		.resolv_conf(&["10.0.0.1".parse().unwrap()])
+		.resolv_conf(&["10.0.0.2".parse().unwrap()]);
+	network.prepare().unwrap();
+
+	let dir = network.staging.borrow().clone().unwrap();
+	assert_eq!(metadata(&dir).unwrap().permissions().mode() & 0o7777, 0o700);
+	for (file, server) in network.files.iter().zip(&["10.0.0.1", "10.0.0.2"]) {
+		let source = dir.join(&file.name);
+		assert_eq!(metadata(&source).unwrap().permissions().mode() & 0o7777, 0o600);
+		assert_eq!(read_to_string(&source).unwrap(), format!("nameserver {}\n", server));
+	}
+
+	network.cleanup().unwrap();
+	assert!(!dir.exists());
+	network.cleanup().unwrap();
 }
@@ -3,8 +3,6 @@ use libc::{
 	c_int,
 };
 
-use ::error::*;
-use ::Child;
 use super::Namespace;
 
 /// Process IDs
@@ -12,7 +10,7 @@ use super::Namespace;
 /// Process IDs are unique and specific to a PID namespace. Processes from
 /// different namespaces are unable to determine any information about processes
 /// in other PID namespaces.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Pid {}
 
 impl Pid {
@@ -22,6 +20,12 @@ impl Pid {
 	}
 }
 
+impl Default for Pid {
+	fn default() -> Pid {
+		Pid::new()
+	}
+}
+
 impl Namespace for Pid {
 	fn clone_flag(&self) -> c_int {
 		CLONE_NEWPID
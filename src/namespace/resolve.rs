@@ -0,0 +1,52 @@
+//! Path resolution without following symbolic links (see `openat2(2)`).
+
+use std::ffi::CString;
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::FromRawFd;
+
+use libc::{
+	AT_FDCWD,
+	O_CLOEXEC,
+	O_PATH,
+	c_long,
+	syscall,
+};
+
+use ::error::*;
+
+/// The `openat2(2)` system call (Linux 5.6), which has the same number on
+/// every architecture.
+const SYS_OPENAT2: c_long = 437;
+
+/// Fail if any component of the path is a symbolic link.
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+
+/// How `openat2(2)` opens a path.
+#[repr(C)]
+struct OpenHow {
+	flags: u64,
+	mode: u64,
+	resolve: u64,
+}
+
+/// Open `path` as an `O_PATH` descriptor without following any symbolic
+/// links.
+///
+/// This fails with `ENOSYS` if the kernel does not provide `openat2(2)`.
+pub fn open_no_symlinks(path: &CString) -> Result<File> {
+	let how = OpenHow {
+		flags: (O_PATH | O_CLOEXEC) as u64,
+		mode: 0,
+		resolve: RESOLVE_NO_SYMLINKS,
+	};
+
+	let fd = unsafe {
+		syscall(SYS_OPENAT2, AT_FDCWD, path.as_ptr(), &how as *const OpenHow, mem::size_of::<OpenHow>())
+	};
+	if fd == -1 {
+		return Err(errno!(ResolveTarget, path.clone()));
+	}
+
+	Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
@@ -0,0 +1,78 @@
+use std::fs::{OpenOptions, metadata};
+use std::io::{ErrorKind as IoErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use ::error::*;
+use super::Namespace;
+
+/// A kernel parameter set from within the namespaces of the child.
+///
+/// Many kernel parameters are specific to a namespace, such as those under
+/// `net` for a [`Network`](struct.Network.html) namespace and some under
+/// `kernel` for an [`Ipc`](struct.Ipc.html) namespace. The parameter is
+/// written to `/proc/sys` from within the child, so it should be added to a
+/// context after the namespace it applies to.
+///
+/// ```rust
+/// Context::new()
+///     .with(Network::new())
+///     .with(Sysctl::new("net.ipv4.ip_forward", "1")?);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Sysctl {
+	key: String,
+	value: String,
+}
+
+impl Sysctl {
+	/// Set the parameter `key`, given in dotted form, to `value`.
+	pub fn new(key: &str, value: &str) -> Result<Sysctl> {
+		let valid = !key.is_empty() && key.split('.').all(|part| {
+			!part.is_empty() && !part.contains('/') && part != ".."
+		});
+		if !valid {
+			return Err(ErrorKind::NoSuchSysctl(key.to_owned()).into());
+		}
+
+		Ok(Sysctl {
+			key: key.to_owned(),
+			value: value.to_owned(),
+		})
+	}
+
+	/// The path of the parameter in `/proc/sys`.
+	fn path(&self) -> PathBuf {
+		PathBuf::from("/proc/sys").join(self.key.replace('.', "/"))
+	}
+}
+
+impl Namespace for Sysctl {
+	fn internal_config(&mut self) -> Result<()> {
+		let path = self.path();
+		let not_writable = || -> Error {
+			ErrorKind::SysctlNotWritable(self.key.clone()).into()
+		};
+
+		match metadata(&path) {
+			Ok(ref metadata) if metadata.is_file() => {
+				if metadata.permissions().mode() & 0o200 == 0 {
+					return Err(not_writable());
+				}
+			}
+			_ => return Err(ErrorKind::NoSuchSysctl(self.key.clone()).into()),
+		}
+
+		debug!("setting {} to {:?}", self.key, self.value);
+		let mut sysctl = match OpenOptions::new().write(true).open(&path) {
+			Ok(sysctl) => sysctl,
+			Err(ref err) if err.kind() == IoErrorKind::PermissionDenied => {
+				return Err(not_writable());
+			}
+			Err(err) => return Err(err.into()),
+		};
+		sysctl.write_all(self.value.as_bytes())?;
+
+		Ok(())
+	}
+}
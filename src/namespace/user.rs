@@ -27,10 +27,11 @@ use super::Namespace;
 /// The root user of a user namespace can, for the purposes of that namespace
 /// and child namespaces, act as user 0 for all system operations allowing for
 /// operations such as mount and chroot.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct User {
 	map_root_user: bool,
 	map_root_group: bool,
+	allow_setgroups: bool,
 }
 
 impl User {
@@ -57,12 +58,43 @@ impl User {
 		}
 	}
 
+	/// Allow processes in the namespace to set their supplementary groups.
+	///
+	/// By default, `setgroups(2)` is denied in the namespace before the group
+	/// mapping is written as this is required for unprivileged processes to
+	/// write the mapping. Allowing `setgroups(2)` requires that the creator
+	/// of the namespace has `CAP_SETGID` in the parent namespace.
+	///
+	/// `setgroups(2)` can't be called until the group mapping is written, so
+	/// this requires [`map_root_group`](#method.map_root_group). A context
+	/// that allows it without mapping the group is rejected when it is
+	/// validated.
+	pub fn allow_setgroups(self) -> User {
+		User {
+			allow_setgroups: true,
+			..
+			self
+		}
+	}
+
+	/// Check that `setgroups(2)` is only allowed along with the group
+	/// mapping that it requires.
+	pub(crate) fn validate(&self) -> Result<()> {
+		if self.allow_setgroups && !self.map_root_group {
+			let reason = "allowing setgroups requires mapping the root group".to_owned();
+			return Err(ErrorKind::InvalidNamespaceOrder(reason).into());
+		}
+
+		Ok(())
+	}
+
 	/// Map root to the calling user.
 	fn set_root_user(&self, child: &Child) -> Result<()> {
 		let uid = unsafe { getuid() };
+		debug!("mapping root user to {} for child {}", uid, child.pid());
 		let mut uid_map = OpenOptions::new()
 			.append(true)
-			.open(format!("/proc/{}/uid_map", child.pid()))?;
+			.open(child.proc_path("uid_map"))?;
 		uid_map.write_all(format!("0 {} 1", uid).as_bytes())?;
 
 		Ok(())
@@ -70,24 +102,43 @@ impl User {
 
 	/// Map root group to calling user gid.
 	fn set_root_group(&self, child: &Child) -> Result<()> {
-		SetGroups::Deny.write(child)?;
+		if self.allow_setgroups {
+			SetGroups::Allow.write(child)?;
+		} else {
+			SetGroups::Deny.write(child)?;
+		}
 
 		let gid = unsafe { getgid() };
+		debug!("mapping root group to {} for child {}", gid, child.pid());
 		let mut gid_map = OpenOptions::new()
 			.append(true)
-			.open(format!("/proc/{}/gid_map", child.pid()))?;
+			.open(child.proc_path("gid_map"))?;
 		gid_map.write_all(format!("0 {} 1", gid).as_bytes())?;
 
 		Ok(())
 	}
 }
 
-impl Default for User {
-	fn default() -> User {
-		User {
-			map_root_user: false,
-			map_root_group: false,
-		}
+impl ::std::fmt::Debug for User {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		// Show the mappings as they will be written for the calling user.
+		let uid_map = if self.map_root_user {
+			Some(format!("0 {} 1", unsafe { getuid() }))
+		} else {
+			None
+		};
+		let gid_map = if self.map_root_group {
+			Some(format!("0 {} 1", unsafe { getgid() }))
+		} else {
+			None
+		};
+		let setgroups = if self.allow_setgroups { SetGroups::Allow } else { SetGroups::Deny };
+
+		f.debug_struct("User")
+			.field("uid_map", &uid_map)
+			.field("gid_map", &gid_map)
+			.field("setgroups", &format_args!("{}", setgroups))
+			.finish()
 	}
 }
 
@@ -97,6 +148,17 @@ impl Namespace for User {
 	}
 
 	fn external_config(&self, child: &Child) -> Result<()> {
+		if !self.map_root_user && !self.map_root_group {
+			return Ok(());
+		}
+
+		// Without /proc the maps can't be written, which would otherwise be
+		// reported as though the child no longer exists.
+		let proc_dir = child.proc_path("");
+		if !proc_dir.is_dir() {
+			return Err(ErrorKind::ProcNotMounted(proc_dir).into());
+		}
+
 		if self.map_root_user {
 			self.set_root_user(child)?;
 		}
@@ -119,7 +181,7 @@ impl SetGroups {
 	fn write(&self, child: &Child) -> Result<()> {
 		let mut setgroup = OpenOptions::new()
 			.append(true)
-			.open(format!("/proc/{}/setgroups", child.pid()))?;
+			.open(child.proc_path("setgroups"))?;
 		setgroup.write_all(format!("{}", self).as_bytes())?;
 
 		Ok(())
@@ -1,23 +1,50 @@
 use libc::{
+	c_char,
 	c_int,
+	sethostname,
+	setdomainname,
+	size_t,
 	CLONE_NEWUTS,
 };
 
 use ::error::*;
-use ::Child;
 use super::Namespace;
 
 /// Unix Timesharing System (UTS)
 ///
 /// The Unix Timesharing System provides the domain and hostname of the system.
 /// This is given its own namespace and can be changed within that namespace.
-#[derive(Clone)]
-pub struct Uts {}
+#[derive(Clone, Debug)]
+pub struct Uts {
+	hostname: Option<String>,
+	domainname: Option<String>,
+}
 
 impl Uts {
 	/// Configure a new UTS namespace for creation.
 	pub fn new() -> Uts {
-		Uts {}
+		Uts {
+			hostname: None,
+			domainname: None,
+		}
+	}
+
+	/// Set the hostname within the new namespace.
+	pub fn hostname(mut self, hostname: &str) -> Uts {
+		self.hostname = Some(hostname.to_owned());
+		self
+	}
+
+	/// Set the NIS domain name within the new namespace.
+	pub fn domainname(mut self, domainname: &str) -> Uts {
+		self.domainname = Some(domainname.to_owned());
+		self
+	}
+}
+
+impl Default for Uts {
+	fn default() -> Uts {
+		Uts::new()
 	}
 }
 
@@ -25,4 +52,22 @@ impl Namespace for Uts {
 	fn clone_flag(&self) -> c_int {
 		CLONE_NEWUTS
 	}
+
+	fn internal_config(&mut self) -> Result<()> {
+		if let Some(ref hostname) = self.hostname {
+			let name = hostname.as_ptr() as *const c_char;
+			if unsafe { sethostname(name, hostname.len() as size_t) } == -1 {
+				return Err(errno!(Hostname));
+			}
+		}
+
+		if let Some(ref domainname) = self.domainname {
+			let name = domainname.as_ptr() as *const c_char;
+			if unsafe { setdomainname(name, domainname.len() as size_t) } == -1 {
+				return Err(errno!(Domainname));
+			}
+		}
+
+		Ok(())
+	}
 }
@@ -0,0 +1,590 @@
+//! Process attributes applied to the child as part of its context.
+//!
+//! These aren't namespaces but are configured in the same phases as them,
+//! allowing them to be added to a context alongside namespaces.
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::mem;
+use std::ptr;
+
+use std::os::unix::io::RawFd;
+
+use libc::{
+	CPU_SET,
+	CPU_SETSIZE,
+	CPU_ZERO,
+	FD_CLOEXEC,
+	F_GETFD,
+	F_SETFD,
+	EINVAL,
+	EPERM,
+	RLIMIT_NPROC,
+	SCHED_BATCH,
+	SCHED_FIFO,
+	SCHED_IDLE,
+	SCHED_OTHER,
+	SCHED_RR,
+	SIGKILL,
+	SIGSTOP,
+	SIG_DFL,
+	SIG_ERR,
+	SIG_SETMASK,
+	_SC_NPROCESSORS_CONF,
+	c_int,
+	c_ulong,
+	cpu_set_t,
+	dup2,
+	fcntl,
+	geteuid,
+	gid_t,
+	prctl,
+	rlimit,
+	sched_get_priority_max,
+	sched_get_priority_min,
+	sched_param,
+	sched_setaffinity,
+	sched_setscheduler,
+	setgid,
+	seteuid,
+	setgroups,
+	setrlimit,
+	setuid,
+	sigemptyset,
+	signal,
+	sigprocmask,
+	sigset_t,
+	size_t,
+	sysconf,
+	uid_t,
+};
+
+use ::error::*;
+use ::namespace::Namespace;
+use ::Child;
+
+/// Set the supplementary groups of the child.
+#[derive(Clone, Debug)]
+pub struct Groups(Vec<gid_t>);
+
+impl Groups {
+	/// Set the supplementary groups to exactly the given groups.
+	pub fn new(groups: &[gid_t]) -> Groups {
+		Groups(groups.to_vec())
+	}
+}
+
+impl Namespace for Groups {
+	fn internal_config(&mut self) -> Result<()> {
+		if setgroups_denied()? {
+			return Err(ErrorKind::SetGroupsDenied.into());
+		}
+
+		match unsafe { setgroups(self.0.len() as size_t, self.0.as_ptr()) } {
+			-1 => Err(errno!(SetGroups)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Adjust the likelihood of the child being chosen by the OOM killer.
+#[derive(Clone, Debug)]
+pub struct OomScoreAdj(i32);
+
+impl OomScoreAdj {
+	/// Set the OOM score adjustment, which must be within `-1000..=1000`.
+	pub fn new(adj: i32) -> Result<OomScoreAdj> {
+		if !(-1000..=1000).contains(&adj) {
+			return Err(ErrorKind::OomScoreAdjRange(adj).into());
+		}
+
+		Ok(OomScoreAdj(adj))
+	}
+}
+
+impl Namespace for OomScoreAdj {
+	fn external_config(&self, child: &Child) -> Result<()> {
+		let mut oom_score_adj = OpenOptions::new()
+			.write(true)
+			.open(child.proc_path("oom_score_adj"))?;
+		oom_score_adj.write_all(format!("{}", self.0).as_bytes())?;
+
+		Ok(())
+	}
+}
+
+/// Set the audit login user ID of the child.
+#[derive(Clone, Debug)]
+pub struct LoginUid(uid_t);
+
+impl LoginUid {
+	/// Set the login user ID to `uid`.
+	pub fn new(uid: uid_t) -> LoginUid {
+		LoginUid(uid)
+	}
+}
+
+impl Namespace for LoginUid {
+	fn external_config(&self, child: &Child) -> Result<()> {
+		let mut loginuid = OpenOptions::new()
+			.write(true)
+			.open(child.proc_path("loginuid"))?;
+
+		match loginuid.write_all(format!("{}", self.0).as_bytes()) {
+			Err(ref err) if err.raw_os_error() == Some(EPERM) => {
+				Err(ErrorKind::LoginUidDenied(self.0).into())
+			}
+			result => Ok(result?),
+		}
+	}
+}
+
+/// Make a file descriptor available to the child at a fixed number.
+#[derive(Clone, Debug)]
+pub struct PreserveFd {
+	src: RawFd,
+	dest: RawFd,
+}
+
+impl PreserveFd {
+	/// Duplicate `src` to `dest` in the child.
+	pub fn new(src: RawFd, dest: RawFd) -> PreserveFd {
+		PreserveFd {
+			src,
+			dest,
+		}
+	}
+}
+
+impl Namespace for PreserveFd {
+	fn internal_config(&mut self) -> Result<()> {
+		if self.src != self.dest {
+			// The duplicated descriptor never has close-on-exec set.
+			if unsafe { dup2(self.src, self.dest) } == -1 {
+				return Err(errno!(PreserveFd, self.src, self.dest));
+			}
+
+			return Ok(());
+		}
+
+		unsafe {
+			let flags = fcntl(self.dest, F_GETFD);
+			if flags == -1 || fcntl(self.dest, F_SETFD, flags & !FD_CLOEXEC) == -1 {
+				return Err(errno!(PreserveFd, self.src, self.dest));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Restrict the child to running on a set of CPUs.
+#[derive(Clone, Debug)]
+pub struct CpuAffinity(Vec<usize>);
+
+impl CpuAffinity {
+	/// Allow the child to run only on the given CPUs.
+	///
+	/// Each CPU must be present in the system.
+	pub fn new(cpus: &[usize]) -> Result<CpuAffinity> {
+		let configured = unsafe { sysconf(_SC_NPROCESSORS_CONF) };
+		let limit = if configured > 0 {
+			configured as usize
+		} else {
+			CPU_SETSIZE as usize
+		};
+
+		for &cpu in cpus {
+			if cpu >= limit || cpu >= CPU_SETSIZE as usize {
+				return Err(ErrorKind::InvalidCpu(cpu).into());
+			}
+		}
+
+		Ok(CpuAffinity(cpus.to_vec()))
+	}
+}
+
+impl Namespace for CpuAffinity {
+	fn external_config(&self, child: &Child) -> Result<()> {
+		unsafe {
+			let mut set: cpu_set_t = mem::zeroed();
+			CPU_ZERO(&mut set);
+			for &cpu in &self.0 {
+				CPU_SET(cpu, &mut set);
+			}
+
+			let size = mem::size_of::<cpu_set_t>();
+			if sched_setaffinity(child.pid(), size, &set) == -1 {
+				return Err(errno!(CpuAffinity));
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Modify the environment of the child.
+#[derive(Clone, Debug)]
+pub enum Environment {
+	/// Set a variable, replacing any existing value.
+	Set(String, String),
+	/// Remove every variable inherited from the parent.
+	Clear,
+	/// Remove every variable inherited from the parent except those named.
+	ClearExcept(Vec<String>),
+}
+
+impl Environment {
+	/// Set the variable `name` to `value`.
+	pub fn set(name: &str, value: &str) -> Result<Environment> {
+		Environment::check_name(name)?;
+		if value.contains('\0') {
+			return Err(ErrorKind::InvalidEnvVar(name.to_owned()).into());
+		}
+
+		Ok(Environment::Set(name.to_owned(), value.to_owned()))
+	}
+
+	/// Check that `name` can be used as the name of a variable.
+	pub fn check_name(name: &str) -> Result<()> {
+		if name.is_empty() || name.contains('=') || name.contains('\0') {
+			return Err(ErrorKind::InvalidEnvVar(name.to_owned()).into());
+		}
+
+		Ok(())
+	}
+}
+
+impl Namespace for Environment {
+	fn internal_config(&mut self) -> Result<()> {
+		match *self {
+			Environment::Set(ref name, ref value) => env::set_var(name, value),
+			Environment::Clear => for (name, _) in env::vars_os() {
+				env::remove_var(name);
+			},
+			Environment::ClearExcept(ref keep) => for (name, _) in env::vars_os() {
+				if !keep.iter().any(|kept| name == kept.as_str()) {
+					env::remove_var(name);
+				}
+			},
+		}
+
+		Ok(())
+	}
+}
+
+/// Switch the child to a different user and group.
+#[derive(Clone, Debug)]
+pub struct SwitchTo {
+	uid: uid_t,
+	gid: gid_t,
+	keep_groups: bool,
+}
+
+impl SwitchTo {
+	/// Switch to the user `uid` and the group `gid`.
+	pub fn new(uid: uid_t, gid: gid_t) -> SwitchTo {
+		SwitchTo {
+			uid,
+			gid,
+			keep_groups: false,
+		}
+	}
+
+	/// Keep the supplementary groups rather than clearing them, as they have
+	/// been set by [`Groups`](struct.Groups.html).
+	pub fn keep_groups(&mut self) {
+		self.keep_groups = true;
+	}
+}
+
+impl Namespace for SwitchTo {
+	fn internal_config(&mut self) -> Result<()> {
+		if !id_mapped("/proc/self/uid_map", self.uid)? {
+			return Err(ErrorKind::UnmappedUid(self.uid).into());
+		}
+		if !id_mapped("/proc/self/gid_map", self.gid)? {
+			return Err(ErrorKind::UnmappedGid(self.gid).into());
+		}
+
+		// The supplementary groups of the parent are dropped unless setting
+		// them is denied in the user namespace, in which case they can't be
+		// changed at all.
+		let clear_groups = !self.keep_groups && !setgroups_denied()?;
+		if clear_groups && unsafe { setgroups(0, ptr::null()) } == -1 {
+			return Err(errno!(SetGroups));
+		}
+
+		// The group must be changed first as changing the user may drop the
+		// privileges needed to do so.
+		if unsafe { setgid(self.gid) } == -1 {
+			return Err(errno!(SetGid, self.gid));
+		}
+		if unsafe { setuid(self.uid) } == -1 {
+			return Err(errno!(SetUid, self.uid));
+		}
+
+		Ok(())
+	}
+}
+
+/// Raise the effective user ID of the parent to root until dropped.
+///
+/// The original effective user ID is restored when the guard is dropped,
+/// including when unwinding.
+pub(crate) struct EffectiveRoot {
+	euid: uid_t,
+}
+
+impl EffectiveRoot {
+	/// Set the effective user ID to root.
+	pub fn acquire() -> Result<EffectiveRoot> {
+		let euid = unsafe { geteuid() };
+		if unsafe { seteuid(0) } == -1 {
+			return Err(errno!(SetEuid, 0));
+		}
+		debug!("raised effective user from {} to root", euid);
+
+		Ok(EffectiveRoot { euid })
+	}
+}
+
+impl Drop for EffectiveRoot {
+	fn drop(&mut self) {
+		// Dropping privileges from root cannot fail.
+		if unsafe { seteuid(self.euid) } == -1 {
+			warn!("could not restore effective user {}: {}", self.euid, ::errno::errno());
+		}
+	}
+}
+
+/// Determine if `setgroups(2)` is denied in the user namespace of the caller.
+///
+/// Kernels that predate `/proc/<pid>/setgroups` (Linux 3.19) never deny it.
+fn setgroups_denied() -> Result<bool> {
+	let mut state = String::new();
+	match File::open("/proc/self/setgroups") {
+		Ok(mut file) => file.read_to_string(&mut state)?,
+		Err(ref err) if err.kind() == ::std::io::ErrorKind::NotFound => return Ok(false),
+		Err(err) => return Err(err.into()),
+	};
+
+	Ok(state.trim() == "deny")
+}
+
+/// Determine if an id falls within one of the ranges of an id map.
+fn id_mapped(map: &str, id: u32) -> Result<bool> {
+	let mut text = String::new();
+	File::open(map)?.read_to_string(&mut text)?;
+
+	for line in text.lines() {
+		let fields: Vec<u64> = line.split_whitespace()
+			.filter_map(|field| field.parse().ok())
+			.collect();
+		if let [inside, _, count] = fields[..] {
+			if (inside..inside + count).contains(&(id as u64)) {
+				return Ok(true);
+			}
+		}
+	}
+
+	Ok(false)
+}
+
+/// A scheduling policy for the child (see `sched(7)`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+	/// The default time-sharing policy (`SCHED_OTHER`).
+	Other,
+	/// Time-sharing for non-interactive, CPU-intensive work (`SCHED_BATCH`).
+	Batch,
+	/// Run only when the system is otherwise idle (`SCHED_IDLE`).
+	Idle,
+	/// Real-time first-in, first-out (`SCHED_FIFO`).
+	Fifo,
+	/// Real-time round-robin (`SCHED_RR`).
+	RoundRobin,
+}
+
+impl Policy {
+	fn policy(&self) -> c_int {
+		match *self {
+			Policy::Other => SCHED_OTHER,
+			Policy::Batch => SCHED_BATCH,
+			Policy::Idle => SCHED_IDLE,
+			Policy::Fifo => SCHED_FIFO,
+			Policy::RoundRobin => SCHED_RR,
+		}
+	}
+}
+
+/// Set the scheduling policy of the child.
+#[derive(Clone, Debug)]
+pub struct SchedPolicy {
+	policy: Policy,
+	priority: i32,
+}
+
+impl SchedPolicy {
+	/// Use `policy` with the static `priority`.
+	///
+	/// The priority must be within the range supported by the policy, which
+	/// is `0` for all but the real-time policies.
+	pub fn new(policy: Policy, priority: i32) -> Result<SchedPolicy> {
+		let (min, max) = unsafe {
+			(
+				sched_get_priority_min(policy.policy()),
+				sched_get_priority_max(policy.policy()),
+			)
+		};
+		if !(min..=max).contains(&priority) {
+			return Err(ErrorKind::SchedPriorityRange(policy, priority).into());
+		}
+
+		Ok(SchedPolicy {
+			policy,
+			priority,
+		})
+	}
+}
+
+impl Namespace for SchedPolicy {
+	fn external_config(&self, child: &Child) -> Result<()> {
+		let param = sched_param {
+			sched_priority: self.priority,
+		};
+
+		if unsafe { sched_setscheduler(child.pid(), self.policy.policy(), &param) } == -1 {
+			let err = ::errno::errno();
+			if err.0 == EPERM {
+				return Err(ErrorKind::SchedPolicyDenied(self.policy).into());
+			}
+			return Err(ErrorKind::SchedPolicy(err, self.policy).into());
+		}
+
+		Ok(())
+	}
+}
+
+/// Limit the number of processes that may be created by the user of the
+/// child.
+///
+/// This sets `RLIMIT_NPROC` within the child, which limits the number of
+/// processes owned by the real user ID of the child in its user namespace.
+#[derive(Clone, Debug)]
+pub struct MaxPids(u64);
+
+impl MaxPids {
+	/// Allow at most `pids` processes.
+	pub fn new(pids: u64) -> MaxPids {
+		MaxPids(pids)
+	}
+}
+
+impl Namespace for MaxPids {
+	fn internal_config(&mut self) -> Result<()> {
+		let limit = rlimit {
+			rlim_cur: self.0,
+			rlim_max: self.0,
+		};
+
+		match unsafe { setrlimit(RLIMIT_NPROC, &limit) } {
+			-1 => Err(errno!(MaxPids, self.0)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// The number of signals, including the real-time signals (not currently in
+/// libc).
+const NSIG: c_int = 65;
+
+/// Reset the signal dispositions and mask of the child.
+///
+/// `CLONE_CLEAR_SIGHAND` can only be passed to `clone3(2)`, so the handlers
+/// are reset individually.
+#[derive(Clone, Debug)]
+pub struct ResetSignals;
+
+impl Namespace for ResetSignals {
+	fn internal_config(&mut self) -> Result<()> {
+		for sig in 1..NSIG {
+			if sig == SIGKILL || sig == SIGSTOP {
+				continue;
+			}
+
+			// The C library reserves some real-time signals for itself and
+			// refuses to change them.
+			if unsafe { signal(sig, SIG_DFL) } == SIG_ERR && ::errno::errno().0 != EINVAL {
+				return Err(errno!(ResetSignals));
+			}
+		}
+
+		let mut mask: sigset_t = unsafe { mem::zeroed() };
+		unsafe { sigemptyset(&mut mask) };
+		if unsafe { sigprocmask(SIG_SETMASK, &mask, ptr::null_mut()) } == -1 {
+			return Err(errno!(ResetSignals));
+		}
+
+		Ok(())
+	}
+}
+
+/// Apply a raw `prctl(2)` operation to the child.
+#[derive(Clone, Debug)]
+pub struct Prctl {
+	option: c_int,
+	args: [c_ulong; 4],
+}
+
+impl Prctl {
+	/// Apply `prctl(option, arg2, arg3, arg4, arg5)`.
+	///
+	/// Unused arguments should be given as zero.
+	pub fn new(option: c_int, arg2: c_ulong, arg3: c_ulong, arg4: c_ulong, arg5: c_ulong) -> Prctl {
+		Prctl {
+			option,
+			args: [arg2, arg3, arg4, arg5],
+		}
+	}
+}
+
+impl Namespace for Prctl {
+	fn internal_config(&mut self) -> Result<()> {
+		let [arg2, arg3, arg4, arg5] = self.args;
+		match unsafe { prctl(self.option, arg2, arg3, arg4, arg5) } {
+			-1 => Err(errno!(Prctl, self.option)),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Test that the child is pinned to the given CPUs.
+#[test]
+fn cpu_affinity() {
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use libc::{CPU_ISSET, sched_getaffinity};
+	use ::Context;
+
+	static PINNED: AtomicBool = AtomicBool::new(false);
+
+	let child = Context::new()
+		.unisolated()
+		.cpu_affinity(&[0])
+		.unwrap()
+		.exec_shared(|| {
+			let mut set: cpu_set_t = unsafe { mem::zeroed() };
+			if unsafe { sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut set) } == -1 {
+				return;
+			}
+			let cpus: Vec<usize> = (0..CPU_SETSIZE as usize)
+				.filter(|&cpu| unsafe { CPU_ISSET(cpu, &set) })
+				.collect();
+			PINNED.store(cpus == [0], Ordering::SeqCst);
+		})
+		.unwrap();
+	child.wait().unwrap();
+
+	assert!(PINNED.load(Ordering::SeqCst));
+}
@@ -0,0 +1,194 @@
+//! Declarative descriptions of contexts.
+
+// The impls generated by `serde_derive` are placed inside anonymous
+// constants.
+#![cfg_attr(feature = "spec", allow(non_local_definitions))]
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+use ::error::*;
+use ::namespace::{Mount, Namespace, NsKind, Uts};
+use ::process::Environment;
+
+/// A declarative description of a [`Context`](struct.Context.html).
+///
+/// A context is constructed from a spec with
+/// [`Context::from_spec`](struct.Context.html#method.from_spec). With the
+/// `spec` feature, specs can be deserialized from any format supported by
+/// [`serde`](https://docs.rs/serde). All fields are optional and unknown
+/// fields are rejected.
+///
+/// ```rust
+/// let spec: ContextSpec = toml::from_str(r#"
+///     namespaces = ["mount", "pid"]
+///     hostname = "sandbox"
+///     env_clear = true
+///
+///     [env]
+///     PATH = "/bin:/usr/bin"
+///
+///     [[mounts]]
+///     source = "proc"
+///     target = "/proc"
+///     fstype = "proc"
+/// "#)?;
+///
+/// let context = Context::from_spec(&spec)?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "spec", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "spec", serde(default, deny_unknown_fields))]
+pub struct ContextSpec {
+	/// Namespaces to create, in order, named as for
+	/// [`NsKind`](namespace/enum.NsKind.html).
+	pub namespaces: Vec<String>,
+	/// Hostname of the child. Setting this creates a new UTS namespace.
+	pub hostname: Option<String>,
+	/// NIS domain name of the child. Setting this creates a new UTS namespace.
+	pub domainname: Option<String>,
+	/// Mounts to make in the child once the namespaces have been created.
+	pub mounts: Vec<MountSpec>,
+	/// Start the child with an empty environment.
+	pub env_clear: bool,
+	/// Variables to keep when the environment is cleared.
+	pub env_keep: Vec<String>,
+	/// Clear the environment, passing only the named variables from the
+	/// parent to the child. Variables in `env` take precedence.
+	pub env_passthrough: Option<Vec<String>>,
+	/// Environment variables to set in the child.
+	pub env: BTreeMap<String, String>,
+}
+
+/// A declarative description of a [`Mount`](namespace/struct.Mount.html).
+///
+/// A mount with a source but no file-system type is a bind mount. A mount
+/// with a file-system type but no source uses the type as its source, as is
+/// conventional for virtual file-systems such as `proc` and `tmpfs`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "spec", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "spec", serde(default, deny_unknown_fields))]
+pub struct MountSpec {
+	/// Source of the mount.
+	pub source: Option<PathBuf>,
+	/// Mount point.
+	pub target: PathBuf,
+	/// File-system type of the mount.
+	pub fstype: Option<String>,
+	/// File-system specific mount options.
+	pub data: Vec<String>,
+	/// Mount the file-system read-only.
+	pub read_only: bool,
+	/// Create the mount point if it does not exist.
+	pub make_target_dir: bool,
+}
+
+impl ContextSpec {
+	/// Get the variables to keep from the parent and whether the environment
+	/// is cleared.
+	///
+	/// Passing variables through implies clearing the environment. Returns
+	/// an error if a variable that is passed through has an invalid name.
+	pub(crate) fn env_kept(&self) -> Result<(Vec<&str>, bool)> {
+		let mut keep: Vec<&str> = self.env_keep.iter().map(String::as_str).collect();
+		let passthrough = match self.env_passthrough {
+			Some(ref names) => names,
+			None => return Ok((keep, self.env_clear)),
+		};
+
+		for name in passthrough {
+			Environment::check_name(name)?;
+			keep.push(name);
+		}
+
+		Ok((keep, true))
+	}
+
+	/// Construct the namespaces and mounts described by the spec.
+	///
+	/// If a hostname or domain name is given, the UTS namespace is configured
+	/// with it, and is added after the other namespaces if it was not named.
+	pub(crate) fn namespaces(&self) -> Result<Vec<Box<dyn Namespace>>> {
+		let mut uts = None;
+		if self.hostname.is_some() || self.domainname.is_some() {
+			let mut config = Uts::new();
+			if let Some(ref hostname) = self.hostname {
+				config = config.hostname(hostname);
+			}
+			if let Some(ref domainname) = self.domainname {
+				config = config.domainname(domainname);
+			}
+			uts = Some(config);
+		}
+
+		let mut namespaces = Vec::new();
+		for name in &self.namespaces {
+			let ns = match (NsKind::try_from(name.as_str())?, uts.take()) {
+				(NsKind::Uts, Some(config)) => Box::new(config),
+				(_, config) => {
+					uts = config;
+					Box::<dyn Namespace>::try_from(name.as_str())?
+				}
+			};
+			namespaces.push(ns);
+		}
+
+		if let Some(config) = uts {
+			namespaces.push(Box::new(config));
+		}
+
+		for mount in &self.mounts {
+			namespaces.push(Box::new(mount.mount()?));
+		}
+
+		Ok(namespaces)
+	}
+}
+
+impl MountSpec {
+	/// Construct the mount described by the spec.
+	pub fn mount(&self) -> Result<Mount> {
+		let mut mount = match (self.source.as_ref(), self.fstype.as_ref()) {
+			(Some(source), Some(fstype)) => Mount::new(source, &self.target, fstype)?,
+			(Some(source), None) => Mount::bind(source, &self.target)?,
+			(None, Some(fstype)) => Mount::new(fstype, &self.target, fstype)?,
+			(None, None) => {
+				let reason = "mount has neither a source nor a file-system type";
+				return Err(ErrorKind::InvalidSpec(reason.to_owned()).into());
+			}
+		};
+
+		for option in &self.data {
+			mount = mount.data(option);
+		}
+		if self.read_only {
+			mount = mount.read_only();
+		}
+		if self.make_target_dir {
+			mount = mount.make_target_dir();
+		}
+
+		Ok(mount)
+	}
+}
+
+/// Test that a configured UTS namespace replaces the named one.
+#[test]
+fn spec_namespaces() {
+	let spec = ContextSpec {
+		namespaces: vec!["uts".to_owned(), "pid".to_owned()],
+		hostname: Some("sandbox".to_owned()),
+		..ContextSpec::default()
+	};
+
+	let namespaces = spec.namespaces().unwrap();
+	assert_eq!(namespaces.len(), 2);
+	assert!(format!("{:?}", namespaces[0]).contains("sandbox"));
+
+	let spec = ContextSpec {
+		mounts: vec![MountSpec::default()],
+		..ContextSpec::default()
+	};
+	assert!(spec.namespaces().is_err());
+}